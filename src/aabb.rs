@@ -0,0 +1,128 @@
+use crate::geometry::{v3, Point, Ray};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: Point,
+    pub max: Point,
+}
+
+impl Aabb {
+    pub fn new(min: Point, max: Point) -> Self {
+        Aabb { min, max }
+    }
+
+    pub fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> bool {
+        let mut t_min = t_min;
+        let mut t_max = t_max;
+
+        for a in 0..3 {
+            if ray.dir[a] == 0.0 {
+                // Parallel to this slab: either always inside it or never
+                if ray.orig[a] < self.min[a] || ray.orig[a] > self.max[a] {
+                    return false;
+                }
+                continue;
+            }
+
+            let inv_d = 1.0 / ray.dir[a];
+            let mut t0 = (self.min[a] - ray.orig[a]) * inv_d;
+            let mut t1 = (self.max[a] - ray.orig[a]) * inv_d;
+            if inv_d < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            t_min = t0.max(t_min);
+            t_max = t1.min(t_max);
+            if t_max <= t_min {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn centroid(&self) -> Point {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.max - self.min;
+        2. * (d.x * d.y + d.y * d.z + d.z * d.x)
+    }
+}
+
+pub fn surrounding_box(a: &Aabb, b: &Aabb) -> Aabb {
+    Aabb {
+        min: v3(
+            a.min.x.min(b.min.x),
+            a.min.y.min(b.min.y),
+            a.min.z.min(b.min.z),
+        ),
+        max: v3(
+            a.max.x.max(b.max.x),
+            a.max.y.max(b.max.y),
+            a.max.z.max(b.max.z),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::V3;
+
+    fn unit_box() -> Aabb {
+        Aabb::new(v3(0., 0., 0.), v3(1., 1., 1.))
+    }
+
+    fn ray(orig: Point, dir: V3) -> Ray {
+        Ray {
+            orig,
+            dir,
+            time: 0.,
+            wavelength: None,
+        }
+    }
+
+    #[test]
+    fn test_hit() {
+        let bbox = unit_box();
+
+        assert!(bbox.hit(&ray(v3(0.5, 0.5, -2.), v3(0., 0., 1.)), 0., 100.));
+        // Negative direction swaps the slab ends
+        assert!(bbox.hit(&ray(v3(0.5, 0.5, 3.), v3(0., 0., -1.)), 0., 100.));
+        assert!(bbox.hit(&ray(v3(-1., -1., -1.), v3(1., 1., 1.)), 0., 100.));
+        // The box lies beyond t_max
+        assert!(!bbox.hit(&ray(v3(0.5, 0.5, -2.), v3(0., 0., 1.)), 0., 1.));
+    }
+
+    #[test]
+    fn test_miss() {
+        let bbox = unit_box();
+
+        // Parallel to the z slabs but outside them
+        assert!(!bbox.hit(&ray(v3(0.5, 0.5, 2.), v3(1., 0., 0.)), 0., 100.));
+        // Pointing away
+        assert!(!bbox.hit(&ray(v3(0.5, 0.5, -2.), v3(0., 0., -1.)), 0., 100.));
+        assert!(!bbox.hit(&ray(v3(2., 2., -2.), v3(0., 0., 1.)), 0., 100.));
+    }
+
+    #[test]
+    fn test_grazing() {
+        let bbox = unit_box();
+
+        // Runs along the top face, starting exactly on its plane
+        assert!(bbox.hit(&ray(v3(-1., 1., 0.5), v3(1., 0., 0.)), 0., 100.));
+        // Just above it
+        assert!(!bbox.hit(&ray(v3(-1., 1.001, 0.5), v3(1., 0., 0.)), 0., 100.));
+    }
+
+    #[test]
+    fn test_surrounding_box() {
+        let a = Aabb::new(v3(0., -1., 2.), v3(1., 0., 3.));
+        let b = Aabb::new(v3(-2., 0.5, 0.), v3(0.5, 4., 2.5));
+
+        let union = surrounding_box(&a, &b);
+        assert_eq!(union, Aabb::new(v3(-2., -1., 0.), v3(1., 4., 3.)));
+        assert_eq!(surrounding_box(&b, &a), union);
+    }
+}
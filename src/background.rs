@@ -0,0 +1,176 @@
+use std::fs::File;
+use std::io::{BufReader, Result};
+use std::path::Path;
+
+use crate::color::Color;
+use crate::geometry::{deg_to_rad, sphere_uv, unit, v3, Ray, V3};
+use crate::hdr;
+
+/// What a ray sees when it escapes the scene.
+#[derive(Clone, Debug)]
+pub enum Background {
+    /// Blend from `bottom` straight down to `top` straight up.
+    Gradient {
+        top: Color,
+        bottom: Color,
+    },
+    Solid(Color),
+    /// Light captured from a real scene, for image-based lighting.
+    Environment(EnvironmentMap),
+    /// The default sky plus a sun, for hard directional light.
+    Sky(Sky),
+}
+
+/// A blue sky, white at the horizon, with a sun disk of uniform brightness.
+#[derive(Clone, Debug)]
+pub struct Sky {
+    sun_dir: V3,
+    sun_intensity: Color,
+    // Cosine of the sun's angular radius, so the disk test is one dot product
+    cos_sun_radius: f64,
+}
+
+/// An equirectangular panorama of incoming light, indexed by direction.
+#[derive(Clone, Debug)]
+pub struct EnvironmentMap {
+    width: usize,
+    height: usize,
+    pixels: Vec<Color>,
+}
+
+impl Default for Background {
+    /// The blue-white sky.
+    fn default() -> Self {
+        Background::Gradient {
+            top: Color(v3(0.5, 0.7, 1.0)),
+            bottom: Color(v3(1.0, 1.0, 1.0)),
+        }
+    }
+}
+
+impl Background {
+    pub fn value(&self, ray: &Ray) -> Color {
+        match self {
+            Background::Gradient { top, bottom } => {
+                let unit_dir = unit(&ray.direction());
+                let t = 0.5 * (unit_dir.y + 1.0);
+                Color::lerp(*bottom, *top, t)
+            }
+            Background::Solid(color) => *color,
+            Background::Environment(map) => map.value(&ray.direction()),
+            Background::Sky(sky) => sky.value(&ray.direction()),
+        }
+    }
+}
+
+impl Sky {
+    /// A sun in direction `sun_dir`, any length, `sun_angular_radius`
+    /// degrees across from centre to edge. The real sun is about 0.27.
+    pub fn new(sun_dir: V3, sun_intensity: Color, sun_angular_radius: f64) -> Self {
+        Sky {
+            sun_dir: unit(&sun_dir),
+            sun_intensity,
+            cos_sun_radius: deg_to_rad(sun_angular_radius).cos(),
+        }
+    }
+
+    pub fn value(&self, dir: &V3) -> Color {
+        let unit_dir = unit(dir);
+        if unit_dir.dot(&self.sun_dir) >= self.cos_sun_radius {
+            return self.sun_intensity;
+        }
+        Background::default().value(&Ray {
+            orig: V3::zeros(),
+            dir: unit_dir,
+            time: 0.,
+            wavelength: None,
+        })
+    }
+}
+
+impl EnvironmentMap {
+    /// Load a Radiance `.hdr` panorama from disk.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let (width, height, pixels) = hdr::read_rgbe(&mut reader)?;
+        Ok(Self::new(width, height, pixels))
+    }
+
+    /// Build a map from linear colors given in rows top-to-bottom.
+    pub fn new(width: usize, height: usize, pixels: Vec<Color>) -> Self {
+        assert_eq!(pixels.len(), width * height);
+        EnvironmentMap {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Radiance arriving from direction `dir`, using the same longitude and
+    /// latitude mapping as sphere textures.
+    pub fn value(&self, dir: &V3) -> Color {
+        if self.pixels.is_empty() {
+            return Color::black();
+        }
+
+        let (u, v) = sphere_uv(&unit(dir));
+        // Image rows run top-down while v runs bottom-up
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = (((1. - v) * self.height as f64) as usize).min(self.height - 1);
+
+        self.pixels[j * self.width + i]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn test_sun_outshines_horizon() {
+        let sky = Background::Sky(Sky::new(v3(1., 2., 0.), Color(v3(20., 18., 15.)), 0.5));
+        let looking = |dir: V3| {
+            sky.value(&Ray {
+                orig: v3(0., 0., 0.),
+                dir,
+                time: 0.,
+                wavelength: None,
+            })
+        };
+
+        let sun = looking(v3(2., 4., 0.));
+        let horizon = looking(v3(0., 0., 1.));
+        assert!(sun.luminance() > horizon.luminance());
+        assert_eq!(sun.0, v3(20., 18., 15.));
+        // Just outside the disk is ordinary sky
+        let beside = looking(v3(1., 2., 0.) + v3(0., 0., 0.05));
+        assert!(beside.luminance() < 1.);
+    }
+
+    #[test]
+    fn test_constant_environment() {
+        let mut bytes = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 4 +X 8\n".to_vec();
+        for _ in 0..32 {
+            bytes.extend(&[32, 64, 128, 130]);
+        }
+        // The process id keeps concurrent test runs from sharing the file
+        let file = format!("tracer_test_constant_{}.hdr", std::process::id());
+        let path = std::env::temp_dir().join(file);
+        std::fs::write(&path, &bytes).unwrap();
+        let map = EnvironmentMap::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let background = Background::Environment(map);
+        let mut rng = StdRng::seed_from_u64(0);
+        for dir in (0..100).map(|_| crate::geometry::random_unit_vec(&mut rng)) {
+            let ray = Ray {
+                orig: v3(0., 0., 0.),
+                dir,
+                time: 0.,
+                wavelength: None,
+            };
+            assert_eq!(background.value(&ray).0, v3(0.5, 1., 2.));
+        }
+    }
+}
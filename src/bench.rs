@@ -0,0 +1,93 @@
+//! A fixed render for timing changes to the tracer.
+//!
+//! The scene, camera and seeds never change, so every run traces the same
+//! paths and only the speed of tracing them differs.
+
+use std::time::{Duration, Instant};
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::camera::Camera;
+use crate::geometry::v3;
+use crate::hittable::{Hittable, HittableList};
+use crate::render::{render, RenderSettings};
+use crate::scene::make_world;
+use crate::stats;
+
+pub const SEED: u64 = 2021;
+pub const WIDTH: usize = 320;
+pub const HEIGHT: usize = 180;
+pub const SAMPLES: i32 = 8;
+pub const MAX_DEPTH: i32 = 10;
+
+pub struct BenchReport {
+    pub primary_rays: u64,
+    /// Ray-primitive intersection tests, if built with the `stats` feature.
+    pub primitive_tests: Option<u64>,
+    pub elapsed: Duration,
+}
+
+impl BenchReport {
+    /// Primary rays traced per second of wall time.
+    pub fn rays_per_second(&self) -> f64 {
+        self.primary_rays as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+}
+
+/// The objects of the benchmark scene, to be wrapped in whichever
+/// accelerator is being measured.
+pub fn scene() -> HittableList {
+    make_world(&mut StdRng::seed_from_u64(SEED))
+}
+
+/// Time a render of `world`, normally built from `scene()`, at `width` by
+/// `height` with `samples` per pixel.
+pub fn run(world: &impl Hittable, width: usize, height: usize, samples: i32) -> BenchReport {
+    let camera = Camera::builder(v3(13., 2., 3.), v3(0., 0., 0.))
+        .vfov(20.)
+        .aspect(width as f64 / height as f64)
+        .aperture(0.1)
+        .shutter(0., 1.)
+        .build();
+    let settings = RenderSettings {
+        width,
+        height,
+        samples,
+        max_depth: MAX_DEPTH,
+        seed: SEED,
+        ..Default::default()
+    };
+
+    stats::reset();
+    let start = Instant::now();
+    render(
+        &camera,
+        world,
+        &HittableList::new(),
+        &settings,
+        None,
+        |_, _| {},
+    );
+    let elapsed = start.elapsed();
+
+    BenchReport {
+        primary_rays: (width * height) as u64 * samples as u64,
+        primitive_tests: Some(stats::primitive_tests()).filter(|_| stats::ENABLED),
+        elapsed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::Bvh;
+
+    #[test]
+    fn test_bench_runs() {
+        let world = Bvh::new(scene().list);
+        let report = run(&world, 8, 4, 1);
+        assert_eq!(report.primary_rays, 32);
+        assert!(report.rays_per_second() > 0.);
+        assert!(report.primitive_tests.unwrap() > 0);
+    }
+}
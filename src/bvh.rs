@@ -0,0 +1,378 @@
+use std::sync::Arc;
+use std::thread;
+
+use crate::{
+    aabb::{surrounding_box, Aabb},
+    geometry::{Point, Ray},
+    hittable::{HitRecord, Hittable},
+};
+
+/// Subtrees over at least this many objects are built on their own thread
+/// by `Bvh::new_parallel`; below it, spawning costs more than it saves.
+const PARALLEL_THRESHOLD: usize = 1024;
+
+// Relative costs of stepping into a node and of testing a primitive, for SAH
+const TRAVERSAL_COST: f64 = 1.;
+const INTERSECTION_COST: f64 = 1.;
+
+/// How `Bvh` picks where to split each node.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BvhBuildStrategy {
+    /// Halve the objects along the axis their centroids spread out most.
+    Median,
+    /// Surface area heuristic: bucket the centroids into `bins` slices along
+    /// each axis and split between the slices where the estimated cost of
+    /// tracing a ray through the children is lowest. Splits that wouldn't
+    /// beat testing every object fall back to `Median`.
+    Sah { bins: usize },
+}
+
+type Entry = (Arc<dyn Hittable>, Aabb);
+
+#[cfg(test)]
+thread_local! {
+    // Bounding boxes tested by `BvhNode::hit` on this thread, to compare trees
+    static BOX_TESTS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+/// Bounding volume hierarchy over a set of hittables.
+///
+/// Objects without a bounding box (infinite primitives) can't be placed in
+/// the tree, so they are kept aside and tested against every ray.
+pub struct Bvh {
+    root: Option<BvhNode>,
+    unbounded: Vec<Arc<dyn Hittable>>,
+}
+
+enum BvhNode {
+    Leaf(Arc<dyn Hittable>, Aabb),
+    Branch(Box<BvhNode>, Box<BvhNode>, Aabb),
+}
+
+impl Bvh {
+    pub fn new(objects: Vec<Arc<dyn Hittable>>) -> Self {
+        Self::build(objects, BvhBuildStrategy::Median, false)
+    }
+
+    /// Like `new`, but builds large subtrees concurrently. The tree is the
+    /// same as `new` gives.
+    pub fn new_parallel(objects: Vec<Arc<dyn Hittable>>) -> Self {
+        Self::build(objects, BvhBuildStrategy::Median, true)
+    }
+
+    pub fn with_strategy(objects: Vec<Arc<dyn Hittable>>, strategy: BvhBuildStrategy) -> Self {
+        Self::build(objects, strategy, false)
+    }
+
+    fn build(objects: Vec<Arc<dyn Hittable>>, strategy: BvhBuildStrategy, parallel: bool) -> Self {
+        let mut bounded = Vec::with_capacity(objects.len());
+        let mut unbounded = Vec::new();
+
+        for object in objects {
+            match object.bounding_box() {
+                Some(bbox) => bounded.push((object, bbox)),
+                None => unbounded.push(object),
+            }
+        }
+
+        let root = if bounded.is_empty() {
+            None
+        } else {
+            Some(BvhNode::build(bounded, strategy, parallel))
+        };
+
+        Bvh { root, unbounded }
+    }
+}
+
+impl BvhNode {
+    fn build(mut objects: Vec<Entry>, strategy: BvhBuildStrategy, parallel: bool) -> Self {
+        if objects.len() == 1 {
+            let (object, bbox) = objects.pop().unwrap();
+            return BvhNode::Leaf(object, bbox);
+        }
+
+        let (lo, hi) = centroid_bounds(&objects);
+        let split = match strategy {
+            BvhBuildStrategy::Median => None,
+            BvhBuildStrategy::Sah { bins } => sah_split(&mut objects, bins, lo, hi),
+        };
+        let right = match split {
+            Some(at) => objects.split_off(at),
+            None => {
+                // Split along the axis where the object centroids are spread the most
+                let axis = (hi - lo).imax();
+                objects.sort_by(|(_, a), (_, b)| {
+                    a.centroid()[axis].partial_cmp(&b.centroid()[axis]).unwrap()
+                });
+                objects.split_off(objects.len() / 2)
+            }
+        };
+
+        let (left, right) = if parallel && right.len() >= PARALLEL_THRESHOLD {
+            thread::scope(|s| {
+                let left = s.spawn(|| BvhNode::build(objects, strategy, parallel));
+                let right = BvhNode::build(right, strategy, parallel);
+                (left.join().unwrap(), right)
+            })
+        } else {
+            (
+                BvhNode::build(objects, strategy, parallel),
+                BvhNode::build(right, strategy, parallel),
+            )
+        };
+        let bbox = surrounding_box(left.bbox(), right.bbox());
+
+        BvhNode::Branch(Box::new(left), Box::new(right), bbox)
+    }
+
+    fn bbox(&self) -> &Aabb {
+        match self {
+            BvhNode::Leaf(_, bbox) => bbox,
+            BvhNode::Branch(_, _, bbox) => bbox,
+        }
+    }
+
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        #[cfg(test)]
+        BOX_TESTS.with(|n| n.set(n.get() + 1));
+        if !self.bbox().hit(ray, t_min, t_max) {
+            return None;
+        }
+
+        match self {
+            BvhNode::Leaf(object, _) => object.hit(ray, t_min, t_max),
+            BvhNode::Branch(left, right, _) => {
+                let hit_left = left.hit(ray, t_min, t_max);
+                let closest = hit_left.as_ref().map_or(t_max, |rec| rec.t);
+                right.hit(ray, t_min, closest).or(hit_left)
+            }
+        }
+    }
+}
+
+fn centroid_bounds(objects: &[Entry]) -> (Point, Point) {
+    let first = objects[0].1.centroid();
+    objects.iter().fold((first, first), |(lo, hi), (_, bbox)| {
+        let c = bbox.centroid();
+        (lo.inf(&c), hi.sup(&c))
+    })
+}
+
+/// Order `objects` for the cheapest SAH split and return how many go left,
+/// or `None` if no split beats testing them all.
+fn sah_split(objects: &mut [Entry], bins: usize, lo: Point, hi: Point) -> Option<usize> {
+    let bins = bins.max(2);
+    let total_area = objects
+        .iter()
+        .map(|(_, bbox)| *bbox)
+        .reduce(|a, b| surrounding_box(&a, &b))?
+        .surface_area();
+    let bin_of = |bbox: &Aabb, axis: usize| {
+        let t = (bbox.centroid()[axis] - lo[axis]) / (hi[axis] - lo[axis]);
+        ((t * bins as f64) as usize).min(bins - 1)
+    };
+
+    // Best (cost, axis, first bin on the right) so far
+    let mut best: Option<(f64, usize, usize)> = None;
+    for axis in 0..3 {
+        if hi[axis] <= lo[axis] {
+            continue;
+        }
+        let mut counts = vec![0; bins];
+        let mut boxes: Vec<Option<Aabb>> = vec![None; bins];
+        for (_, bbox) in objects.iter() {
+            let b = bin_of(bbox, axis);
+            counts[b] += 1;
+            boxes[b] = Some(boxes[b].map_or(*bbox, |acc| surrounding_box(&acc, bbox)));
+        }
+
+        // Area and count of everything left of each boundary, then right of it
+        let sweep = |range: &mut dyn Iterator<Item = usize>| {
+            let (mut count, mut bbox) = (0, None::<Aabb>);
+            range
+                .map(|b| {
+                    count += counts[b];
+                    if let Some(next) = boxes[b] {
+                        bbox = Some(bbox.map_or(next, |acc| surrounding_box(&acc, &next)));
+                    }
+                    (count, bbox.map_or(0., |bbox| bbox.surface_area()))
+                })
+                .collect::<Vec<_>>()
+        };
+        let left = sweep(&mut (0..bins - 1));
+        let mut right = sweep(&mut (1..bins).rev());
+        right.reverse();
+
+        for (split, (&(n_left, a_left), &(n_right, a_right))) in
+            left.iter().zip(right.iter()).enumerate()
+        {
+            if n_left == 0 || n_right == 0 {
+                continue;
+            }
+            let cost = TRAVERSAL_COST
+                + (a_left * n_left as f64 + a_right * n_right as f64) / total_area
+                    * INTERSECTION_COST;
+            if best.is_none_or(|(c, _, _)| cost < c) {
+                best = Some((cost, axis, split + 1));
+            }
+        }
+    }
+
+    let (cost, axis, split) = best?;
+    if cost >= objects.len() as f64 * INTERSECTION_COST {
+        return None;
+    }
+    objects.sort_by_key(|(_, bbox)| bin_of(bbox, axis));
+    Some(
+        objects
+            .iter()
+            .filter(|(_, bbox)| bin_of(bbox, axis) < split)
+            .count(),
+    )
+}
+
+impl Hittable for Bvh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut any_hit = self.root.as_ref().and_then(|r| r.hit(ray, t_min, t_max));
+        let mut closest_so_far = any_hit.as_ref().map_or(t_max, |rec| rec.t);
+
+        for hittable in self.unbounded.iter() {
+            if let Some(rec) = hittable.hit(ray, t_min, closest_so_far) {
+                closest_so_far = rec.t;
+                any_hit = Some(rec);
+            }
+        }
+        any_hit
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        match self.root {
+            Some(ref root) if self.unbounded.is_empty() => Some(*root.bbox()),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::geometry::{rand_vec_bounded, random_unit_vec, v3, Sphere};
+    use crate::material::Lambertian;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn same_tree(a: &BvhNode, b: &BvhNode) -> bool {
+        match (a, b) {
+            (BvhNode::Leaf(x, xbox), BvhNode::Leaf(y, ybox)) => Arc::ptr_eq(x, y) && xbox == ybox,
+            (BvhNode::Branch(xl, xr, xbox), BvhNode::Branch(yl, yr, ybox)) => {
+                xbox == ybox && same_tree(xl, yl) && same_tree(xr, yr)
+            }
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_parallel_build_matches_serial() {
+        let mut rng = StdRng::seed_from_u64(9);
+        let material = Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5))));
+        let spheres: Vec<Arc<dyn Hittable>> = (0..4000)
+            .map(|_| {
+                Arc::new(Sphere {
+                    center: rand_vec_bounded(&mut rng, -50., 50.),
+                    radius: rng.gen_range(0.1..1.),
+                    material: material.clone(),
+                }) as Arc<dyn Hittable>
+            })
+            .collect();
+
+        let serial = Bvh::new(spheres.clone());
+        let parallel = Bvh::new_parallel(spheres);
+        assert!(same_tree(
+            serial.root.as_ref().unwrap(),
+            parallel.root.as_ref().unwrap()
+        ));
+
+        for _ in 0..500 {
+            let ray = Ray {
+                orig: rand_vec_bounded(&mut rng, -60., 60.),
+                dir: random_unit_vec(&mut rng),
+                time: 0.,
+                wavelength: None,
+            };
+            let a = serial.hit(&ray, 0.001, f64::INFINITY).map(|rec| rec.t);
+            let b = parallel.hit(&ray, 0.001, f64::INFINITY).map(|rec| rec.t);
+            assert_eq!(a, b);
+        }
+    }
+
+    /// A sphere that counts how often rays are tested against it.
+    struct Counted {
+        sphere: Sphere,
+        tests: Arc<AtomicUsize>,
+    }
+
+    impl Hittable for Counted {
+        fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+            self.tests.fetch_add(1, Ordering::Relaxed);
+            self.sphere.hit(ray, t_min, t_max)
+        }
+
+        fn bounding_box(&self) -> Option<Aabb> {
+            self.sphere.bounding_box()
+        }
+    }
+
+    #[test]
+    fn test_sah_needs_fewer_tests() {
+        let mut rng = StdRng::seed_from_u64(10);
+        let material = Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5))));
+        let tests = Arc::new(AtomicUsize::new(0));
+        // A tight clump of small spheres off to one side of a sparse field
+        let mut spheres: Vec<Arc<dyn Hittable>> = Vec::new();
+        for k in 0..2000 {
+            let (center, radius) = if k % 4 == 0 {
+                (rand_vec_bounded(&mut rng, -100., 100.), 1.)
+            } else {
+                (v3(60., 0., 0.) + rand_vec_bounded(&mut rng, -2., 2.), 0.05)
+            };
+            spheres.push(Arc::new(Counted {
+                sphere: Sphere {
+                    center,
+                    radius,
+                    material: material.clone(),
+                },
+                tests: tests.clone(),
+            }));
+        }
+        let rays: Vec<Ray> = (0..2000)
+            .map(|_| {
+                // Aimed at the clump from all over
+                let orig = rand_vec_bounded(&mut rng, -120., 120.);
+                let target = v3(60., 0., 0.) + rand_vec_bounded(&mut rng, -3., 3.);
+                Ray {
+                    orig,
+                    dir: target - orig,
+                    time: 0.,
+                    wavelength: None,
+                }
+            })
+            .collect();
+
+        // Box and primitive tests together
+        let count_tests = |strategy| {
+            let bvh = Bvh::with_strategy(spheres.clone(), strategy);
+            tests.store(0, Ordering::Relaxed);
+            BOX_TESTS.with(|n| n.set(0));
+            for ray in &rays {
+                bvh.hit(ray, 0.001, f64::INFINITY);
+            }
+            tests.load(Ordering::Relaxed) + BOX_TESTS.with(|n| n.get())
+        };
+
+        let median = count_tests(BvhBuildStrategy::Median);
+        let sah = count_tests(BvhBuildStrategy::Sah { bins: 16 });
+        assert!(sah * 10 < median * 9, "{} vs {}", sah, median);
+    }
+}
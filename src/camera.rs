@@ -1,16 +1,137 @@
-use crate::geometry::{deg_to_rad, rand_in_unit_disk, unit, Point, Ray, V3};
+use rand::RngCore;
 
+use std::f64::consts::PI;
+
+use crate::geometry::{
+    deg_to_rad, rand_in, rand_in_polygon, rand_in_unit_disk, unit, Point, Ray, V3,
+};
+
+/// How image-plane coordinates are turned into rays.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Projection {
+    Perspective,
+    /// Parallel rays along `-w` from points on the image plane.
+    Orthographic,
+    /// Equirectangular: `s` is longitude and `t` latitude over the whole
+    /// sphere, with the image centre looking along `-w`.
+    Panoramic,
+    /// Circular fisheye: distance from the image centre maps linearly to the
+    /// angle off `-w`, reaching `half_fov` at the edge of the circle.
+    Fisheye {
+        half_fov: f64,
+        aspect: f64,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
 pub struct Camera {
+    projection: Projection,
     origin: Point,
     lower_left: Point,
     horizontal: V3,
     vertical: V3,
     u: V3,
     v: V3,
+    w: V3,
     lens_radius: f64,
+    aperture_blades: Option<u32>,
+    time0: f64,
+    time1: f64,
+}
+
+/// Sets up a perspective `Camera` by name rather than by position in
+/// `Camera::new`'s argument list. Unset options default to a 90 degree field
+/// of view at 16:9 with `+y` up, a pinhole lens focused on `lookat`, and an
+/// instant shutter at time 0.
+#[derive(Clone, Copy, Debug)]
+pub struct CameraBuilder {
+    lookfrom: Point,
+    lookat: Point,
+    vup: V3,
+    vfov: f64,
+    aspect: f64,
+    aperture: f64,
+    focus_dist: Option<f64>,
+    time0: f64,
+    time1: f64,
+}
+
+impl CameraBuilder {
+    pub fn new(lookfrom: Point, lookat: Point) -> Self {
+        CameraBuilder {
+            lookfrom,
+            lookat,
+            vup: V3::new(0., 1., 0.),
+            vfov: 90.,
+            aspect: 16. / 9.,
+            aperture: 0.,
+            focus_dist: None,
+            time0: 0.,
+            time1: 0.,
+        }
+    }
+
+    pub fn vup(mut self, vup: V3) -> Self {
+        self.vup = vup;
+        self
+    }
+
+    /// Vertical field of view in degrees.
+    pub fn vfov(mut self, vfov: f64) -> Self {
+        self.vfov = vfov;
+        self
+    }
+
+    /// Width / height of the image.
+    pub fn aspect(mut self, aspect: f64) -> Self {
+        self.aspect = aspect;
+        self
+    }
+
+    /// Lens diameter; zero keeps everything in focus.
+    pub fn aperture(mut self, aperture: f64) -> Self {
+        self.aperture = aperture;
+        self
+    }
+
+    pub fn focus_dist(mut self, focus_dist: f64) -> Self {
+        self.focus_dist = Some(focus_dist);
+        self
+    }
+
+    /// Open the shutter from `time0` to `time1`, for motion blur.
+    pub fn shutter(mut self, time0: f64, time1: f64) -> Self {
+        self.time0 = time0;
+        self.time1 = time1;
+        self
+    }
+
+    pub fn build(self) -> Camera {
+        let focus_dist = self
+            .focus_dist
+            .unwrap_or_else(|| (self.lookfrom - self.lookat).norm());
+        Camera::new(
+            self.lookfrom,
+            self.lookat,
+            self.vup,
+            self.vfov,
+            self.aspect,
+            self.aperture,
+            focus_dist,
+            self.time0,
+            self.time1,
+        )
+    }
 }
 
 impl Camera {
+    pub fn builder(lookfrom: Point, lookat: Point) -> CameraBuilder {
+        CameraBuilder::new(lookfrom, lookat)
+    }
+
+    /// `time0` and `time1` bound the shutter interval; each ray is cast at a
+    /// random time within it.
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         lookfrom: Point,
         lookat: Point,
@@ -19,6 +140,8 @@ impl Camera {
         aspect: f64,
         aperture: f64,
         focus_dist: f64,
+        time0: f64,
+        time1: f64,
     ) -> Self {
         let theta = deg_to_rad(vfov);
         let h = f64::tan(theta / 2.);
@@ -38,23 +161,297 @@ impl Camera {
         let lens_radius = aperture / 2.;
 
         Self {
+            projection: Projection::Perspective,
             origin,
             lower_left,
             horizontal,
             vertical,
             u,
             v,
+            w,
             lens_radius,
+            aperture_blades: None,
+            time0,
+            time1,
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = self.lens_radius * rand_in_unit_disk();
-        let offset = self.u * rd.x + self.v * rd.y;
+    /// Parallel projection onto a `viewport_height` tall image plane through
+    /// `lookfrom`. Everything is in focus and the shutter is instant.
+    pub fn orthographic(
+        lookfrom: Point,
+        lookat: Point,
+        vup: V3,
+        viewport_height: f64,
+        aspect: f64,
+    ) -> Self {
+        let w = unit(&(lookfrom - lookat));
+        let u = unit(&vup.cross(&w));
+        let v = w.cross(&u);
+
+        let horizontal = aspect * viewport_height * u;
+        let vertical = viewport_height * v;
 
-        Ray {
-            orig: self.origin + offset,
-            dir: self.lower_left + s * self.horizontal + t * self.vertical - self.origin - offset, //dir: self.lower_left + s*self.horizontal + t*self.vertical - self.origin
+        Self {
+            projection: Projection::Orthographic,
+            origin: lookfrom,
+            lower_left: lookfrom - horizontal / 2.0 - vertical / 2.0,
+            horizontal,
+            vertical,
+            u,
+            v,
+            w,
+            lens_radius: 0.,
+            aperture_blades: None,
+            time0: 0.,
+            time1: 0.,
         }
     }
+
+    /// Shape the aperture as a regular polygon with this many blades, giving
+    /// polygonal bokeh instead of round. Panics with fewer than 3 blades,
+    /// which don't enclose an area.
+    pub fn with_aperture_blades(mut self, blades: u32) -> Self {
+        assert!(
+            blades >= 3,
+            "an aperture needs at least 3 blades, got {}",
+            blades
+        );
+        self.aperture_blades = Some(blades);
+        self
+    }
+
+    /// A full 360 by 180 degree view from `origin`, for rendering environment
+    /// maps. Use a 2:1 image.
+    pub fn panoramic(origin: Point, lookat: Point, vup: V3) -> Self {
+        let w = unit(&(origin - lookat));
+        let u = unit(&vup.cross(&w));
+        let v = w.cross(&u);
+
+        Self {
+            projection: Projection::Panoramic,
+            origin,
+            lower_left: origin,
+            horizontal: V3::zeros(),
+            vertical: V3::zeros(),
+            u,
+            v,
+            w,
+            lens_radius: 0.,
+            aperture_blades: None,
+            time0: 0.,
+            time1: 0.,
+        }
+    }
+
+    /// Circular fisheye covering `fov` degrees across the image height.
+    pub fn fisheye(lookfrom: Point, lookat: Point, vup: V3, fov: f64, aspect: f64) -> Self {
+        Self {
+            projection: Projection::Fisheye {
+                half_fov: deg_to_rad(fov) / 2.,
+                aspect,
+            },
+            ..Self::panoramic(lookfrom, lookat, vup)
+        }
+    }
+
+    /// Cameras for the left and right eye, `ipd` apart along the image's
+    /// horizontal. Both keep this camera's view direction, rather than
+    /// turning in on `lookat`, so objects at infinity line up in the two.
+    pub fn stereo_pair(&self, ipd: f64) -> (Camera, Camera) {
+        let eye = |side: f64| {
+            let offset = side * ipd / 2. * self.u;
+            Camera {
+                origin: self.origin + offset,
+                lower_left: self.lower_left + offset,
+                ..self.clone()
+            }
+        };
+        (eye(-1.), eye(1.))
+    }
+
+    /// The ray through `(s, t)` on the image, or `None` where the projection
+    /// doesn't cover that point.
+    pub fn get_ray(&self, s: f64, t: f64, rng: &mut dyn RngCore) -> Option<Ray> {
+        let (orig, dir) = match self.projection {
+            Projection::Perspective => {
+                let rd = self.lens_radius
+                    * match self.aperture_blades {
+                        Some(blades) => rand_in_polygon(rng, blades),
+                        None => rand_in_unit_disk(rng),
+                    };
+                let offset = self.u * rd.x + self.v * rd.y;
+                let orig = self.origin + offset;
+                let target = self.lower_left + s * self.horizontal + t * self.vertical;
+                (orig, target - orig)
+            }
+            Projection::Orthographic => (
+                self.lower_left + s * self.horizontal + t * self.vertical,
+                -self.w,
+            ),
+            Projection::Panoramic => {
+                // Azimuth 0 is straight ahead; polar angle 0 is straight down
+                let phi = 2. * PI * s - PI;
+                let theta = PI * t;
+                let around = phi.cos() * -self.w + phi.sin() * self.u;
+                (self.origin, theta.sin() * around - theta.cos() * self.v)
+            }
+            Projection::Fisheye { half_fov, aspect } => {
+                let x = (2. * s - 1.) * aspect;
+                let y = 2. * t - 1.;
+                let r = x.hypot(y);
+                if r > 1. {
+                    return None;
+                }
+
+                let angle = r * half_fov;
+                let side = if r > 0. {
+                    (x * self.u + y * self.v) / r
+                } else {
+                    V3::zeros()
+                };
+                (self.origin, angle.cos() * -self.w + angle.sin() * side)
+            }
+        };
+
+        Some(Ray {
+            orig,
+            dir,
+            time: rand_in(rng, self.time0, self.time1),
+            wavelength: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::v3;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn test_builder_matches_new() {
+        let (lookfrom, lookat) = (v3(13., 2., 3.), v3(0., 0., 0.));
+        let distance = (lookfrom - lookat).norm();
+
+        let defaults = Camera::builder(lookfrom, lookat).build();
+        let expected = Camera::new(
+            lookfrom,
+            lookat,
+            v3(0., 1., 0.),
+            90.,
+            16. / 9.,
+            0.,
+            distance,
+            0.,
+            0.,
+        );
+        assert_eq!(defaults, expected);
+
+        let custom = Camera::builder(lookfrom, lookat)
+            .vup(v3(0., 0., 1.))
+            .vfov(20.)
+            .aspect(1.5)
+            .aperture(0.1)
+            .focus_dist(10.)
+            .shutter(0., 1.)
+            .build();
+        let expected = Camera::new(lookfrom, lookat, v3(0., 0., 1.), 20., 1.5, 0.1, 10., 0., 1.);
+        assert_eq!(custom, expected);
+    }
+
+    #[test]
+    fn test_orthographic_rays_are_parallel() {
+        let camera = Camera::orthographic(v3(0., 0., 5.), v3(0., 0., 0.), v3(0., 1., 0.), 2., 2.);
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let a = camera.get_ray(0.25, 0.5, &mut rng).unwrap();
+        let b = camera.get_ray(0.75, 1., &mut rng).unwrap();
+        assert_eq!(a.dir, b.dir);
+        assert!((unit(&a.dir) - v3(0., 0., -1.)).norm() < 1e-12);
+        assert!((a.orig - v3(-1., 0., 5.)).norm() < 1e-12);
+        assert!((b.orig - v3(1., 1., 5.)).norm() < 1e-12);
+    }
+
+    #[test]
+    fn test_panoramic_wraps() {
+        let camera = Camera::panoramic(v3(0., 0., 0.), v3(0., 0., -1.), v3(0., 1., 0.));
+        let mut rng = StdRng::seed_from_u64(0);
+        let dir = |s, t, rng: &mut StdRng| unit(&camera.get_ray(s, t, rng).unwrap().dir);
+
+        assert!((dir(0.5, 0.5, &mut rng) - v3(0., 0., -1.)).norm() < 1e-12);
+        assert!((dir(0.75, 0.5, &mut rng) - v3(1., 0., 0.)).norm() < 1e-12);
+        assert!((dir(0.5, 1., &mut rng) - v3(0., 1., 0.)).norm() < 1e-12);
+
+        // Both side edges look straight behind the camera
+        let left = dir(0., 0.5, &mut rng);
+        let right = dir(1., 0.5, &mut rng);
+        assert!((left - v3(0., 0., 1.)).norm() < 1e-12);
+        assert!((left - right).norm() < 1e-12);
+    }
+
+    #[test]
+    fn test_fisheye() {
+        let camera = Camera::fisheye(v3(0., 0., 0.), v3(0., 0., -1.), v3(0., 1., 0.), 180., 1.);
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let center = camera.get_ray(0.5, 0.5, &mut rng).unwrap();
+        assert!((unit(&center.dir) - v3(0., 0., -1.)).norm() < 1e-12);
+
+        // The top of the circle is a quarter turn from the view direction
+        let top = camera.get_ray(0.5, 1., &mut rng).unwrap();
+        assert!((unit(&top.dir) - v3(0., 1., 0.)).norm() < 1e-12);
+
+        for &(s, t) in &[(0., 0.), (1., 0.), (0., 1.), (1., 1.)] {
+            assert!(camera.get_ray(s, t, &mut rng).is_none());
+        }
+    }
+
+    #[test]
+    fn test_aperture_blades() {
+        let camera = || {
+            Camera::new(
+                v3(0., 0., 0.),
+                v3(0., 0., -1.),
+                v3(0., 1., 0.),
+                90.,
+                1.,
+                2.,
+                1.,
+                0.,
+                0.,
+            )
+        };
+        let round = camera();
+        let hexagonal = camera().with_aperture_blades(6);
+
+        let mut rng = StdRng::seed_from_u64(0);
+        for _ in 0..1000 {
+            let ray = hexagonal.get_ray(0.5, 0.5, &mut rng).unwrap();
+            assert!(ray.orig.norm() <= 1. + 1e-12);
+        }
+
+        // Without blades the lens is sampled exactly as before
+        let (mut a, mut b) = (StdRng::seed_from_u64(1), StdRng::seed_from_u64(1));
+        let expected = rand_in_unit_disk(&mut a);
+        let ray = round.get_ray(0.5, 0.5, &mut b).unwrap();
+        assert!((ray.orig - v3(expected.x, expected.y, 0.)).norm() < 1e-12);
+    }
+
+    #[test]
+    #[should_panic(expected = "at least 3 blades, got 2")]
+    fn test_aperture_blades_minimum() {
+        Camera::new(
+            v3(0., 0., 0.),
+            v3(0., 0., -1.),
+            v3(0., 1., 0.),
+            90.,
+            1.,
+            2.,
+            1.,
+            0.,
+            0.,
+        )
+        .with_aperture_blades(2);
+    }
 }
@@ -0,0 +1,318 @@
+use std::path::PathBuf;
+
+use tracer::color::ToneMap;
+
+pub const USAGE: &str = "\
+Usage: tracer [OPTIONS]
+
+Options:
+    --width <PIXELS>      Image width [default: 400]
+    --aspect <RATIO>      Width / height, as a number or W:H [default: 16:9]
+    --samples <N>         Samples per pixel [default: 16]
+    --max-depth <N>       Maximum ray bounces [default: 50]
+    --output <PATH>       Output file, .png, .ppm or .exr [default: out.ppm]
+    --seed <N>            Seed for the scene and sampling [default: random]
+    --tone-map <MODE>     none, reinhard or reinhard:WHITE [default: none]
+    --gamma <G>           Output gamma, 1 for linear [default: 2]
+    --firefly-clamp <L>   Cap the luminance of single samples [default: off]
+    --environment <PATH>  Light the scene with a .hdr panorama [default: sky]
+    --target-noise <T>    Stop once the image changes less than T between
+                          sample batches [default: off]
+    --ssaa <N>            Render N times larger and shrink, for cleaner edges
+                          [default: 1]
+    --resume <PATH>       Add to the samples saved in PATH, if it exists, and
+                          save them back; reuses the saved seed [default: off]
+    --accel <KIND>        Ray search structure: bvh, grid or list [default: bvh]
+    --bench               Time a fixed scene with the chosen --accel and print
+                          rays per second; other options are ignored
+    -h, --help            Print this message";
+
+const VALUE_FLAGS: [&str; 14] = [
+    "--width",
+    "--aspect",
+    "--samples",
+    "--max-depth",
+    "--output",
+    "--seed",
+    "--tone-map",
+    "--gamma",
+    "--firefly-clamp",
+    "--environment",
+    "--target-noise",
+    "--resume",
+    "--ssaa",
+    "--accel",
+];
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    Ppm,
+    Png,
+    /// Linear float, ignoring `--tone-map` and `--gamma`.
+    Exr,
+}
+
+/// How the world is organized for finding ray hits.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Accelerator {
+    Bvh,
+    Grid,
+    /// Test every object; only worth it for tiny scenes.
+    List,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Options {
+    pub width: usize,
+    pub aspect: f64,
+    pub samples: i32,
+    pub max_depth: i32,
+    pub output: PathBuf,
+    pub seed: Option<u64>,
+    pub tone_map: ToneMap,
+    pub gamma: f64,
+    pub firefly_clamp: Option<f64>,
+    pub environment: Option<PathBuf>,
+    pub target_noise: Option<f64>,
+    pub resume: Option<PathBuf>,
+    /// Supersampling factor along each axis.
+    pub ssaa: usize,
+    pub accel: Accelerator,
+    pub bench: bool,
+    pub help: bool,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            width: 400,
+            aspect: 16. / 9.,
+            samples: 16,
+            max_depth: 50,
+            output: PathBuf::from("out.ppm"),
+            seed: None,
+            tone_map: ToneMap::None,
+            gamma: 2.0,
+            firefly_clamp: None,
+            environment: None,
+            target_noise: None,
+            resume: None,
+            ssaa: 1,
+            accel: Accelerator::Bvh,
+            bench: false,
+            help: false,
+        }
+    }
+}
+
+impl Options {
+    /// Parse the arguments following the program name.
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let mut options = Options::default();
+        let mut args = args.into_iter();
+
+        while let Some(arg) = args.next() {
+            // Accept both `--flag value` and `--flag=value`
+            let (flag, inline) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg, None),
+            };
+            if flag == "-h" || flag == "--help" {
+                options.help = true;
+                continue;
+            }
+            if flag == "--bench" {
+                options.bench = true;
+                continue;
+            }
+            if !VALUE_FLAGS.contains(&flag.as_str()) {
+                return Err(format!("unknown option {}", flag));
+            }
+
+            let value = inline
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("missing value for {}", flag))?;
+
+            match flag.as_str() {
+                "--width" => options.width = parse_number(&flag, &value)?,
+                "--aspect" => options.aspect = parse_aspect(&value)?,
+                "--samples" => options.samples = parse_number(&flag, &value)?,
+                "--max-depth" => options.max_depth = parse_number(&flag, &value)?,
+                "--output" => options.output = PathBuf::from(value),
+                "--seed" => options.seed = Some(parse_number(&flag, &value)?),
+                "--tone-map" => options.tone_map = parse_tone_map(&value)?,
+                "--gamma" => options.gamma = parse_number(&flag, &value)?,
+                "--firefly-clamp" => options.firefly_clamp = Some(parse_number(&flag, &value)?),
+                "--environment" => options.environment = Some(PathBuf::from(value)),
+                "--target-noise" => options.target_noise = Some(parse_number(&flag, &value)?),
+                "--resume" => options.resume = Some(PathBuf::from(value)),
+                "--ssaa" => options.ssaa = parse_number(&flag, &value)?,
+                "--accel" => options.accel = parse_accel(&value)?,
+                _ => unreachable!(),
+            }
+        }
+
+        if options.width < 2 || options.height() < 2 {
+            return Err("image must be at least 2x2 pixels".to_string());
+        }
+        if options.samples < 1 {
+            return Err("--samples must be at least 1".to_string());
+        }
+        if options.ssaa < 1 {
+            return Err("--ssaa must be at least 1".to_string());
+        }
+        if !options.gamma.is_finite() || options.gamma <= 0. {
+            return Err("--gamma must be positive".to_string());
+        }
+        if options
+            .firefly_clamp
+            .is_some_and(|cap| !cap.is_finite() || cap <= 0.)
+        {
+            return Err("--firefly-clamp must be positive".to_string());
+        }
+
+        Ok(options)
+    }
+
+    pub fn height(&self) -> usize {
+        (self.width as f64 / self.aspect) as usize
+    }
+
+    pub fn format(&self) -> OutputFormat {
+        match self.output.extension().and_then(|e| e.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("png") => OutputFormat::Png,
+            Some(ext) if ext.eq_ignore_ascii_case("exr") => OutputFormat::Exr,
+            _ => OutputFormat::Ppm,
+        }
+    }
+}
+
+fn parse_number<T: std::str::FromStr>(flag: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value '{}' for {}", value, flag))
+}
+
+fn parse_aspect(value: &str) -> Result<f64, String> {
+    let aspect = match value.split_once(':') {
+        Some((w, h)) => parse_number::<f64>("--aspect", w)? / parse_number::<f64>("--aspect", h)?,
+        None => parse_number("--aspect", value)?,
+    };
+    if aspect.is_finite() && aspect > 0. {
+        Ok(aspect)
+    } else {
+        Err(format!("invalid value '{}' for --aspect", value))
+    }
+}
+
+fn parse_accel(value: &str) -> Result<Accelerator, String> {
+    match value {
+        "bvh" => Ok(Accelerator::Bvh),
+        "grid" => Ok(Accelerator::Grid),
+        "list" => Ok(Accelerator::List),
+        _ => Err(format!("invalid value '{}' for --accel", value)),
+    }
+}
+
+fn parse_tone_map(value: &str) -> Result<ToneMap, String> {
+    match value.split_once(':') {
+        Some(("reinhard", white)) => match parse_number::<f64>("--tone-map", white)? {
+            white if white.is_finite() && white > 0. => Ok(ToneMap::ReinhardExtended { white }),
+            _ => Err(format!("invalid value '{}' for --tone-map", value)),
+        },
+        None if value == "none" => Ok(ToneMap::None),
+        None if value == "reinhard" => Ok(ToneMap::Reinhard),
+        _ => Err(format!("invalid value '{}' for --tone-map", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Options, String> {
+        Options::parse(args.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn test_defaults() {
+        let options = parse(&[]).unwrap();
+        assert_eq!(options, Options::default());
+        assert_eq!(options.height(), 225);
+        assert_eq!(options.format(), OutputFormat::Ppm);
+
+        let exr = parse(&["--output", "render.EXR"]).unwrap();
+        assert_eq!(exr.format(), OutputFormat::Exr);
+    }
+
+    #[test]
+    fn test_flags() {
+        let options = parse(&[
+            "--width",
+            "800",
+            "--aspect=4:3",
+            "--samples",
+            "64",
+            "--max-depth",
+            "8",
+            "--output",
+            "render.PNG",
+            "--seed",
+            "7",
+            "--tone-map=reinhard:4",
+            "--gamma",
+            "1",
+            "--firefly-clamp",
+            "8",
+            "--environment",
+            "studio.hdr",
+            "--target-noise",
+            "0.01",
+            "--resume",
+            "render.acc",
+            "--ssaa",
+            "2",
+            "--accel",
+            "grid",
+            "--bench",
+        ])
+        .unwrap();
+
+        assert_eq!(options.width, 800);
+        assert_eq!(options.height(), 600);
+        assert_eq!(options.samples, 64);
+        assert_eq!(options.max_depth, 8);
+        assert_eq!(options.format(), OutputFormat::Png);
+        assert_eq!(options.seed, Some(7));
+        assert_eq!(options.tone_map, ToneMap::ReinhardExtended { white: 4. });
+        assert_eq!(options.gamma, 1.);
+        assert_eq!(options.firefly_clamp, Some(8.));
+        assert_eq!(options.environment, Some(PathBuf::from("studio.hdr")));
+        assert_eq!(options.target_noise, Some(0.01));
+        assert_eq!(options.resume, Some(PathBuf::from("render.acc")));
+        assert_eq!(options.ssaa, 2);
+        assert_eq!(options.accel, Accelerator::Grid);
+        assert!(options.bench);
+    }
+
+    #[test]
+    fn test_bad_flags() {
+        assert!(parse(&["--width"]).is_err());
+        assert!(parse(&["--width", "wide"]).is_err());
+        assert!(parse(&["--aspect", "0"]).is_err());
+        assert!(parse(&["--tone-map", "filmic"]).is_err());
+        for white in &["0", "-2", "NaN", "inf"] {
+            assert!(parse(&["--tone-map", &format!("reinhard:{}", white)]).is_err());
+        }
+        assert!(parse(&["--gamma", "0"]).is_err());
+        for cap in &["0", "-1", "NaN", "inf"] {
+            assert!(parse(&["--firefly-clamp", cap]).is_err());
+        }
+        assert!(parse(&["--ssaa", "0"]).is_err());
+        assert!(parse(&["--accel", "kd-tree"]).is_err());
+        assert_eq!(
+            parse(&["--colour", "red"]),
+            Err("unknown option --colour".to_string())
+        );
+    }
+}
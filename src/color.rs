@@ -1,42 +1,132 @@
 use std::iter::Sum;
-use std::ops::{Add, Mul};
+use std::ops::{Add, Div, Mul, Sub};
 
-use rand::random;
+use rand::Rng;
 
 use crate::geometry::{rand_in, v3, V3};
 
+/// Range of wavelengths, in nanometres, picked for spectral samples.
+pub const MIN_WAVELENGTH: f64 = 380.;
+pub const MAX_WAVELENGTH: f64 = 780.;
+
 #[derive(Clone, Copy, Debug)]
 pub struct Color(pub V3);
 
+/// Compresses linear radiance into `[0, 1)` before gamma correction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ToneMap {
+    /// Channels are clamped as-is.
+    None,
+    /// `c / (1 + c)` per channel.
+    Reinhard,
+    /// Reinhard, but channels at `white` and above map to 1.
+    ReinhardExtended { white: f64 },
+}
+
+impl ToneMap {
+    pub fn apply(self, color: Color) -> Color {
+        let map = |c: f64| match self {
+            ToneMap::None => c,
+            ToneMap::Reinhard => c / (1. + c),
+            ToneMap::ReinhardExtended { white } => c * (1. + c / (white * white)) / (1. + c),
+        };
+        Color(v3(map(color.0.x), map(color.0.y), map(color.0.z)))
+    }
+}
+
 impl Color {
     pub fn black() -> Self {
         Color(v3(0.0, 0.0, 0.0))
     }
 
     pub fn ppm(&self) -> String {
-        // sqrt for gamma correction
-        let r = self.0.x.sqrt();
-        let g = self.0.y.sqrt();
-        let b = self.0.z.sqrt();
+        self.ppm_gamma(2.0)
+    }
+
+    pub fn ppm_gamma(&self, gamma: f64) -> String {
+        let [r, g, b] = self.to_rgb8_gamma(gamma);
+        format!("{} {} {}", r, g, b)
+    }
+
+    pub fn to_rgb8(self) -> [u8; 3] {
+        self.to_rgb8_gamma(2.0)
+    }
+
+    /// Gamma-encode and quantize; a `gamma` of 1.0 leaves the values linear.
+    pub fn to_rgb8_gamma(self, gamma: f64) -> [u8; 3] {
+        let r = self.0.x.powf(1.0 / gamma);
+        let g = self.0.y.powf(1.0 / gamma);
+        let b = self.0.z.powf(1.0 / gamma);
+
+        [
+            (256.0 * r.clamp(0.0, 0.999)) as u8,
+            (256.0 * g.clamp(0.0, 0.999)) as u8,
+            (256.0 * b.clamp(0.0, 0.999)) as u8,
+        ]
+    }
+
+    /// `a` at `t = 0` through to `b` at `t = 1`.
+    pub fn lerp(a: Color, b: Color, t: f64) -> Color {
+        a * (1. - t) + b * t
+    }
 
-        format!(
-            "{} {} {}",
-            (256.0 * r.clamp(0.0, 0.999)) as u32,
-            (256.0 * g.clamp(0.0, 0.999)) as u32,
-            (256.0 * b.clamp(0.0, 0.999)) as u32,
-        )
+    /// Weight of a light sample at wavelength `nm`, as a Gaussian bump per
+    /// channel. Averaged over all the spectral range it comes to white, give
+    /// or take the bumps' clipped tails.
+    pub fn from_wavelength(nm: f64) -> Color {
+        let bump = |peak: f64, width: f64| {
+            let area = width * std::f64::consts::PI.sqrt();
+            (MAX_WAVELENGTH - MIN_WAVELENGTH) / area * (-((nm - peak) / width).powi(2)).exp()
+        };
+        Color(v3(bump(605., 45.), bump(545., 40.), bump(455., 30.)))
+    }
+
+    /// Perceived brightness, using the Rec. 709 weights.
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.0.x + 0.7152 * self.0.y + 0.0722 * self.0.z
+    }
+
+    /// Parse `#rgb` or `#rrggbb`, undoing the same gamma 2 encoding as
+    /// `to_rgb8`. The `#` is optional.
+    pub fn from_hex(hex: &str) -> Result<Color, String> {
+        let digits = hex.strip_prefix('#').unwrap_or(hex);
+        let invalid = || format!("invalid hex color '{}'", hex);
+        if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+
+        let channel = |s: &str| u8::from_str_radix(s, 16).map_err(|_| invalid());
+        let [r, g, b] = match digits.len() {
+            // Each digit doubles up, so f becomes ff
+            3 => [0, 1, 2].map(|k| channel(&digits[k..k + 1]).map(|c| c * 17)),
+            6 => [0, 2, 4].map(|k| channel(&digits[k..k + 2])),
+            _ => return Err(invalid()),
+        };
+
+        let linear = |c: u8| (c as f64 / 255.).powi(2);
+        Ok(Color(v3(linear(r?), linear(g?), linear(b?))))
+    }
+
+    /// `#rrggbb`, gamma encoded like `to_rgb8`.
+    pub fn to_hex(self) -> String {
+        let [r, g, b] = self.to_rgb8();
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
     }
 
     pub fn mut_const_mul(&mut self, c: f64) {
         self.0 *= c;
     }
 
-    pub fn random() -> Color {
-        Color(v3(random(), random(), random()))
+    pub fn random<R: Rng + ?Sized>(rng: &mut R) -> Color {
+        Color(v3(rng.gen(), rng.gen(), rng.gen()))
     }
 
-    pub fn random_in(min: f64, max: f64) -> Color {
-        Color(v3(rand_in(min, max), rand_in(min, max), rand_in(min, max)))
+    pub fn random_in<R: Rng + ?Sized>(rng: &mut R, min: f64, max: f64) -> Color {
+        Color(v3(
+            rand_in(rng, min, max),
+            rand_in(rng, min, max),
+            rand_in(rng, min, max),
+        ))
     }
 }
 
@@ -60,8 +150,116 @@ impl Mul for Color {
     }
 }
 
+impl Sub for Color {
+    type Output = Color;
+
+    fn sub(self, rhs: Self) -> Self::Output {
+        Color(self.0 - rhs.0)
+    }
+}
+
+impl Mul<f64> for Color {
+    type Output = Color;
+
+    fn mul(self, rhs: f64) -> Self::Output {
+        Color(self.0 * rhs)
+    }
+}
+
+impl Div<f64> for Color {
+    type Output = Color;
+
+    fn div(self, rhs: f64) -> Self::Output {
+        Color(self.0 / rhs)
+    }
+}
+
 impl Sum for Color {
     fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
         iter.fold(Color::black(), |a, b| a + b)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reinhard() {
+        let bright = Color(v3(4., 4., 4.));
+
+        let mapped = ToneMap::Reinhard.apply(bright);
+        assert!((mapped.0.x - 0.8).abs() < 1e-12);
+        assert!(mapped.0.max() < 1.);
+        assert_eq!(ToneMap::None.apply(bright).0, bright.0);
+
+        let white = ToneMap::ReinhardExtended { white: 4. }.apply(bright);
+        assert!((white.0.x - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_operators() {
+        let a = Color(v3(0.5, 0.25, 1.));
+        let b = Color(v3(0.25, 0.25, 0.5));
+
+        assert_eq!((a + b).0, v3(0.75, 0.5, 1.5));
+        assert_eq!((a - b).0, v3(0.25, 0., 0.5));
+        assert_eq!((a * b).0, v3(0.125, 0.0625, 0.5));
+        assert_eq!((a * 2.).0, v3(1., 0.5, 2.));
+        assert_eq!((a / 4.).0, v3(0.125, 0.0625, 0.25));
+        assert_eq!(vec![a, b].into_iter().sum::<Color>().0, (a + b).0);
+    }
+
+    #[test]
+    fn test_hex() {
+        let white = Color::from_hex("#fff").unwrap();
+        assert_eq!(white.0, v3(1., 1., 1.));
+        assert_eq!(Color::from_hex("#ffffff").unwrap().0, white.0);
+        assert_eq!(
+            Color::from_hex("#FF8800").unwrap().0,
+            Color::from_hex("ff8800").unwrap().0
+        );
+        assert_eq!(Color::from_hex("#f80").unwrap().to_hex(), "#ff8800");
+        assert_eq!(Color::from_hex("#123abc").unwrap().to_hex(), "#123abc");
+        assert_eq!(Color::black().to_hex(), "#000000");
+
+        for bad in &["", "#", "#ff", "#fffff", "#fffffff", "#ggg", "#12 456"] {
+            assert!(Color::from_hex(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_lerp_and_luminance() {
+        let a = Color(v3(0.2, 0.4, 0.6));
+        let b = Color(v3(1., 0., 0.5));
+
+        assert_eq!(Color::lerp(a, b, 0.).0, a.0);
+        assert_eq!(Color::lerp(a, b, 1.).0, b.0);
+        assert!((Color::lerp(a, b, 0.5).0 - v3(0.6, 0.2, 0.55)).norm() < 1e-12);
+
+        let green = Color(v3(0., 1., 0.));
+        let blue = Color(v3(0., 0., 1.));
+        assert!(green.luminance() > blue.luminance());
+        assert!((Color(v3(1., 1., 1.)).luminance() - 1.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_gamma() {
+        let gray = Color(v3(0.25, 0.25, 0.25));
+
+        assert_eq!(gray.to_rgb8_gamma(1.0), [64, 64, 64]);
+        assert_eq!(gray.to_rgb8_gamma(2.0), [128, 128, 128]);
+        assert_eq!(gray.to_rgb8(), gray.to_rgb8_gamma(2.0));
+        assert_eq!(gray.ppm(), "128 128 128");
+    }
+
+    #[test]
+    fn test_wavelengths_average_to_white() {
+        let n = 4000;
+        let step = (MAX_WAVELENGTH - MIN_WAVELENGTH) / n as f64;
+        let mean: Color = (0..n)
+            .map(|i| Color::from_wavelength(MIN_WAVELENGTH + (i as f64 + 0.5) * step) / n as f64)
+            .sum();
+        assert!((mean.0 - v3(1., 1., 1.)).amax() < 0.01, "{:?}", mean);
+    }
+}
@@ -0,0 +1,217 @@
+use std::convert::{TryFrom, TryInto};
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+const MAGIC: [u8; 4] = [0x76, 0x2f, 0x31, 0x01];
+// Version 2, single-part scanline file with no flags set
+const VERSION: [u8; 4] = [2, 0, 0, 0];
+const PIXEL_TYPE_FLOAT: i32 = 2;
+// Channels must be listed alphabetically, so pixel data runs B, G, R
+const CHANNELS: [&str; 3] = ["B", "G", "R"];
+
+/// Encode linear RGB pixel rows, given top-to-bottom, as an OpenEXR image
+/// with 32-bit float channels.
+///
+/// Scanlines are stored uncompressed, which keeps the encoder dependency free
+/// at the cost of file size.
+pub fn write_rgb_f32<W: Write>(w: &mut W, width: usize, height: usize, rows: &[f32]) -> Result<()> {
+    assert_eq!(rows.len(), width * height * 3);
+
+    let mut header = Vec::new();
+    let mut channels = Vec::new();
+    for name in &CHANNELS {
+        channels.extend(name.as_bytes());
+        channels.push(0);
+        channels.extend(&PIXEL_TYPE_FLOAT.to_le_bytes());
+        // pLinear and reserved bytes, then x and y sampling
+        channels.extend(&[0; 4]);
+        channels.extend(&1i32.to_le_bytes());
+        channels.extend(&1i32.to_le_bytes());
+    }
+    channels.push(0);
+    attribute(&mut header, "channels", "chlist", &channels);
+    attribute(&mut header, "compression", "compression", &[0]);
+    let window: Vec<u8> = [0, 0, width as i32 - 1, height as i32 - 1]
+        .iter()
+        .flat_map(|v| v.to_le_bytes())
+        .collect();
+    attribute(&mut header, "dataWindow", "box2i", &window);
+    attribute(&mut header, "displayWindow", "box2i", &window);
+    attribute(&mut header, "lineOrder", "lineOrder", &[0]);
+    attribute(
+        &mut header,
+        "pixelAspectRatio",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    attribute(&mut header, "screenWindowCenter", "v2f", &[0; 8]);
+    attribute(
+        &mut header,
+        "screenWindowWidth",
+        "float",
+        &1f32.to_le_bytes(),
+    );
+    header.push(0);
+
+    w.write_all(&MAGIC)?;
+    w.write_all(&VERSION)?;
+    w.write_all(&header)?;
+
+    // One scanline per chunk, each found through the offset table
+    let line_size = width * 4 * CHANNELS.len();
+    let first_chunk = 8 + header.len() + 8 * height;
+    for y in 0..height {
+        let offset = first_chunk + y * (8 + line_size);
+        w.write_all(&(offset as u64).to_le_bytes())?;
+    }
+    for (y, row) in rows.chunks(width * 3).enumerate() {
+        w.write_all(&(y as i32).to_le_bytes())?;
+        w.write_all(&(line_size as i32).to_le_bytes())?;
+        for channel in (0..3).rev() {
+            for px in row.chunks(3) {
+                w.write_all(&px[channel].to_le_bytes())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn attribute(header: &mut Vec<u8>, name: &str, kind: &str, value: &[u8]) {
+    header.extend(name.as_bytes());
+    header.push(0);
+    header.extend(kind.as_bytes());
+    header.push(0);
+    header.extend(&(value.len() as i32).to_le_bytes());
+    header.extend(value);
+}
+
+/// Decode an uncompressed OpenEXR image with float R, G and B channels, as
+/// written by `write_rgb_f32`, into RGB rows top-to-bottom.
+pub fn read_rgb_f32<R: Read>(r: &mut R) -> Result<(usize, usize, Vec<f32>)> {
+    let mut bytes = Vec::new();
+    r.read_to_end(&mut bytes)?;
+    if bytes.len() < 8 || bytes[..4] != MAGIC || bytes[4] != 2 || bytes[5..8] != [0; 3] {
+        return Err(invalid("not a single-part scanline exr"));
+    }
+
+    let mut pos = 8;
+    let mut channels = Vec::new();
+    let mut window = None;
+    loop {
+        let name = c_string(&bytes, &mut pos)?;
+        if name.is_empty() {
+            break;
+        }
+        let kind = c_string(&bytes, &mut pos)?;
+        let size = usize::try_from(i32_at(&bytes, pos)?)
+            .map_err(|_| invalid("exr: negative attribute size"))?;
+        let value = bytes
+            .get(pos + 4..)
+            .and_then(|rest| rest.get(..size))
+            .ok_or_else(|| invalid("truncated exr header"))?;
+        pos += 4 + size;
+
+        match (name.as_str(), kind.as_str()) {
+            ("channels", "chlist") => {
+                let mut at = 0;
+                loop {
+                    let channel = c_string(value, &mut at)?;
+                    if channel.is_empty() {
+                        break;
+                    }
+                    if i32_at(value, at)? != PIXEL_TYPE_FLOAT {
+                        return Err(invalid("exr: only float channels are supported"));
+                    }
+                    channels.push(channel);
+                    at += 16;
+                }
+            }
+            ("compression", _) if value != [0] => {
+                return Err(invalid("exr: only uncompressed images are supported"));
+            }
+            ("dataWindow", "box2i") => {
+                let corner = |k: usize| i32_at(value, 4 * k);
+                window = Some((corner(0)?, corner(1)?, corner(2)?, corner(3)?));
+            }
+            _ => {}
+        }
+    }
+
+    let (x0, y0, x1, y1) = window.ok_or_else(|| invalid("exr: missing dataWindow"))?;
+    if channels != CHANNELS || x1 < x0 || y1 < y0 {
+        return Err(invalid("exr: expected B, G and R channels"));
+    }
+    // Spans of i32 corners can exceed i32 but not i64
+    let span = |lo: i32, hi: i32| usize::try_from(hi as i64 - lo as i64 + 1);
+    let (width, height) = match (span(x0, x1), span(y0, y1)) {
+        (Ok(width), Ok(height)) => (width, height),
+        _ => return Err(invalid("exr: data window too large")),
+    };
+    // Every scanline, with its offset and 8-byte chunk header, must fit in
+    // the file, which bounds the pixel buffer before it's allocated
+    let line_size = width
+        .checked_mul(12)
+        .filter(|&size| {
+            size.checked_add(16)
+                .and_then(|chunk| chunk.checked_mul(height))
+                .is_some_and(|total| total <= bytes.len())
+        })
+        .ok_or_else(|| invalid("truncated exr scanline"))?;
+    let offsets = height
+        .checked_mul(8)
+        .and_then(|size| bytes.get(pos..)?.get(..size))
+        .ok_or_else(|| invalid("truncated exr offsets"))?;
+    let count = width
+        .checked_mul(height)
+        .and_then(|n| n.checked_mul(3))
+        .ok_or_else(|| invalid("exr: data window too large"))?;
+
+    let mut rows = vec![0.; count];
+    for offset in offsets.chunks(8) {
+        let offset = u64::from_le_bytes(offset.try_into().unwrap());
+        let offset = usize::try_from(offset).map_err(|_| invalid("truncated exr scanline"))?;
+        let y = i32_at(&bytes, offset)? as i64 - y0 as i64;
+        let data = bytes
+            .get(offset + 8..)
+            .and_then(|rest| rest.get(..line_size))
+            .ok_or_else(|| invalid("truncated exr scanline"))?;
+        let y = match usize::try_from(y) {
+            Ok(y) if y < height => y,
+            _ => return Err(invalid("exr: scanline outside the data window")),
+        };
+
+        let row = &mut rows[y * width * 3..(y + 1) * width * 3];
+        for (c, plane) in data.chunks(width * 4).enumerate() {
+            let channel = 2 - c;
+            for (px, value) in row.chunks_mut(3).zip(plane.chunks(4)) {
+                px[channel] = f32::from_le_bytes(value.try_into().unwrap());
+            }
+        }
+    }
+
+    Ok((width, height, rows))
+}
+
+fn c_string(bytes: &[u8], pos: &mut usize) -> Result<String> {
+    let len = bytes
+        .get(*pos..)
+        .unwrap_or_default()
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or_else(|| invalid("truncated exr header"))?;
+    let s = String::from_utf8_lossy(&bytes[*pos..*pos + len]).into_owned();
+    *pos += len + 1;
+    Ok(s)
+}
+
+fn i32_at(bytes: &[u8], pos: usize) -> Result<i32> {
+    let b = bytes
+        .get(pos..)
+        .and_then(|rest| rest.get(..4))
+        .ok_or_else(|| invalid("truncated exr"))?;
+    Ok(i32::from_le_bytes(b.try_into().unwrap()))
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
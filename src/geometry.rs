@@ -1,13 +1,17 @@
 use nalgebra::base::Vector3;
 use rand::{
     distributions::uniform::{UniformFloat, UniformSampler},
-    random, thread_rng,
+    Rng, RngCore,
 };
 use std::f64::consts::PI;
+use std::sync::Arc;
 
 use crate::{
-    hittable::{HitRecord, Hittable},
+    aabb::{surrounding_box, Aabb},
+    hittable::{HitRecord, Hittable, HittableList},
+    instance::FlipNormals,
     material::Material,
+    stats,
 };
 
 pub type V3 = Vector3<f64>;
@@ -17,20 +21,106 @@ pub type Point = V3;
 pub struct Ray {
     pub orig: Point,
     pub dir: V3,
+    pub time: f64,
+    /// Wavelength in nanometres when tracing a single spectral sample, or
+    /// `None` for an ordinary RGB ray.
+    pub wavelength: Option<f64>,
 }
 
 pub struct Sphere {
     pub center: Point,
     pub radius: f64,
-    pub material: Box<dyn Material>,
+    pub material: Arc<dyn Material>,
 }
 
+/// Sphere whose center moves linearly from `center0` at `time0` to `center1`
+/// at `time1`. With no time between the two it stays at `center0`.
+pub struct MovingSphere {
+    pub center0: Point,
+    pub center1: Point,
+    pub time0: f64,
+    pub time1: f64,
+    pub radius: f64,
+    pub material: Arc<dyn Material>,
+}
+
+pub struct XyRect {
+    pub x0: f64,
+    pub x1: f64,
+    pub y0: f64,
+    pub y1: f64,
+    pub k: f64,
+    pub material: Arc<dyn Material>,
+}
+
+pub struct XzRect {
+    pub x0: f64,
+    pub x1: f64,
+    pub z0: f64,
+    pub z1: f64,
+    pub k: f64,
+    pub material: Arc<dyn Material>,
+}
+
+pub struct YzRect {
+    pub y0: f64,
+    pub y1: f64,
+    pub z0: f64,
+    pub z1: f64,
+    pub k: f64,
+    pub material: Arc<dyn Material>,
+}
+
+pub struct Triangle {
+    pub v0: Point,
+    pub v1: Point,
+    pub v2: Point,
+    pub material: Arc<dyn Material>,
+}
+
+/// Cylinder of `radius` around `axis`, running `height` from `base`. Without
+/// caps the ends are open.
+pub struct Cylinder {
+    pub base: Point,
+    pub axis: V3,
+    pub radius: f64,
+    pub height: f64,
+    pub capped: bool,
+    pub material: Arc<dyn Material>,
+}
+
+/// Flat disk of `radius` around `center`, facing `normal`.
+pub struct Disk {
+    pub center: Point,
+    pub normal: V3,
+    pub radius: f64,
+    pub material: Arc<dyn Material>,
+}
+
+/// Plane through `point` facing `normal`, extending forever; a floor that
+/// doesn't need a giant sphere.
+pub struct InfinitePlane {
+    pub point: Point,
+    pub normal: V3,
+    pub material: Arc<dyn Material>,
+}
+
+/// Axis-aligned box built from six rectangles.
+pub struct BoxPrim {
+    pub min: Point,
+    pub max: Point,
+    sides: HittableList,
+}
+
+// Padding that keeps bounding boxes of flat primitives from being degenerate
+const FLAT_PAD: f64 = 0.0001;
+
 pub fn v3(x: f64, y: f64, z: f64) -> V3 {
     V3::new(x, y, z)
 }
 
-pub fn rand_vec() -> V3 {
-    v3(random(), random(), random())
+pub fn rand_vec<R: Rng + ?Sized>(rng: &mut R) -> V3 {
+    v3(rng.gen(), rng.gen(), rng.gen())
 }
 
 pub fn unit(v: &V3) -> V3 {
@@ -42,34 +132,55 @@ pub fn near_zero(v: &V3) -> bool {
     v.norm() < eps
 }
 
-pub fn rand_in(min: f64, max: f64) -> f64 {
-    random::<f64>() * (max - min) + min
+/// Uniform in `[min, max)`. Requires `min <= max`, which is only checked in
+/// debug builds since this runs for every sample.
+pub fn rand_in<R: Rng + ?Sized>(rng: &mut R, min: f64, max: f64) -> f64 {
+    debug_assert!(min <= max, "rand_in: max ({}) < min ({})", max, min);
+    rng.gen::<f64>() * (max - min) + min
 }
 
-pub fn rand_in_unit_disk() -> V3 {
-    v3(rand_in(-1., 1.), rand_in(-1., 1.), 0.).normalize() * random::<f64>()
+pub fn rand_in_unit_disk<R: Rng + ?Sized>(rng: &mut R) -> V3 {
+    v3(rand_in(rng, -1., 1.), rand_in(rng, -1., 1.), 0.).normalize() * rng.gen::<f64>()
 }
 
-pub fn rand_vec_bounded(min: f64, max: f64) -> V3 {
-    let mut rng = thread_rng();
+/// Uniform point in the regular polygon with `sides` corners on the unit
+/// circle, the first at `+x`. `sides` should be at least 3.
+pub fn rand_in_polygon<R: Rng + ?Sized>(rng: &mut R, sides: u32) -> V3 {
+    // Pick one of the triangles fanning out from the centre, then a point
+    // inside it
+    let step = 2. * PI / sides as f64;
+    let k = rng.gen_range(0..sides) as f64;
+    let a = v3((k * step).cos(), (k * step).sin(), 0.);
+    let b = v3(((k + 1.) * step).cos(), ((k + 1.) * step).sin(), 0.);
+
+    let (mut s, mut t) = (rng.gen::<f64>(), rng.gen::<f64>());
+    if s + t > 1. {
+        s = 1. - s;
+        t = 1. - t;
+    }
+    s * a + t * b
+}
+
+pub fn rand_vec_bounded<R: Rng + ?Sized>(rng: &mut R, min: f64, max: f64) -> V3 {
     let range: UniformFloat<f64> = UniformSampler::new(min, max);
-    v3(
-        range.sample(&mut rng),
-        range.sample(&mut rng),
-        range.sample(&mut rng),
-    )
+    v3(range.sample(rng), range.sample(rng), range.sample(rng))
 }
 
 pub fn deg_to_rad(deg: f64) -> f64 {
-    (deg * PI / 2.) / 180.
+    deg * PI / 180.
 }
 
-pub fn random_in_unit_sphere() -> V3 {
-    rand_vec_bounded(-1., 1.).normalize() * random::<f64>()
+pub fn random_in_unit_sphere<R: Rng + ?Sized>(rng: &mut R) -> V3 {
+    rand_vec_bounded(rng, -1., 1.).normalize() * rng.gen::<f64>()
 }
 
-pub fn random_unit_vec() -> V3 {
-    rand_vec().normalize()
+/// Uniformly distributed over the unit sphere, so adding it to a normal gives
+/// a cosine-weighted direction.
+pub fn random_unit_vec<R: Rng + ?Sized>(rng: &mut R) -> V3 {
+    let z = rng.gen_range(-1.0..1.0);
+    let phi = rng.gen_range(0.0..2. * PI);
+    let r = f64::sqrt(1. - z * z);
+    v3(r * phi.cos(), r * phi.sin(), z)
 }
 
 pub fn reflect(v: &V3, n: &V3) -> V3 {
@@ -77,13 +188,38 @@ pub fn reflect(v: &V3, n: &V3) -> V3 {
 }
 
 pub fn refract(uv: &V3, n: &V3, etai_over_etat: f64) -> V3 {
-    let cos_theta = -uv.dot(n).min(-1.);
+    let cos_theta = (-uv).dot(n).min(1.);
     let r_out_perp = etai_over_etat * (uv + cos_theta * n);
     let r_out_par = -(1. - r_out_perp.dot(&r_out_perp)).abs().sqrt() * n;
 
     r_out_perp + r_out_par
 }
 
+/// Texture coordinates of a point `p` on the unit sphere. `u` runs around the
+/// y axis starting from -x, `v` runs from the bottom pole to the top.
+pub fn sphere_uv(p: &Point) -> (f64, f64) {
+    let theta = (-p.y).acos();
+    let phi = f64::atan2(-p.z, p.x) + PI;
+
+    (phi / (2. * PI), theta / PI)
+}
+
+/// Two unit vectors perpendicular to the unit vector `n` and to each other.
+pub(crate) fn perpendicular_basis(n: &V3) -> (V3, V3) {
+    let helper = if n.x.abs() > 0.9 {
+        v3(0., 1., 0.)
+    } else {
+        v3(1., 0., 0.)
+    };
+    let s = unit(&n.cross(&helper));
+    (s, n.cross(&s))
+}
+
+/// Half-extents of the box around a disk of `radius` facing the unit `normal`.
+fn disk_extent(normal: &V3, radius: f64) -> V3 {
+    normal.map(|c| radius * (1. - c * c).max(0.).sqrt())
+}
+
 impl Ray {
     pub fn at(&self, t: f64) -> V3 {
         self.orig + t * self.dir
@@ -98,45 +234,615 @@ impl Ray {
     }
 }
 
+fn hit_sphere<'mat>(
+    center: Point,
+    radius: f64,
+    material: &'mat dyn Material,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<HitRecord<'mat>> {
+    let oc = ray.origin() - center;
+    let a = ray.direction().norm_squared();
+    let half_b = oc.dot(&ray.direction());
+    let c = oc.norm_squared() - radius * radius;
+
+    let discriminant = half_b.powf(2.) - a * c;
+
+    if discriminant < 0.0 {
+        return None;
+    }
+
+    let mut root = (-half_b - discriminant.sqrt()) / a;
+    if root < t_min || t_max < root {
+        root = (-half_b + discriminant.sqrt()) / a;
+        if root < t_min || t_max < root {
+            return None;
+        }
+    }
+
+    let t = root;
+    let point = ray.at(root);
+    let outward_normal = (point - center) / radius;
+
+    Some(HitRecord::new(
+        ray,
+        &outward_normal,
+        point,
+        material,
+        t,
+        sphere_uv(&outward_normal),
+    ))
+}
+
+impl Sphere {
+    /// Cosine of the half-angle of the cone the sphere fills as seen from
+    /// `origin`; -1 from inside, where it fills every direction.
+    fn cos_theta_max(&self, origin: &Point) -> f64 {
+        let distance_squared = (self.center - origin).norm_squared();
+        let ratio = self.radius * self.radius / distance_squared;
+        if ratio >= 1. {
+            -1.
+        } else {
+            (1. - ratio).sqrt()
+        }
+    }
+}
+
 impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let oc = ray.origin() - self.center;
-        let a = ray.direction().norm_squared();
-        let half_b = oc.dot(&ray.direction());
-        let c = oc.norm_squared() - self.radius * self.radius;
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        stats::record_primitive_test();
+        hit_sphere(
+            self.center,
+            self.radius,
+            self.material.as_ref(),
+            ray,
+            t_min,
+            t_max,
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = v3(self.radius, self.radius, self.radius);
+        Some(Aabb::new(self.center - r, self.center + r))
+    }
+
+    /// Uniform over the cone of directions the sphere covers from `origin`.
+    fn pdf_value(&self, origin: &Point, dir: &V3) -> f64 {
+        let ray = Ray {
+            orig: *origin,
+            dir: *dir,
+            time: 0.,
+            wavelength: None,
+        };
+        if self.hit(&ray, 0.001, f64::INFINITY).is_none() {
+            return 0.;
+        }
+        1. / (2. * PI * (1. - self.cos_theta_max(origin)))
+    }
+
+    fn random(&self, origin: &Point, rng: &mut dyn RngCore) -> V3 {
+        let cos_theta_max = self.cos_theta_max(origin);
+        // From inside the cone is the whole sphere of directions, and from
+        // the centre there's no axis to build it around
+        if cos_theta_max <= -1. {
+            return random_unit_vec(rng);
+        }
+        let axis = unit(&(self.center - origin));
+        let (s, t) = perpendicular_basis(&axis);
+        let z = rng.gen_range(cos_theta_max..=1.);
+        let phi = rng.gen_range(0.0..2. * PI);
+        let r = (1. - z * z).max(0.).sqrt();
+        r * phi.cos() * s + r * phi.sin() * t + z * axis
+    }
+}
+
+impl MovingSphere {
+    pub fn center(&self, time: f64) -> Point {
+        if self.time0 == self.time1 {
+            return self.center0;
+        }
+        let s = (time - self.time0) / (self.time1 - self.time0);
+        self.center0 + s * (self.center1 - self.center0)
+    }
+}
+
+impl Hittable for MovingSphere {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        stats::record_primitive_test();
+        hit_sphere(
+            self.center(ray.time),
+            self.radius,
+            self.material.as_ref(),
+            ray,
+            t_min,
+            t_max,
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        // Covers the sphere over the whole shutter interval
+        let r = v3(self.radius, self.radius, self.radius);
+        let box0 = Aabb::new(self.center0 - r, self.center0 + r);
+        let box1 = Aabb::new(self.center1 - r, self.center1 + r);
+        Some(surrounding_box(&box0, &box1))
+    }
+}
+
+/// Intersect a ray with the disk of `radius` around `center` facing the unit
+/// `normal`. `u` is the angle around the normal and `v` the distance from the
+/// center, both scaled to `[0, 1]`.
+fn hit_disk<'mat>(
+    center: Point,
+    normal: &V3,
+    radius: f64,
+    material: &'mat dyn Material,
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+) -> Option<HitRecord<'mat>> {
+    let denom = ray.dir.dot(normal);
+    if denom.abs() < 1e-12 {
+        return None;
+    }
+
+    let t = (center - ray.orig).dot(normal) / denom;
+    if !(t_min..=t_max).contains(&t) {
+        return None;
+    }
+
+    let point = ray.at(t);
+    let offset = point - center;
+    if offset.norm_squared() > radius * radius {
+        return None;
+    }
+
+    let (s, r) = perpendicular_basis(normal);
+    let phi = f64::atan2(offset.dot(&r), offset.dot(&s)) + PI;
+    let uv = (phi / (2. * PI), offset.norm() / radius);
+
+    Some(HitRecord::new(ray, normal, point, material, t, uv))
+}
+
+/// Intersect a ray with the rectangle `[a0, a1] x [b0, b1]` lying in the plane
+/// `axis[c] = k`, where `axis = (a, b, c)` indexes the coordinates.
+fn hit_rect<'mat>(
+    ray: &Ray,
+    t_min: f64,
+    t_max: f64,
+    (a, b, c): (usize, usize, usize),
+    (a0, a1, b0, b1): (f64, f64, f64, f64),
+    k: f64,
+    material: &'mat dyn Material,
+) -> Option<HitRecord<'mat>> {
+    let t = (k - ray.orig[c]) / ray.dir[c];
+    if !(t_min..=t_max).contains(&t) {
+        return None;
+    }
+
+    let pa = ray.orig[a] + t * ray.dir[a];
+    let pb = ray.orig[b] + t * ray.dir[b];
+    if pa < a0 || pa > a1 || pb < b0 || pb > b1 {
+        return None;
+    }
+
+    let mut outward_normal = V3::zeros();
+    outward_normal[c] = 1.;
+    let uv = ((pa - a0) / (a1 - a0), (pb - b0) / (b1 - b0));
+
+    Some(HitRecord::new(
+        ray,
+        &outward_normal,
+        ray.at(t),
+        material,
+        t,
+        uv,
+    ))
+}
+
+impl Hittable for XyRect {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        stats::record_primitive_test();
+        let bounds = (self.x0, self.x1, self.y0, self.y1);
+        hit_rect(
+            ray,
+            t_min,
+            t_max,
+            (0, 1, 2),
+            bounds,
+            self.k,
+            self.material.as_ref(),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(
+            v3(self.x0, self.y0, self.k - FLAT_PAD),
+            v3(self.x1, self.y1, self.k + FLAT_PAD),
+        ))
+    }
+}
+
+impl Hittable for XzRect {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        stats::record_primitive_test();
+        let bounds = (self.x0, self.x1, self.z0, self.z1);
+        hit_rect(
+            ray,
+            t_min,
+            t_max,
+            (0, 2, 1),
+            bounds,
+            self.k,
+            self.material.as_ref(),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(
+            v3(self.x0, self.k - FLAT_PAD, self.z0),
+            v3(self.x1, self.k + FLAT_PAD, self.z1),
+        ))
+    }
 
-        let discriminant = half_b.powf(2.) - a * c;
+    fn pdf_value(&self, origin: &Point, dir: &V3) -> f64 {
+        let ray = Ray {
+            orig: *origin,
+            dir: *dir,
+            time: 0.,
+            wavelength: None,
+        };
+        let Some(rec) = self.hit(&ray, 0.001, f64::INFINITY) else {
+            return 0.;
+        };
+
+        // Convert the uniform density over the area to one over solid angle
+        let area = (self.x1 - self.x0) * (self.z1 - self.z0);
+        let distance_squared = rec.t * rec.t * dir.norm_squared();
+        let cosine = (dir.y / dir.norm()).abs();
+        distance_squared / (cosine * area)
+    }
+
+    fn random(&self, origin: &Point, rng: &mut dyn RngCore) -> V3 {
+        let x = rng.gen_range(self.x0..self.x1);
+        let z = rng.gen_range(self.z0..self.z1);
+        v3(x, self.k, z) - origin
+    }
+}
+
+impl Hittable for YzRect {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        stats::record_primitive_test();
+        let bounds = (self.y0, self.y1, self.z0, self.z1);
+        hit_rect(
+            ray,
+            t_min,
+            t_max,
+            (1, 2, 0),
+            bounds,
+            self.k,
+            self.material.as_ref(),
+        )
+    }
 
-        if discriminant < 0.0 {
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(
+            v3(self.k - FLAT_PAD, self.y0, self.z0),
+            v3(self.k + FLAT_PAD, self.y1, self.z1),
+        ))
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        stats::record_primitive_test();
+        // Möller–Trumbore
+        let eps = 1e-12;
+        let edge1 = self.v1 - self.v0;
+        let edge2 = self.v2 - self.v0;
+
+        let pvec = ray.dir.cross(&edge2);
+        let det = edge1.dot(&pvec);
+        if det.abs() < eps {
+            // Ray is parallel to the triangle's plane
             return None;
         }
+        let inv_det = 1. / det;
 
-        let mut root = (-half_b - discriminant.sqrt()) / a;
-        if root < t_min || t_max < root {
-            root = (-half_b + discriminant.sqrt()) / a;
-            if root < t_min || t_max < root {
-                return None;
-            }
+        let tvec = ray.orig - self.v0;
+        let u = tvec.dot(&pvec) * inv_det;
+        if !(0.0..=1.0).contains(&u) {
+            return None;
+        }
+
+        let qvec = tvec.cross(&edge1);
+        let v = ray.dir.dot(&qvec) * inv_det;
+        if v < 0. || u + v > 1. {
+            return None;
         }
 
-        let t = root;
-        let point = ray.at(root);
-        let outward_normal = (point - self.center) / self.radius;
+        let t = edge2.dot(&qvec) * inv_det;
+        if t < t_min || t_max < t {
+            return None;
+        }
+
+        let outward_normal = unit(&edge1.cross(&edge2));
 
         Some(HitRecord::new(
-            &ray,
+            ray,
             &outward_normal,
+            ray.at(t),
+            self.material.as_ref(),
+            t,
+            (u, v),
+        ))
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let pad = v3(FLAT_PAD, FLAT_PAD, FLAT_PAD);
+        let min = self.v0.inf(&self.v1).inf(&self.v2);
+        let max = self.v0.sup(&self.v1).sup(&self.v2);
+        Some(Aabb::new(min - pad, max + pad))
+    }
+}
+
+impl Cylinder {
+    fn hit_body(&self, axis: &V3, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        // Drop the components along the axis and solve for the infinite tube
+        let oc = ray.orig - self.base;
+        let d_perp = ray.dir - ray.dir.dot(axis) * axis;
+        let oc_perp = oc - oc.dot(axis) * axis;
+
+        let a = d_perp.norm_squared();
+        let half_b = oc_perp.dot(&d_perp);
+        let c = oc_perp.norm_squared() - self.radius * self.radius;
+        let discriminant = half_b * half_b - a * c;
+        if a < 1e-12 || discriminant < 0. {
+            return None;
+        }
+
+        let sqrtd = discriminant.sqrt();
+        for &t in &[(-half_b - sqrtd) / a, (-half_b + sqrtd) / a] {
+            if t < t_min || t_max < t {
+                continue;
+            }
+            let point = ray.at(t);
+            let h = (point - self.base).dot(axis);
+            if h < 0. || h > self.height {
+                continue;
+            }
+
+            let radial = point - self.base - h * axis;
+            let outward_normal = radial / self.radius;
+            let (s, r) = perpendicular_basis(axis);
+            let phi = f64::atan2(radial.dot(&r), radial.dot(&s)) + PI;
+            let uv = (phi / (2. * PI), h / self.height);
+
+            return Some(HitRecord::new(
+                ray,
+                &outward_normal,
+                point,
+                self.material.as_ref(),
+                t,
+                uv,
+            ));
+        }
+        None
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        stats::record_primitive_test();
+        let axis = unit(&self.axis);
+        let mut closest = self.hit_body(&axis, ray, t_min, t_max);
+
+        if self.capped {
+            let top = self.base + self.height * axis;
+            for &(center, normal) in &[(self.base, -axis), (top, axis)] {
+                let t_max = closest.as_ref().map_or(t_max, |rec| rec.t);
+                let material = self.material.as_ref();
+                if let Some(rec) =
+                    hit_disk(center, &normal, self.radius, material, ray, t_min, t_max)
+                {
+                    closest = Some(rec);
+                }
+            }
+        }
+
+        closest
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let axis = unit(&self.axis);
+        let top = self.base + self.height * axis;
+        let extent = disk_extent(&axis, self.radius);
+        let base_box = Aabb::new(self.base - extent, self.base + extent);
+        let top_box = Aabb::new(top - extent, top + extent);
+        Some(surrounding_box(&base_box, &top_box))
+    }
+}
+
+impl Hittable for Disk {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        stats::record_primitive_test();
+        hit_disk(
+            self.center,
+            &unit(&self.normal),
+            self.radius,
+            self.material.as_ref(),
+            ray,
+            t_min,
+            t_max,
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let pad = v3(FLAT_PAD, FLAT_PAD, FLAT_PAD);
+        let extent = disk_extent(&unit(&self.normal), self.radius) + pad;
+        Some(Aabb::new(self.center - extent, self.center + extent))
+    }
+}
+
+impl Hittable for InfinitePlane {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        stats::record_primitive_test();
+        let normal = unit(&self.normal);
+        let denom = ray.dir.dot(&normal);
+        if denom.abs() < 1e-12 {
+            return None;
+        }
+
+        let t = (self.point - ray.orig).dot(&normal) / denom;
+        if !(t_min..=t_max).contains(&t) {
+            return None;
+        }
+
+        // Texture coordinates repeat every unit along the plane
+        let point = ray.at(t);
+        let (s, r) = perpendicular_basis(&normal);
+        let offset = point - self.point;
+        let uv = (offset.dot(&s).rem_euclid(1.), offset.dot(&r).rem_euclid(1.));
+
+        Some(HitRecord::new(
+            ray,
+            &normal,
             point,
             self.material.as_ref(),
             t,
+            uv,
         ))
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+impl BoxPrim {
+    pub fn new(min: Point, max: Point, material: Arc<dyn Material>) -> Self {
+        let mut sides = HittableList::new();
+
+        // Rects face +axis, so the faces on the min side are turned inside
+        // out to face away from the box
+        let mut add_side = |side: Box<dyn Hittable>, k: f64, min_k: f64| {
+            if k == min_k {
+                sides.add(Arc::new(FlipNormals { object: side }));
+            } else {
+                sides.add(Arc::from(side));
+            }
+        };
+        for &k in &[min.z, max.z] {
+            let side = XyRect {
+                x0: min.x,
+                x1: max.x,
+                y0: min.y,
+                y1: max.y,
+                k,
+                material: material.clone(),
+            };
+            add_side(Box::new(side), k, min.z);
+        }
+        for &k in &[min.y, max.y] {
+            let side = XzRect {
+                x0: min.x,
+                x1: max.x,
+                z0: min.z,
+                z1: max.z,
+                k,
+                material: material.clone(),
+            };
+            add_side(Box::new(side), k, min.y);
+        }
+        for &k in &[min.x, max.x] {
+            let side = YzRect {
+                y0: min.y,
+                y1: max.y,
+                z0: min.z,
+                z1: max.z,
+                k,
+                material: material.clone(),
+            };
+            add_side(Box::new(side), k, min.x);
+        }
+
+        BoxPrim { min, max, sides }
+    }
+}
+
+impl Hittable for BoxPrim {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.sides.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::new(self.min, self.max))
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::{Color, Metal};
+    use crate::{color::Color, material::Metal};
+    use rand::SeedableRng;
+
+    #[test]
+    fn test_infinite_plane() {
+        let plane = InfinitePlane {
+            point: v3(0., -1., 0.),
+            normal: v3(0., 2., 0.),
+            material: Arc::new(Metal::new(Color(v3(1., 1., 1.)), 0.)),
+        };
+        let ray = |orig, dir| Ray {
+            orig,
+            dir,
+            time: 0.,
+            wavelength: None,
+        };
+
+        let down = plane
+            .hit(&ray(v3(3., 4., -2.), v3(0., -2., 0.)), 0.001, f64::INFINITY)
+            .unwrap();
+        assert!((down.t - 2.5).abs() < 1e-12);
+        assert!((down.point - v3(3., -1., -2.)).norm() < 1e-12);
+        assert!(down.front_face);
+
+        let up = plane
+            .hit(&ray(v3(0., -3., 0.), v3(0.5, 1., 0.)), 0.001, f64::INFINITY)
+            .unwrap();
+        assert!(!up.front_face);
+        assert_eq!(up.normal, v3(0., -1., 0.));
+
+        // Parallel rays never meet it, and neither do hits behind t_min
+        assert!(plane
+            .hit(&ray(v3(0., 0., 0.), v3(1., 0., 0.)), 0.001, f64::INFINITY)
+            .is_none());
+        assert!(plane
+            .hit(&ray(v3(0., 0., 0.), v3(0., 1., 0.)), 0.001, f64::INFINITY)
+            .is_none());
+        assert!(plane.bounding_box().is_none());
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "rand_in: max (1) < min (2)")]
+    fn test_rand_in_inverted_range() {
+        rand_in(&mut rand::rngs::StdRng::seed_from_u64(0), 2., 1.);
+    }
+
+    #[test]
+    fn test_random_unit_vec_covers_sphere() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let mut octants = [0; 8];
+        for _ in 0..1000 {
+            let v = random_unit_vec(&mut rng);
+            assert!((v.norm() - 1.).abs() < 1e-12);
+            let octant =
+                (v.x < 0.) as usize | ((v.y < 0.) as usize) << 1 | ((v.z < 0.) as usize) << 2;
+            octants[octant] += 1;
+        }
+        assert!(octants.iter().all(|&n| n > 80), "{:?}", octants);
+    }
 
     #[test]
     fn test_hit_sphere() {
@@ -154,6 +860,8 @@ mod tests {
         let ray = Ray {
             orig: v3(-2., 0., 0.),
             dir: v3(1., 0., 0.),
+            time: 0.,
+            wavelength: None,
         };
 
         // Test hit
@@ -162,7 +870,7 @@ mod tests {
         // A Hit should be detected
         match res {
             // recorded hit site should be -1, 0, 0
-            Some(rec) => assert_eq!((rec.point - v3(-1., 0., 0.)).norm() < 0.01, true),
+            Some(rec) => assert!((rec.point - v3(-1., 0., 0.)).norm() < 0.01),
             None => panic!("Expected a hit to be recorded"),
         }
     }
@@ -183,16 +891,15 @@ mod tests {
         let ray = Ray {
             orig: v3(-2., 0., 0.),
             dir: v3(0., 1., 0.),
+            time: 0.,
+            wavelength: None,
         };
 
         // Test hit
         let res = sphere.hit(&ray, 0., 100.);
 
         // No hit should be detected
-        match res {
-            Some(_) => panic!("No hit should be detected"),
-            None => (),
-        }
+        assert!(res.is_none(), "No hit should be detected");
     }
 
     #[test]
@@ -211,6 +918,8 @@ mod tests {
         let ray = Ray {
             orig: v3(0., 0., 0.),
             dir: v3(1., 0., 0.),
+            time: 0.,
+            wavelength: None,
         };
 
         // Test hit
@@ -219,7 +928,7 @@ mod tests {
         // Hit should be detected
         match res {
             // Hit should occur at 1, 0, 0
-            Some(rec) => assert_eq!((rec.point - v3(1., 0., 0.)).norm() < 0.01, true),
+            Some(rec) => assert!((rec.point - v3(1., 0., 0.)).norm() < 0.01),
             None => panic!("Expected a hit to be recorded"),
         };
     }
@@ -240,6 +949,8 @@ mod tests {
         let ray = Ray {
             orig: v3(-2., 1., 0.),
             dir: v3(1., 0., 0.),
+            time: 0.,
+            wavelength: None,
         };
 
         // Test hit
@@ -248,8 +959,458 @@ mod tests {
         // Hit should be detected
         match res {
             // Hit should occur at 0, 1, 0
-            Some(rec) => assert_eq!((rec.point - v3(0., 1., 0.)).norm() < 0.01, true),
+            Some(rec) => assert!((rec.point - v3(0., 1., 0.)).norm() < 0.01),
+            None => panic!("Expected a hit to be recorded"),
+        };
+    }
+
+    #[test]
+    fn test_refract_snell() {
+        // 45 degrees from the normal, from air into glass
+        let n = v3(0., 1., 0.);
+        let uv = unit(&v3(1., -1., 0.));
+        let eta = 1. / 1.5;
+
+        let out = refract(&uv, &n, eta);
+        let sin_out = (PI / 4.).sin() * eta;
+        assert!((out.norm() - 1.).abs() < 1e-9);
+        assert!((out.x - sin_out).abs() < 1e-9);
+        assert!((out.y + (1. - sin_out * sin_out).sqrt()).abs() < 1e-9);
+
+        // Head-on rays pass straight through
+        let straight = refract(&v3(0., -1., 0.), &n, eta);
+        assert!((straight - v3(0., -1., 0.)).norm() < 1e-9);
+    }
+
+    #[test]
+    fn test_rand_in_polygon() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let apothem = (PI / 6.).cos();
+
+        for _ in 0..1000 {
+            let p = rand_in_polygon(&mut rng, 6);
+            assert!(p.norm() <= 1. && p.z == 0.);
+            // Inside every edge of the hexagon
+            for k in 0..6 {
+                let mid = (k as f64 + 0.5) * PI / 3.;
+                assert!(p.dot(&v3(mid.cos(), mid.sin(), 0.)) <= apothem + 1e-12);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sphere_hit_uv() {
+        let sphere = Sphere {
+            center: v3(1., 2., 3.),
+            radius: 2.,
+            material: Arc::new(Metal::new(Color(v3(1., 1., 1.)), 0.)),
+        };
+        let uv_from = |orig: V3, dir: V3| {
+            let ray = Ray {
+                orig,
+                dir,
+                time: 0.,
+                wavelength: None,
+            };
+            let rec = sphere.hit(&ray, 0., 100.).unwrap();
+            (rec.u, rec.v)
+        };
+
+        // Poles along y, equator around it
+        let (_, bottom) = uv_from(v3(1., -5., 3.), v3(0., 1., 0.));
+        let (_, top) = uv_from(v3(1., 9., 3.), v3(0., -1., 0.));
+        assert!(bottom.abs() < 1e-9 && (top - 1.).abs() < 1e-9);
+
+        let (u, v) = uv_from(v3(-5., 2., 3.), v3(1., 0., 0.));
+        assert!(u.abs() < 1e-9 && (v - 0.5).abs() < 1e-9);
+        let (u, v) = uv_from(v3(1., 2., 9.), v3(0., 0., -1.));
+        assert!((u - 0.25).abs() < 1e-9 && (v - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_deg_to_rad() {
+        assert!((deg_to_rad(180.) - PI).abs() < 1e-12);
+        assert!((deg_to_rad(90.) - PI / 2.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_hit_xy_rect() {
+        let rect = XyRect {
+            x0: -1.,
+            x1: 1.,
+            y0: -1.,
+            y1: 1.,
+            k: -2.,
+            material: Arc::new(Metal {
+                albedo: Color(v3(1., 1., 1.)),
+                fuzz: 0.1,
+            }),
+        };
+
+        let ray = Ray {
+            orig: v3(0., 0., 0.),
+            dir: v3(0., 0., -1.),
+            time: 0.,
+            wavelength: None,
+        };
+
+        match rect.hit(&ray, 0., 100.) {
+            Some(rec) => {
+                assert!((rec.point - v3(0., 0., -2.)).norm() < 0.01);
+                assert!((rec.t - 2.).abs() < 0.01);
+                // The ray travels against +z, so it sees the front face
+                assert!(rec.front_face);
+                assert_eq!(rec.normal, v3(0., 0., 1.));
+                assert!((rec.u - 0.5).abs() < 0.01 && (rec.v - 0.5).abs() < 0.01);
+            }
             None => panic!("Expected a hit to be recorded"),
+        }
+
+        let miss = Ray {
+            orig: v3(2., 0., 0.),
+            dir: v3(0., 0., -1.),
+            time: 0.,
+            wavelength: None,
+        };
+        assert!(rect.hit(&miss, 0., 100.).is_none());
+    }
+
+    fn unit_triangle() -> Triangle {
+        Triangle {
+            v0: v3(-1., -1., 0.),
+            v1: v3(1., -1., 0.),
+            v2: v3(0., 1., 0.),
+            material: Arc::new(Metal {
+                albedo: Color(v3(1., 1., 1.)),
+                fuzz: 0.1,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_hit_triangle_front_face() {
+        let tri = unit_triangle();
+        let ray = Ray {
+            orig: v3(0., 0., 2.),
+            dir: v3(0., 0., -1.),
+            time: 0.,
+            wavelength: None,
         };
+
+        match tri.hit(&ray, 0., 100.) {
+            Some(rec) => {
+                assert!((rec.point - v3(0., 0., 0.)).norm() < 0.01);
+                assert!(rec.front_face);
+                assert_eq!(rec.normal, v3(0., 0., 1.));
+                // Barycentrics of the hit relative to v1 and v2
+                assert!((rec.u - 0.25).abs() < 0.01 && (rec.v - 0.5).abs() < 0.01);
+            }
+            None => panic!("Expected a hit to be recorded"),
+        }
+    }
+
+    #[test]
+    fn test_hit_triangle_back_face() {
+        let tri = unit_triangle();
+        let ray = Ray {
+            orig: v3(0., 0., -2.),
+            dir: v3(0., 0., 1.),
+            time: 0.,
+            wavelength: None,
+        };
+
+        match tri.hit(&ray, 0., 100.) {
+            Some(rec) => {
+                assert!(!rec.front_face);
+                assert_eq!(rec.normal, v3(0., 0., -1.));
+            }
+            None => panic!("Expected a hit to be recorded"),
+        }
+    }
+
+    #[test]
+    fn test_hit_triangle_miss() {
+        let tri = unit_triangle();
+
+        // Parallel to the plane
+        let parallel = Ray {
+            orig: v3(-5., 0., 1.),
+            dir: v3(1., 0., 0.),
+            time: 0.,
+            wavelength: None,
+        };
+        assert!(tri.hit(&parallel, 0., 100.).is_none());
+
+        // Crosses the plane outside the triangle
+        let outside = Ray {
+            orig: v3(2., 2., 2.),
+            dir: v3(0., 0., -1.),
+            time: 0.,
+            wavelength: None,
+        };
+        assert!(tri.hit(&outside, 0., 100.).is_none());
+    }
+
+    #[test]
+    fn test_hit_box_face() {
+        let mat = Metal {
+            albedo: Color(v3(1., 1., 1.)),
+            fuzz: 0.1,
+        };
+        let bx = BoxPrim::new(v3(0., 0., 0.), v3(1., 2., 3.), Arc::new(mat));
+
+        // Fire at the centre of each face from outside
+        let centre = v3(0.5, 1., 1.5);
+        for axis in 0..3 {
+            for &sign in &[-1., 1.] {
+                let mut dir = V3::zeros();
+                dir[axis] = -sign;
+                let ray = Ray {
+                    orig: centre - 10. * dir,
+                    dir,
+                    time: 0.,
+                    wavelength: None,
+                };
+
+                let rec = bx
+                    .hit(&ray, 0., 100.)
+                    .expect("Expected a hit to be recorded");
+                let mut face = centre;
+                face[axis] = if sign < 0. { 0. } else { [1., 2., 3.][axis] };
+                assert!((rec.point - face).norm() < 0.01);
+                assert_eq!(rec.normal, -dir);
+                assert!(rec.front_face, "axis {} sign {}", axis, sign);
+            }
+        }
+
+        // From inside every face is a back face
+        let ray = Ray {
+            orig: centre,
+            dir: v3(-1., 0., 0.),
+            time: 0.,
+            wavelength: None,
+        };
+        assert!(!bx.hit(&ray, 0., 100.).unwrap().front_face);
+
+        assert_eq!(
+            bx.bounding_box(),
+            Some(Aabb::new(v3(0., 0., 0.), v3(1., 2., 3.)))
+        );
+    }
+
+    #[test]
+    fn test_moving_sphere_center() {
+        let sphere = MovingSphere {
+            center0: v3(0., 0., 0.),
+            center1: v3(2., 4., -2.),
+            time0: 1.,
+            time1: 3.,
+            radius: 0.5,
+            material: Arc::new(Metal {
+                albedo: Color(v3(1., 1., 1.)),
+                fuzz: 0.1,
+            }),
+        };
+
+        assert_eq!(sphere.center(1.), sphere.center0);
+        assert_eq!(sphere.center(3.), sphere.center1);
+        assert_eq!(sphere.center(2.), v3(1., 2., -1.));
+
+        // A ray at time1 finds the sphere at its final position
+        let ray = Ray {
+            orig: v3(2., 4., 2.),
+            dir: v3(0., 0., -1.),
+            time: 3.,
+            wavelength: None,
+        };
+        match sphere.hit(&ray, 0., 100.) {
+            Some(rec) => assert!((rec.point - v3(2., 4., -1.5)).norm() < 0.01),
+            None => panic!("Expected a hit to be recorded"),
+        }
+
+        // A zero-length shutter interval doesn't divide by zero
+        let still = MovingSphere {
+            time1: 1.,
+            ..sphere
+        };
+        assert_eq!(still.center(1.), still.center0);
+        assert_eq!(still.center(5.), still.center0);
+    }
+
+    fn unit_cylinder(capped: bool) -> Cylinder {
+        Cylinder {
+            base: v3(0., 0., 0.),
+            axis: v3(0., 1., 0.),
+            radius: 1.,
+            height: 2.,
+            capped,
+            material: Arc::new(Metal {
+                albedo: Color(v3(1., 1., 1.)),
+                fuzz: 0.1,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_hit_cylinder_side() {
+        let cyl = unit_cylinder(true);
+        let ray = Ray {
+            orig: v3(-3., 1., 0.),
+            dir: v3(1., 0., 0.),
+            time: 0.,
+            wavelength: None,
+        };
+
+        match cyl.hit(&ray, 0., 100.) {
+            Some(rec) => {
+                assert!((rec.point - v3(-1., 1., 0.)).norm() < 1e-9);
+                assert!(rec.front_face);
+                assert!((rec.normal - v3(-1., 0., 0.)).norm() < 1e-9);
+                assert!((rec.v - 0.5).abs() < 1e-9);
+            }
+            None => panic!("Expected a hit to be recorded"),
+        }
+
+        // Passes beside the cylinder
+        let miss = Ray {
+            orig: v3(-3., 1., 2.),
+            dir: v3(1., 0., 0.),
+            time: 0.,
+            wavelength: None,
+        };
+        assert!(cyl.hit(&miss, 0., 100.).is_none());
+    }
+
+    #[test]
+    fn test_hit_cylinder_cap() {
+        let cyl = unit_cylinder(true);
+        let ray = Ray {
+            orig: v3(0.5, 5., 0.),
+            dir: v3(0., -1., 0.),
+            time: 0.,
+            wavelength: None,
+        };
+
+        match cyl.hit(&ray, 0., 100.) {
+            Some(rec) => {
+                assert!((rec.point - v3(0.5, 2., 0.)).norm() < 1e-9);
+                assert!(rec.front_face);
+                assert!((rec.normal - v3(0., 1., 0.)).norm() < 1e-9);
+            }
+            None => panic!("Expected a hit to be recorded"),
+        }
+
+        let bbox = cyl.bounding_box().unwrap();
+        assert_eq!(bbox, Aabb::new(v3(-1., 0., -1.), v3(1., 2., 1.)));
+    }
+
+    #[test]
+    fn test_uncapped_cylinder_is_hollow() {
+        let cyl = unit_cylinder(false);
+
+        // Straight down the axis never touches the tube
+        let ray = Ray {
+            orig: v3(0.5, 5., 0.),
+            dir: v3(0., -1., 0.),
+            time: 0.,
+            wavelength: None,
+        };
+        assert!(cyl.hit(&ray, 0., 100.).is_none());
+
+        // Entering through the open top, the inside of the wall is hit
+        let slanted = Ray {
+            orig: v3(0., 2.5, 0.),
+            dir: v3(1., -1., 0.),
+            time: 0.,
+            wavelength: None,
+        };
+        match cyl.hit(&slanted, 0., 100.) {
+            Some(rec) => {
+                assert!((rec.point - v3(1., 1.5, 0.)).norm() < 1e-9);
+                assert!(!rec.front_face);
+            }
+            None => panic!("Expected a hit to be recorded"),
+        }
+    }
+
+    #[test]
+    fn test_hit_disk() {
+        let disk = Disk {
+            center: v3(0., 0., -2.),
+            normal: v3(0., 0., 2.),
+            radius: 1.,
+            material: Arc::new(Metal {
+                albedo: Color(v3(1., 1., 1.)),
+                fuzz: 0.1,
+            }),
+        };
+
+        let central = Ray {
+            orig: v3(0., 0., 0.),
+            dir: v3(0., 0., -1.),
+            time: 0.,
+            wavelength: None,
+        };
+        match disk.hit(&central, 0., 100.) {
+            Some(rec) => {
+                assert!((rec.point - v3(0., 0., -2.)).norm() < 1e-9);
+                assert!(rec.front_face);
+                assert_eq!(rec.normal, v3(0., 0., 1.));
+            }
+            None => panic!("Expected a hit to be recorded"),
+        }
+
+        // Crosses the plane just outside the radius
+        let off_center = Ray {
+            orig: v3(0.8, 0.8, 0.),
+            dir: v3(0., 0., -1.),
+            time: 0.,
+            wavelength: None,
+        };
+        assert!(disk.hit(&off_center, 0., 100.).is_none());
+    }
+
+    #[test]
+    fn test_sphere_light_sampling() {
+        let sphere = Sphere {
+            center: v3(0., 2., 0.),
+            radius: 1.,
+            material: Arc::new(Metal::new(Color(v3(0.5, 0.5, 0.5)), 0.)),
+        };
+        let origin = v3(0., 0., 0.);
+        let mut rng = rand::rngs::StdRng::seed_from_u64(8);
+
+        for _ in 0..1000 {
+            let dir = sphere.random(&origin, &mut rng);
+            let ray = Ray {
+                orig: origin,
+                dir,
+                time: 0.,
+                wavelength: None,
+            };
+            assert!(sphere.hit(&ray, 0.001, f64::INFINITY).is_some());
+            assert!(sphere.pdf_value(&origin, &dir) > 0.);
+        }
+
+        // Sphere sits above the origin, so the upper hemisphere holds all of it
+        let n = 200_000;
+        let integral: f64 = (0..n)
+            .map(|_| {
+                let mut dir = random_unit_vec(&mut rng);
+                dir.y = dir.y.abs();
+                2. * PI * sphere.pdf_value(&origin, &dir)
+            })
+            .sum::<f64>()
+            / n as f64;
+        assert!((integral - 1.).abs() < 0.05, "{}", integral);
+
+        // From the centre, and anywhere inside, every direction is sampled
+        for &inside in &[sphere.center, v3(0., 2.5, 0.)] {
+            for _ in 0..100 {
+                let dir = sphere.random(&inside, &mut rng);
+                assert!((dir.norm() - 1.).abs() < 1e-12);
+                assert!((sphere.pdf_value(&inside, &dir) - 1. / (4. * PI)).abs() < 1e-12);
+            }
+        }
     }
 }
@@ -0,0 +1,247 @@
+use std::sync::Arc;
+
+use crate::{
+    aabb::{surrounding_box, Aabb},
+    geometry::{v3, Ray, V3},
+    hittable::{HitRecord, Hittable},
+};
+
+// Cells per object on average; a few keeps the lists short without leaving
+// most cells empty
+const CELLS_PER_OBJECT: f64 = 3.;
+const MAX_RESOLUTION: usize = 128;
+
+/// Uniform grid over a set of hittables, an alternative to `Bvh` that suits
+/// many similar-sized objects spread evenly through the scene.
+///
+/// Each cell lists the objects whose boxes overlap it, and rays step from
+/// cell to cell in order, so the search can stop at the first cell holding a
+/// hit. Unbounded objects are kept aside and tested against every ray.
+pub struct Grid {
+    bounds: Aabb,
+    resolution: [usize; 3],
+    cell_size: V3,
+    objects: Vec<Arc<dyn Hittable>>,
+    // Indices into `objects`, x varying fastest
+    cells: Vec<Vec<usize>>,
+    unbounded: Vec<Arc<dyn Hittable>>,
+}
+
+impl Grid {
+    pub fn new(objects: Vec<Arc<dyn Hittable>>) -> Self {
+        let mut bounded = Vec::with_capacity(objects.len());
+        let mut boxes = Vec::with_capacity(objects.len());
+        let mut unbounded = Vec::new();
+        for object in objects {
+            match object.bounding_box() {
+                Some(bbox) => {
+                    bounded.push(object);
+                    boxes.push(bbox);
+                }
+                None => unbounded.push(object),
+            }
+        }
+
+        let bounds = boxes
+            .iter()
+            .copied()
+            .reduce(|a, b| surrounding_box(&a, &b))
+            .unwrap_or_else(|| Aabb::new(V3::zeros(), V3::zeros()));
+        let size = bounds.max - bounds.min;
+
+        // Roughly cubic cells, sized so there are about `CELLS_PER_OBJECT`
+        // cells for each object
+        let volume = size.x.max(1e-9) * size.y.max(1e-9) * size.z.max(1e-9);
+        let per_unit = (CELLS_PER_OBJECT * bounded.len() as f64 / volume).cbrt();
+        let mut resolution = [1; 3];
+        for (axis, res) in resolution.iter_mut().enumerate() {
+            *res = ((size[axis] * per_unit).round() as usize).clamp(1, MAX_RESOLUTION);
+        }
+        let cell_size = v3(
+            size.x / resolution[0] as f64,
+            size.y / resolution[1] as f64,
+            size.z / resolution[2] as f64,
+        );
+
+        let mut grid = Grid {
+            bounds,
+            resolution,
+            cell_size,
+            objects: bounded,
+            cells: vec![Vec::new(); resolution.iter().product()],
+            unbounded,
+        };
+        for (index, bbox) in boxes.iter().enumerate() {
+            let lo = grid.cell_of(&bbox.min);
+            let hi = grid.cell_of(&bbox.max);
+            for z in lo[2]..=hi[2] {
+                for y in lo[1]..=hi[1] {
+                    for x in lo[0]..=hi[0] {
+                        let cell = grid.cell_index([x, y, z]);
+                        grid.cells[cell].push(index);
+                    }
+                }
+            }
+        }
+        grid
+    }
+
+    /// Cell holding `p`, clamped onto the grid.
+    fn cell_of(&self, p: &V3) -> [usize; 3] {
+        let mut cell = [0; 3];
+        for (axis, c) in cell.iter_mut().enumerate() {
+            let offset = (p[axis] - self.bounds.min[axis]) / self.cell_size[axis];
+            // Flat axes have a single cell, and NaN casts to 0
+            *c = (offset.max(0.) as usize).min(self.resolution[axis] - 1);
+        }
+        cell
+    }
+
+    fn cell_index(&self, [x, y, z]: [usize; 3]) -> usize {
+        (z * self.resolution[1] + y) * self.resolution[0] + x
+    }
+
+    /// Parameter range over which `ray` is inside the grid's bounds.
+    fn clip(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+        let (mut t0, mut t1) = (t_min, t_max);
+        for axis in 0..3 {
+            if ray.dir[axis] == 0. {
+                if ray.orig[axis] < self.bounds.min[axis] || ray.orig[axis] > self.bounds.max[axis]
+                {
+                    return None;
+                }
+                continue;
+            }
+            let inv_d = 1. / ray.dir[axis];
+            let mut near = (self.bounds.min[axis] - ray.orig[axis]) * inv_d;
+            let mut far = (self.bounds.max[axis] - ray.orig[axis]) * inv_d;
+            if inv_d < 0. {
+                std::mem::swap(&mut near, &mut far);
+            }
+            t0 = t0.max(near);
+            t1 = t1.min(far);
+            if t1 < t0 {
+                return None;
+            }
+        }
+        Some((t0, t1))
+    }
+
+    /// Closest hit among the bounded objects, walking the cells along `ray`
+    /// with a 3D DDA.
+    fn hit_cells(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let (t_enter, t_exit) = self.clip(ray, t_min, t_max)?;
+        let mut cell = self.cell_of(&ray.at(t_enter));
+
+        // Per axis: which way to step, the `t` of the next cell boundary and
+        // the `t` it takes to cross a whole cell
+        let mut step = [0isize; 3];
+        let mut t_next = [f64::INFINITY; 3];
+        let mut t_delta = [f64::INFINITY; 3];
+        for axis in 0..3 {
+            let d = ray.dir[axis];
+            if d == 0. {
+                continue;
+            }
+            let forward = d > 0.;
+            step[axis] = if forward { 1 } else { -1 };
+            let boundary = self.bounds.min[axis]
+                + (cell[axis] + forward as usize) as f64 * self.cell_size[axis];
+            t_next[axis] = (boundary - ray.orig[axis]) / d;
+            t_delta[axis] = self.cell_size[axis] / d.abs();
+        }
+
+        let mut closest: Option<HitRecord> = None;
+        loop {
+            let t_max = closest.as_ref().map_or(t_max, |rec| rec.t);
+            for &index in &self.cells[self.cell_index(cell)] {
+                // An object spanning several cells may be found again; the
+                // shrinking `t_max` keeps only the closest hit
+                if let Some(rec) = self.objects[index].hit(ray, t_min, t_max) {
+                    if closest.as_ref().is_none_or(|c| rec.t < c.t) {
+                        closest = Some(rec);
+                    }
+                }
+            }
+
+            // Hits beyond this cell might still be beaten by objects in later
+            // cells, so only stop once the closest lies inside it
+            let axis = (0..3)
+                .min_by(|&a, &b| t_next[a].partial_cmp(&t_next[b]).unwrap())
+                .unwrap();
+            let cell_end = t_next[axis].min(t_exit);
+            if closest.as_ref().is_some_and(|rec| rec.t <= cell_end) || t_next[axis] > t_exit {
+                return closest;
+            }
+
+            let next = cell[axis] as isize + step[axis];
+            if next < 0 || next >= self.resolution[axis] as isize {
+                return closest;
+            }
+            cell[axis] = next as usize;
+            t_next[axis] += t_delta[axis];
+        }
+    }
+}
+
+impl Hittable for Grid {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut any_hit = self.hit_cells(ray, t_min, t_max);
+        let mut closest_so_far = any_hit.as_ref().map_or(t_max, |rec| rec.t);
+
+        for hittable in self.unbounded.iter() {
+            if let Some(rec) = hittable.hit(ray, t_min, closest_so_far) {
+                closest_so_far = rec.t;
+                any_hit = Some(rec);
+            }
+        }
+        any_hit
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        if self.unbounded.is_empty() && !self.objects.is_empty() {
+            Some(self.bounds)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{rand_vec_bounded, random_unit_vec};
+    use crate::hittable::HittableList;
+    use crate::scene::make_world;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn test_grid_matches_list() {
+        let mut rng = StdRng::seed_from_u64(12);
+        let world = make_world(&mut rng);
+        let grid = Grid::new(world.list.clone());
+        let list = HittableList {
+            list: world.list.clone(),
+        };
+
+        for k in 0..1000 {
+            // Rays from inside and outside the scene, some aimed at the ground
+            let orig = rand_vec_bounded(&mut rng, -15., 15.) + v3(0., 15., 0.) * (k % 2) as f64;
+            let ray = Ray {
+                orig,
+                dir: random_unit_vec(&mut rng),
+                time: 0.,
+                wavelength: None,
+            };
+            let expected = list.hit(&ray, 0.001, f64::INFINITY);
+            let actual = grid.hit(&ray, 0.001, f64::INFINITY);
+            match (expected, actual) {
+                (Some(e), Some(a)) => {
+                    assert_eq!(e.t, a.t);
+                    assert_eq!(e.point, a.point);
+                }
+                (e, a) => assert_eq!(e.is_some(), a.is_some(), "ray {}", k),
+            }
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use std::io::{Error, ErrorKind, Read, Result};
+
+use crate::color::Color;
+use crate::geometry::v3;
+
+/// Decode a Radiance `.hdr` (RGBE) image into linear colors, top-to-bottom.
+///
+/// Handles flat and run-length encoded scanlines in the standard `-Y h +X w`
+/// orientation; old-style RLE and other orientations are rejected.
+pub fn read_rgbe<R: Read>(r: &mut R) -> Result<(usize, usize, Vec<Color>)> {
+    let mut bytes = Vec::new();
+    r.read_to_end(&mut bytes)?;
+
+    let mut pos = 0;
+    let mut next_line = || {
+        let len = bytes[pos..]
+            .iter()
+            .position(|&b| b == b'\n')
+            .ok_or_else(|| invalid("truncated header"))?;
+        let line = String::from_utf8_lossy(&bytes[pos..pos + len]).into_owned();
+        pos += len + 1;
+        Ok::<_, Error>(line)
+    };
+
+    if !next_line()?.starts_with("#?") {
+        return Err(invalid("missing Radiance signature"));
+    }
+    loop {
+        let line = next_line()?;
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format.trim() != "32-bit_rle_rgbe" {
+                return Err(invalid("unsupported pixel format"));
+            }
+        }
+    }
+
+    let resolution = next_line()?;
+    let (height, width) = match resolution.split_whitespace().collect::<Vec<_>>()[..] {
+        ["-Y", h, "+X", w] => (
+            h.parse::<usize>().map_err(|_| invalid("bad height"))?,
+            w.parse::<usize>().map_err(|_| invalid("bad width"))?,
+        ),
+        _ => return Err(invalid("unsupported resolution line")),
+    };
+
+    // Every scanline takes at least its RLE header and a two-byte run per
+    // 128 pixels of each channel, or four bytes a pixel flat. Checking that
+    // much data is there bounds the buffers before they're allocated
+    let mut data = &bytes[pos..];
+    let min_line = if (8..0x8000).contains(&width) {
+        Some(4 + 8 * width.div_ceil(128))
+    } else {
+        width.checked_mul(4)
+    };
+    let count = min_line
+        .and_then(|line| line.checked_mul(height))
+        .filter(|&size| size <= data.len())
+        .and_then(|_| width.checked_mul(height))
+        .ok_or_else(|| invalid("truncated pixel data"))?;
+
+    let mut pixels = Vec::with_capacity(count);
+    let mut scanline = vec![[0u8; 4]; width];
+    for _ in 0..height {
+        data = read_scanline(data, &mut scanline)?;
+        pixels.extend(scanline.iter().map(|&rgbe| decode(rgbe)));
+    }
+
+    Ok((width, height, pixels))
+}
+
+/// Fill `scanline` from the front of `data`, returning what is left.
+fn read_scanline<'a>(data: &'a [u8], scanline: &mut [[u8; 4]]) -> Result<&'a [u8]> {
+    let width = scanline.len();
+    let take = |data: &'a [u8], n: usize| {
+        if data.len() < n {
+            Err(invalid("truncated pixel data"))
+        } else {
+            Ok(data.split_at(n))
+        }
+    };
+
+    let is_rle = (8..0x8000).contains(&width) && data.len() >= 4 && data[..2] == [2, 2];
+    if !is_rle {
+        let (flat, rest) = take(data, width * 4)?;
+        for (px, rgbe) in scanline.iter_mut().zip(flat.chunks(4)) {
+            px.copy_from_slice(rgbe);
+        }
+        return Ok(rest);
+    }
+
+    if ((data[2] as usize) << 8 | data[3] as usize) != width {
+        return Err(invalid("scanline width mismatch"));
+    }
+    let mut data = &data[4..];
+
+    // Each channel is stored separately as a mix of runs and literal spans
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let (count, rest) = take(data, 1)?;
+            let count = count[0] as usize;
+            let (n, literal) = if count > 128 {
+                (count - 128, false)
+            } else {
+                (count, true)
+            };
+            if n == 0 || x + n > width {
+                return Err(invalid("bad run length"));
+            }
+
+            let (values, rest) = take(rest, if literal { n } else { 1 })?;
+            for (i, px) in scanline[x..x + n].iter_mut().enumerate() {
+                px[channel] = values[if literal { i } else { 0 }];
+            }
+            x += n;
+            data = rest;
+        }
+    }
+
+    Ok(data)
+}
+
+fn decode([r, g, b, e]: [u8; 4]) -> Color {
+    if e == 0 {
+        return Color::black();
+    }
+    // Mantissas are fractions of 256 sharing the exponent `e - 128`
+    let scale = 2f64.powi(e as i32 - 136);
+    Color(v3(r as f64, g as f64, b as f64) * scale)
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_flat_and_rle_match() {
+        let mut flat = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n".to_vec();
+        let mut rle = flat.clone();
+        for _ in 0..16 {
+            flat.extend(&[32, 64, 128, 130]);
+        }
+        for _ in 0..2 {
+            rle.extend(&[2, 2, 0, 8]);
+            // One run per channel, then a literal span for the exponent
+            rle.extend(&[128 + 8, 32, 128 + 8, 64, 128 + 8, 128, 8]);
+            rle.extend(&[130; 8]);
+        }
+
+        for bytes in &[flat, rle] {
+            let (width, height, pixels) = read_rgbe(&mut &bytes[..]).unwrap();
+            assert_eq!((width, height, pixels.len()), (8, 2, 16));
+            assert!(pixels.iter().all(|c| c.0 == v3(0.5, 1., 2.)));
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_header() {
+        assert!(read_rgbe(&mut &b"P3\n1 1\n255\n"[..]).is_err());
+        assert!(read_rgbe(&mut &b"#?RADIANCE\n\n+Y 1 +X 1\n"[..]).is_err());
+        assert!(read_rgbe(&mut &b"#?RADIANCE\n\n-Y 1 +X 1\n\x01"[..]).is_err());
+        for size in &[
+            format!("-Y 2 +X {}", usize::MAX / 2),
+            "-Y 100000 +X 100000".into(),
+        ] {
+            // Tiny files claiming huge images fail before allocating them
+            let header = format!("#?RADIANCE\n\n{}\n\x02\x02", size);
+            let err = read_rgbe(&mut header.as_bytes()).err().unwrap();
+            assert_eq!(err.kind(), ErrorKind::InvalidData);
+        }
+    }
+}
@@ -1,14 +1,40 @@
+use std::fmt;
+use std::sync::Arc;
+
+use rand::{Rng, RngCore};
+
 use crate::{
+    aabb::{surrounding_box, Aabb},
     geometry::{Point, Ray, V3},
     material::Material,
 };
 
+/// Object ID AOV value where the primary ray escaped.
+pub const BACKGROUND_ID: u32 = u32::MAX;
+/// Object ID AOV value for hits on objects without an `ObjectId`.
+pub const UNTAGGED_ID: u32 = u32::MAX - 1;
+
+#[derive(Clone, Default)]
 pub struct HittableList {
-    pub list: Vec<Box<dyn Hittable>>,
+    pub list: Vec<Arc<dyn Hittable>>,
 }
 
 pub trait Hittable: Send + Sync {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
+
+    /// Box enclosing the object, or `None` if it is unbounded.
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    /// Solid-angle density with which `random` picks `dir` from `origin`.
+    /// Objects that can't be sampled as lights leave this at zero.
+    fn pdf_value(&self, _origin: &Point, _dir: &V3) -> f64 {
+        0.
+    }
+
+    /// A direction from `origin` toward a random point on the object.
+    fn random(&self, _origin: &Point, _rng: &mut dyn RngCore) -> V3 {
+        V3::new(1., 0., 0.)
+    }
 }
 #[derive(Clone)]
 pub struct HitRecord<'mat> {
@@ -16,7 +42,11 @@ pub struct HitRecord<'mat> {
     pub normal: V3,
     pub material: &'mat dyn Material,
     pub t: f64,
+    pub u: f64,
+    pub v: f64,
     pub front_face: bool,
+    /// Set by the innermost `ObjectId` wrapper around the hit primitive.
+    pub object_id: Option<u32>,
 }
 
 impl HittableList {
@@ -24,13 +54,19 @@ impl HittableList {
         HittableList { list: Vec::new() }
     }
 
-    pub fn add(&mut self, item: Box<dyn Hittable + Send + Sync>) {
+    pub fn add(&mut self, item: Arc<dyn Hittable>) {
         self.list.push(item);
     }
 }
 
+impl fmt::Debug for HittableList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "HittableList({} objects)", self.list.len())
+    }
+}
+
 impl Hittable for HittableList {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let mut any_hit: Option<HitRecord> = None;
         let mut closest_so_far = t_max;
 
@@ -42,6 +78,43 @@ impl Hittable for HittableList {
         }
         any_hit
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let mut boxes = self.list.iter().map(|h| h.bounding_box());
+        let first = boxes.next()??;
+        boxes.try_fold(first, |acc, b| Some(surrounding_box(&acc, &b?)))
+    }
+
+    /// Each member is picked with equal odds.
+    fn pdf_value(&self, origin: &Point, dir: &V3) -> f64 {
+        let sum: f64 = self.list.iter().map(|h| h.pdf_value(origin, dir)).sum();
+        sum / self.list.len() as f64
+    }
+
+    fn random(&self, origin: &Point, rng: &mut dyn RngCore) -> V3 {
+        let index = rng.gen_range(0..self.list.len());
+        self.list[index].random(origin, rng)
+    }
+}
+
+/// Lets a world picked at run time, such as one of several accelerators, be
+/// passed wherever a `Hittable` is expected.
+impl Hittable for Box<dyn Hittable> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        (**self).hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        (**self).bounding_box()
+    }
+
+    fn pdf_value(&self, origin: &Point, dir: &V3) -> f64 {
+        (**self).pdf_value(origin, dir)
+    }
+
+    fn random(&self, origin: &Point, rng: &mut dyn RngCore) -> V3 {
+        (**self).random(origin, rng)
+    }
 }
 
 impl<'mat> HitRecord<'mat> {
@@ -51,6 +124,7 @@ impl<'mat> HitRecord<'mat> {
         point: Point,
         material: &'mat dyn Material,
         t: f64,
+        (u, v): (f64, f64),
     ) -> Self {
         let (normal, front_face) = Self::get_face_normal_from_ray(ray, outward_normal);
         HitRecord {
@@ -58,7 +132,10 @@ impl<'mat> HitRecord<'mat> {
             normal,
             material,
             t,
+            u,
+            v,
             front_face,
+            object_id: None,
         }
     }
 
@@ -72,3 +149,50 @@ impl<'mat> HitRecord<'mat> {
         (normal, front_face)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::geometry::{v3, Sphere};
+    use crate::material::Lambertian;
+
+    #[test]
+    fn test_clone_shares_objects() {
+        let mut world = HittableList::new();
+        for k in 0..3 {
+            world.add(Arc::new(Sphere {
+                center: v3(k as f64, 0., -2. - k as f64),
+                radius: 0.4,
+                material: Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5)))),
+            }));
+        }
+        let shared = world.clone();
+
+        assert_eq!(shared.list.len(), world.list.len());
+        assert!(shared
+            .list
+            .iter()
+            .zip(&world.list)
+            .all(|(a, b)| Arc::ptr_eq(a, b)));
+
+        // Usable from another thread while the original stays here
+        let ray = Ray {
+            orig: v3(0., 0., 0.),
+            dir: v3(0., 0., -1.),
+            time: 0.,
+            wavelength: None,
+        };
+        let there = std::thread::spawn(move || shared.hit(&ray, 0.001, f64::INFINITY).map(|r| r.t))
+            .join()
+            .unwrap();
+        let ray = Ray {
+            orig: v3(0., 0., 0.),
+            dir: v3(0., 0., -1.),
+            time: 0.,
+            wavelength: None,
+        };
+        assert_eq!(there, world.hit(&ray, 0.001, f64::INFINITY).map(|r| r.t));
+        assert!(there.is_some());
+    }
+}
@@ -1,30 +1,146 @@
 use ndarray::prelude::*;
-use std::io::Result;
-use std::{fs::File, io::Write};
+use std::io::{Error, ErrorKind, Read, Result, Write};
 
-use crate::color::Color;
+use crate::color::{Color, ToneMap};
+use crate::exr;
+use crate::geometry::v3;
+use crate::hittable::BACKGROUND_ID;
+use crate::png;
 
 #[derive(Clone)]
 pub struct Image {
     pub img: Array2<Color>, //pub img: Vec<Vec<Color>>
 }
 
+const ACCUMULATOR_MAGIC: &[u8; 8] = b"TRACCUM2";
+
+/// Average of `images`, each counted as one pass.
 pub fn merge_samples(images: Vec<Image>) -> Image {
-    let s = images.len();
-    let ratio = 1.0 / (s as f64);
-    let w = images[0].width();
-    let h = images[0].height();
+    let mut acc = Accumulator::new(images[0].width(), images[0].height());
+    for image in &images {
+        acc.add_sample(image);
+    }
+    acc.current()
+}
 
-    let mut new = Image::new(w, h);
+/// Running sum of rendered passes and how many samples per pixel went into
+/// it, so an image can be refined a pass at a time.
+#[derive(Clone)]
+pub struct Accumulator {
+    sum: Array2<Color>,
+    samples: u32,
+    seed: u64,
+}
 
-    new.img = images
-        .iter()
-        .map(|img| &img.img)
-        .fold(Array::from_elem((w, h), Color::black()), |acc, i| acc + i);
+impl Accumulator {
+    pub fn new(w: usize, h: usize) -> Self {
+        Accumulator {
+            sum: Array::from_elem((w, h), Color::black()),
+            samples: 0,
+            seed: 0,
+        }
+    }
 
-    new.img.iter_mut().for_each(|c| c.mut_const_mul(ratio));
+    /// Record the seed the passes are rendered with, so a resumed render can
+    /// check it's adding to the same scene.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    /// Add a pass rendered with one sample per pixel.
+    pub fn add_sample(&mut self, image: &Image) {
+        self.add_samples(image, 1);
+    }
+
+    /// Add a pass that already averages `samples` samples per pixel.
+    pub fn add_samples(&mut self, image: &Image, samples: u32) {
+        assert_eq!(self.sum.dim(), image.img.dim());
+        let weight = samples as f64;
+        self.sum
+            .zip_mut_with(&image.img, |s, &c| *s = *s + c * weight);
+        self.samples += samples;
+    }
+
+    pub fn samples(&self) -> u32 {
+        self.samples
+    }
+
+    /// Width and height in pixels.
+    pub fn dim(&self) -> (usize, usize) {
+        self.sum.dim()
+    }
+
+    /// The average so far; black before anything is added.
+    pub fn current(&self) -> Image {
+        let n = self.samples.max(1) as f64;
+        Image {
+            img: self.sum.mapv(|c| c / n),
+        }
+    }
+
+    /// Write the raw sums so a render can be resumed later: a magic header,
+    /// then width, height and sample count as little-endian `u32`s, the seed
+    /// as a little-endian `u64` and every channel sum as a little-endian
+    /// `f64`.
+    pub fn save<W: Write>(&self, w: &mut W) -> Result<()> {
+        let (width, height) = self.sum.dim();
+        w.write_all(ACCUMULATOR_MAGIC)?;
+        for n in &[width as u32, height as u32, self.samples] {
+            w.write_all(&n.to_le_bytes())?;
+        }
+        w.write_all(&self.seed.to_le_bytes())?;
+        for c in self.sum.iter() {
+            for channel in c.0.iter() {
+                w.write_all(&channel.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
 
-    new
+    /// Read back a buffer written by `save`.
+    pub fn load<R: Read>(r: &mut R) -> Result<Self> {
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != ACCUMULATOR_MAGIC {
+            return Err(invalid("not an accumulation buffer"));
+        }
+
+        let mut header = [0; 3];
+        for value in header.iter_mut() {
+            let mut bytes = [0; 4];
+            r.read_exact(&mut bytes)?;
+            *value = u32::from_le_bytes(bytes);
+        }
+        let [width, height, samples] = header;
+        let mut seed = [0; 8];
+        r.read_exact(&mut seed)?;
+        let seed = u64::from_le_bytes(seed);
+
+        let count = (width as usize)
+            .checked_mul(height as usize)
+            .ok_or_else(|| invalid("accumulation buffer too large"))?;
+        // Grown as pixels arrive, so a corrupt header can't force a huge
+        // allocation before the data runs out
+        let mut sums = Vec::new();
+        for _ in 0..count {
+            let mut channels = [0.; 3];
+            for channel in channels.iter_mut() {
+                let mut bytes = [0; 8];
+                r.read_exact(&mut bytes)?;
+                *channel = f64::from_le_bytes(bytes);
+            }
+            sums.push(Color(v3(channels[0], channels[1], channels[2])));
+        }
+        let sum = Array2::from_shape_vec((width as usize, height as usize), sums)
+            .map_err(|_| invalid("bad accumulation buffer size"))?;
+
+        Ok(Accumulator { sum, samples, seed })
+    }
 }
 
 impl Image {
@@ -34,6 +150,253 @@ impl Image {
         }
     }
 
+    /// Shrink by an integer `factor`, averaging each `factor` x `factor`
+    /// block. Sizes that don't divide evenly get smaller blocks on the far
+    /// edges.
+    pub fn downsample(&self, factor: usize) -> Image {
+        assert!(factor > 0);
+        let (w, h) = self.img.dim();
+        let mut out = Image::new(w.div_ceil(factor), h.div_ceil(factor));
+
+        for ((i, j), c) in out.img.indexed_iter_mut() {
+            let block = self.img.slice(s![
+                i * factor..(w.min((i + 1) * factor)),
+                j * factor..(h.min((j + 1) * factor))
+            ]);
+            *c = block.iter().cloned().sum::<Color>() / block.len() as f64;
+        }
+        out
+    }
+
+    /// Smooth out noise while keeping edges: each pixel becomes an average of
+    /// its neighbours weighted both by distance (`spatial_sigma`, in pixels)
+    /// and by how close their colors are (`range_sigma`). Both sigmas must be
+    /// positive.
+    pub fn denoise_bilateral(&self, spatial_sigma: f64, range_sigma: f64) -> Image {
+        self.bilateral(&[], spatial_sigma, range_sigma)
+    }
+
+    /// Blur with a Gaussian `sigma` pixels wide, cut off at three sigma and
+    /// applied as two 1D passes. Pixels past the border repeat the edge.
+    pub fn gaussian_blur(&self, sigma: f64) -> Image {
+        if sigma <= 0. {
+            return self.clone();
+        }
+        let radius = (3. * sigma).ceil() as isize;
+        let kernel: Vec<f64> = (-radius..=radius)
+            .map(|x| (-(x * x) as f64 / (2. * sigma * sigma)).exp())
+            .collect();
+        let total: f64 = kernel.iter().sum();
+
+        let pass = |src: &Array2<Color>, axis: usize| {
+            let dim = src.dim();
+            let len = [dim.0, dim.1][axis] as isize;
+            Array2::from_shape_fn(dim, |(i, j)| {
+                let mut sum = Color::black();
+                for (k, weight) in kernel.iter().enumerate() {
+                    let offset = k as isize - radius;
+                    let mut p = [i, j];
+                    p[axis] = ([i, j][axis] as isize + offset).clamp(0, len - 1) as usize;
+                    sum = sum + src[(p[0], p[1])] * *weight;
+                }
+                sum / total
+            })
+        };
+
+        Image {
+            img: pass(&pass(&self.img, 0), 1),
+        }
+    }
+
+    /// Make bright pixels glow: the part of each pixel's light above
+    /// luminance `threshold` is Gaussian blurred by `sigma`, and `intensity`
+    /// of it is moved from the pixel into the blur. Light is only spread,
+    /// never added, so values stay linear and physically plausible. Apply
+    /// before tone mapping. `intensity` must be in `[0, 1]`: more would move
+    /// out more light than the pixel has.
+    pub fn bloom(&self, threshold: f64, sigma: f64, intensity: f64) -> Image {
+        assert!(
+            (0. ..=1.).contains(&intensity),
+            "bloom intensity must be in [0, 1], got {}",
+            intensity
+        );
+        let excess = Image {
+            img: self.img.mapv(|c| {
+                let lum = c.luminance();
+                if lum > threshold {
+                    c * ((lum - threshold) / lum)
+                } else {
+                    Color::black()
+                }
+            }),
+        };
+        let glow = excess.gaussian_blur(sigma);
+
+        Image {
+            img: Array2::from_shape_fn(self.img.dim(), |p| {
+                self.img[p] + (glow.img[p] - excess.img[p]) * intensity
+            }),
+        }
+    }
+
+    /// Lens-like color fringing: red is sampled `1 + strength` times and blue
+    /// `1 - strength` times as far from the image center as green, so the
+    /// center stays sharp and fringes widen toward the edges.
+    pub fn chromatic_aberration(&self, strength: f64) -> Image {
+        let (w, h) = self.img.dim();
+        let (cx, cy) = ((w - 1) as f64 / 2., (h - 1) as f64 / 2.);
+
+        Image {
+            img: Array2::from_shape_fn((w, h), |(i, j)| {
+                let (dx, dy) = (i as f64 - cx, j as f64 - cy);
+                let at = |scale: f64| self.bilinear(cx + dx * scale, cy + dy * scale);
+                Color(v3(
+                    at(1. + strength).0.x,
+                    self.img[(i, j)].0.y,
+                    at(1. - strength).0.z,
+                ))
+            }),
+        }
+    }
+
+    /// Darken toward the corners like lens falloff. With `r` the distance
+    /// from the center as a fraction of the distance to a corner, pixels
+    /// within `radius` are untouched and beyond it are scaled by
+    /// `1 - strength * t²`, where `t` runs from 0 at `radius` to 1 at the
+    /// corners. Apply to linear colors, before gamma.
+    pub fn vignette(&self, strength: f64, radius: f64) -> Image {
+        let (w, h) = self.img.dim();
+        let (cx, cy) = ((w - 1) as f64 / 2., (h - 1) as f64 / 2.);
+        let corner = cx.hypot(cy).max(1e-12);
+
+        Image {
+            img: Array2::from_shape_fn((w, h), |(i, j)| {
+                let r = (i as f64 - cx).hypot(j as f64 - cy) / corner;
+                let t = ((r - radius) / (1. - radius).max(1e-12)).clamp(0., 1.);
+                self.img[(i, j)] * (1. - strength * t * t)
+            }),
+        }
+    }
+
+    /// Color at fractional pixel coordinates, clamped to the image.
+    fn bilinear(&self, x: f64, y: f64) -> Color {
+        let (w, h) = self.img.dim();
+        let x = x.clamp(0., (w - 1) as f64);
+        let y = y.clamp(0., (h - 1) as f64);
+        let (i, j) = (x.floor() as usize, y.floor() as usize);
+        let (i1, j1) = ((i + 1).min(w - 1), (j + 1).min(h - 1));
+        let (fx, fy) = (x - i as f64, y - j as f64);
+
+        let bottom = Color::lerp(self.img[(i, j)], self.img[(i1, j)], fx);
+        let top = Color::lerp(self.img[(i, j1)], self.img[(i1, j1)], fx);
+        Color::lerp(bottom, top, fy)
+    }
+
+    /// Strength of the luminance edge at each pixel, from 3x3 Sobel kernels.
+    /// Pixels past the border repeat the nearest edge pixel, so flat regions
+    /// come out exactly zero.
+    pub fn sobel_magnitude(&self) -> Array2<f64> {
+        let (w, h) = self.img.dim();
+        let lum = self.img.mapv(|c| c.luminance());
+        let at = |i: usize, j: usize, di: isize, dj: isize| {
+            let x = (i as isize + di).clamp(0, w as isize - 1) as usize;
+            let y = (j as isize + dj).clamp(0, h as isize - 1) as usize;
+            lum[(x, y)]
+        };
+
+        Array2::from_shape_fn((w, h), |(i, j)| {
+            let l = |di, dj| at(i, j, di, dj);
+            let gx = (l(1, -1) + 2. * l(1, 0) + l(1, 1)) - (l(-1, -1) + 2. * l(-1, 0) + l(-1, 1));
+            let gy = (l(-1, 1) + 2. * l(0, 1) + l(1, 1)) - (l(-1, -1) + 2. * l(0, -1) + l(1, -1));
+            gx.hypot(gy)
+        })
+    }
+
+    /// Like `denoise_bilateral`, but neighbours also have to agree in the
+    /// albedo and normal AOVs, so noise can't blur across object edges even
+    /// where the colors happen to match.
+    pub fn denoise_guided(
+        &self,
+        albedo: &Image,
+        normal: &Image,
+        spatial_sigma: f64,
+        range_sigma: f64,
+    ) -> Image {
+        self.bilateral(&[albedo, normal], spatial_sigma, range_sigma)
+    }
+
+    fn bilateral(&self, guides: &[&Image], spatial_sigma: f64, range_sigma: f64) -> Image {
+        assert!(guides.iter().all(|g| g.img.dim() == self.img.dim()));
+        assert!(
+            spatial_sigma > 0. && range_sigma > 0.,
+            "bilateral filter needs positive sigmas, got {} and {}",
+            spatial_sigma,
+            range_sigma
+        );
+        let (w, h) = self.img.dim();
+        let radius = (2. * spatial_sigma).ceil() as usize;
+        let spatial = -0.5 / (spatial_sigma * spatial_sigma);
+        let range = -0.5 / (range_sigma * range_sigma);
+
+        let mut out = Image::new(w, h);
+        for ((i, j), c) in out.img.indexed_iter_mut() {
+            let mut sum = Color::black();
+            let mut total = 0.;
+            for y in j.saturating_sub(radius)..(j + radius + 1).min(h) {
+                for x in i.saturating_sub(radius)..(i + radius + 1).min(w) {
+                    let (dx, dy) = (x as f64 - i as f64, y as f64 - j as f64);
+                    let mut distance = (self.img[(x, y)].0 - self.img[(i, j)].0).norm_squared();
+                    for g in guides {
+                        distance += (g.img[(x, y)].0 - g.img[(i, j)].0).norm_squared();
+                    }
+
+                    let weight = ((dx * dx + dy * dy) * spatial + distance * range).exp();
+                    sum = sum + self.img[(x, y)] * weight;
+                    total += weight;
+                }
+            }
+            // The centre pixel always has weight 1, so `total` is never zero
+            *c = sum / total;
+        }
+        out
+    }
+
+    /// Grayscale view of a depth buffer: the nearest hit is white, fading to
+    /// black at the farthest, with misses black too.
+    pub fn from_depth(depth: &Array2<f64>) -> Self {
+        let finite = depth.iter().cloned().filter(|d| d.is_finite());
+        let near = finite.clone().fold(f64::INFINITY, f64::min);
+        let far = finite.fold(f64::NEG_INFINITY, f64::max);
+        let range = (far - near).max(1e-12);
+
+        Image {
+            img: depth.mapv(|d| {
+                let gray = if d.is_finite() {
+                    1. - 0.9 * (d - near) / range
+                } else {
+                    0.
+                };
+                Color(v3(gray, gray, gray))
+            }),
+        }
+    }
+
+    /// A distinct flat color for each object ID, black for the background.
+    pub fn from_ids(ids: &Array2<u32>) -> Self {
+        Image {
+            img: ids.mapv(|id| {
+                if id == BACKGROUND_ID {
+                    return Color::black();
+                }
+                // Spread neighbouring IDs far apart in hue by the golden ratio
+                let hue = (id as f64 * 0.618_033_988_75).fract();
+                let channel =
+                    |offset: f64| 0.5 + 0.5 * (2. * std::f64::consts::PI * (hue + offset)).cos();
+                Color(v3(channel(0.), channel(1. / 3.), channel(2. / 3.)))
+            }),
+        }
+    }
+
     pub fn width(&self) -> usize {
         self.img.shape()[0]
     }
@@ -42,28 +405,587 @@ impl Image {
         self.img.shape()[1]
     }
 
-    pub fn to_ppm_list(&self) -> Vec<String> {
+    pub fn to_ppm_list(&self, tone: ToneMap, gamma: f64) -> Vec<String> {
         //self.img.iter()
         //    .map(|c| c.ppm()).collect()
         let mut list = Vec::with_capacity(self.height() * self.width());
         for j in (0..self.height()).rev() {
             for i in 0..self.width() {
-                list.push(self.img[(i, j)].ppm());
+                list.push(tone.apply(self.img[(i, j)]).ppm_gamma(gamma));
             }
         }
         list
     }
 
-    pub fn write_ppm(&self, file: &mut File) -> Result<()> {
+    pub fn write_ppm<W: Write>(&self, w: &mut W, tone: ToneMap, gamma: f64) -> Result<()> {
         let mut rows: Vec<String> = Vec::new();
         rows.push("P3".to_string());
         rows.push(format!("{} {}", self.width(), self.height()));
         rows.push("255".to_string());
-        rows.extend(self.to_ppm_list());
+        rows.extend(self.to_ppm_list(tone, gamma));
 
         let contents = rows.join("\n");
-        file.write_all(contents.as_bytes())?;
+        w.write_all(contents.as_bytes())?;
 
         Ok(())
     }
+
+    pub fn write_ppm_binary<W: Write>(&self, w: &mut W, tone: ToneMap, gamma: f64) -> Result<()> {
+        write!(w, "P6\n{} {}\n255\n", self.width(), self.height())?;
+        w.write_all(&self.to_rgb8_rows(tone, gamma))?;
+
+        Ok(())
+    }
+
+    /// Tone-mapped, gamma-corrected RGB bytes, top row first.
+    pub fn to_rgb8_rows(&self, tone: ToneMap, gamma: f64) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(3 * self.height() * self.width());
+        for j in (0..self.height()).rev() {
+            for i in 0..self.width() {
+                bytes.extend(&tone.apply(self.img[(i, j)]).to_rgb8_gamma(gamma));
+            }
+        }
+        bytes
+    }
+
+    /// Read a P3 or P6 PPM, undoing the gamma 2 encoding `write_ppm` applies.
+    pub fn read_ppm<R: Read>(r: &mut R) -> Result<Image> {
+        let mut bytes = Vec::new();
+        r.read_to_end(&mut bytes)?;
+
+        let mut pos = 0;
+        let magic = ppm_token(&bytes, &mut pos)?;
+        let mut header = [0usize; 3];
+        for value in header.iter_mut() {
+            *value = ppm_token(&bytes, &mut pos)?
+                .parse()
+                .map_err(|_| invalid("bad ppm header"))?;
+        }
+        let [width, height, maxval] = header;
+        if maxval == 0 || maxval > 255 {
+            return Err(invalid("ppm: only 8-bit images are supported"));
+        }
+
+        let count = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(3))
+            .ok_or_else(|| invalid("ppm: image too large"))?;
+        let values: Vec<usize> = match magic.as_str() {
+            "P3" => (0..count)
+                .map(|_| {
+                    ppm_token(&bytes, &mut pos)?
+                        .parse()
+                        .map_err(|_| invalid("bad ppm sample"))
+                })
+                .collect::<Result<_>>()?,
+            "P6" => {
+                // A single whitespace byte separates the header from the samples
+                let data = bytes
+                    .get(pos + 1..)
+                    .and_then(|rest| rest.get(..count))
+                    .ok_or_else(|| invalid("ppm: truncated image data"))?;
+                data.iter().map(|&b| b as usize).collect()
+            }
+            _ => return Err(invalid("not a ppm")),
+        };
+
+        // Take the middle of each quantization step, then square to linearize
+        let linear = |v: usize| {
+            let c = (v.min(maxval) as f64 + 0.5) / (maxval + 1) as f64;
+            c * c
+        };
+
+        let mut image = Image::new(width, height);
+        for (k, rgb) in values.chunks(3).enumerate() {
+            let (i, row) = (k % width, k / width);
+            image.img[(i, height - 1 - row)] =
+                Color(v3(linear(rgb[0]), linear(rgb[1]), linear(rgb[2])));
+        }
+        Ok(image)
+    }
+
+    /// Mean squared error against `other` over every channel, and a grayscale
+    /// map of where they differ, scaled so the largest difference is white.
+    pub fn diff(&self, other: &Image) -> Result<(f64, Image)> {
+        if self.img.dim() != other.img.dim() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "can't diff images of different sizes",
+            ));
+        }
+
+        let errors = ndarray::Zip::from(&self.img)
+            .and(&other.img)
+            .map_collect(|a, b| (a.0 - b.0).norm_squared());
+        let mse = errors.sum() / (3 * errors.len().max(1)) as f64;
+
+        let max = errors.fold(0., |m: f64, &e| m.max(e));
+        let scale = if max > 0. { 1. / max.sqrt() } else { 0. };
+        let img = errors.mapv(|e| {
+            let gray = e.sqrt() * scale;
+            Color(v3(gray, gray, gray))
+        });
+
+        Ok((mse, Image { img }))
+    }
+
+    pub fn write_png<W: Write>(&self, w: &mut W, tone: ToneMap, gamma: f64) -> Result<()> {
+        png::write_rgb8(
+            w,
+            self.width(),
+            self.height(),
+            &self.to_rgb8_rows(tone, gamma),
+        )
+    }
+
+    /// Write linear 32-bit float OpenEXR, with no tone mapping, gamma or
+    /// clamping, so values above 1 survive for compositing.
+    pub fn write_exr<W: Write>(&self, w: &mut W) -> Result<()> {
+        let mut rows = Vec::with_capacity(3 * self.width() * self.height());
+        for j in (0..self.height()).rev() {
+            for i in 0..self.width() {
+                rows.extend(self.img[(i, j)].0.iter().map(|&c| c as f32));
+            }
+        }
+        exr::write_rgb_f32(w, self.width(), self.height(), &rows)
+    }
+
+    /// Read an image saved by `write_exr`.
+    pub fn read_exr<R: Read>(r: &mut R) -> Result<Image> {
+        let (width, height, rows) = exr::read_rgb_f32(r)?;
+        let mut image = Image::new(width, height);
+        for (k, rgb) in rows.chunks(3).enumerate() {
+            let (i, row) = (k % width, k / width);
+            image.img[(i, height - 1 - row)] =
+                Color(v3(rgb[0] as f64, rgb[1] as f64, rgb[2] as f64));
+        }
+        Ok(image)
+    }
+}
+
+/// Next whitespace-separated header token, skipping `#` comments. Leaves `pos`
+/// on the byte just after the token.
+fn ppm_token(bytes: &[u8], pos: &mut usize) -> Result<String> {
+    loop {
+        match bytes.get(*pos) {
+            Some(b'#') => {
+                while bytes.get(*pos).is_some_and(|&b| b != b'\n') {
+                    *pos += 1;
+                }
+            }
+            Some(b) if b.is_ascii_whitespace() => *pos += 1,
+            Some(_) => break,
+            None => return Err(invalid("ppm: unexpected end of file")),
+        }
+    }
+
+    let start = *pos;
+    while bytes.get(*pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+        *pos += 1;
+    }
+    Ok(String::from_utf8_lossy(&bytes[start..*pos]).into_owned())
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Pull the raw scanlines back out of a PNG written with stored deflate blocks
+    fn png_scanlines(bytes: &[u8]) -> Vec<u8> {
+        let mut idat = Vec::new();
+        let mut pos = 8;
+        while pos < bytes.len() {
+            let len =
+                u32::from_be_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]]);
+            let kind = &bytes[pos + 4..pos + 8];
+            let data = &bytes[pos + 8..pos + 8 + len as usize];
+            if kind == b"IDAT" {
+                idat.extend(data);
+            }
+            pos += 12 + len as usize;
+        }
+
+        let mut raw = Vec::new();
+        let mut pos = 2;
+        loop {
+            let last = idat[pos] & 1 == 1;
+            let len = u16::from_le_bytes([idat[pos + 1], idat[pos + 2]]) as usize;
+            raw.extend(&idat[pos + 5..pos + 5 + len]);
+            pos += 5 + len;
+            if last {
+                break;
+            }
+        }
+        raw
+    }
+
+    #[test]
+    fn test_write_png() {
+        let mut image = Image::new(2, 2);
+        image.img[(0, 1)] = Color(v3(1., 0., 0.));
+        image.img[(1, 0)] = Color(v3(0., 0., 1.));
+
+        let mut buf = Vec::new();
+        image.write_png(&mut buf, ToneMap::None, 2.0).unwrap();
+
+        assert_eq!(
+            &buf[..8],
+            &[0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n']
+        );
+        assert_eq!(&buf[12..16], b"IHDR");
+        assert_eq!(&buf[16..24], &[0, 0, 0, 2, 0, 0, 0, 2]);
+
+        // Top row first, each prefixed by a zero filter byte
+        let expected = vec![
+            0, 255, 0, 0, 0, 0, 0, //
+            0, 0, 0, 0, 0, 0, 255,
+        ];
+        assert_eq!(png_scanlines(&buf), expected);
+    }
+
+    #[test]
+    fn test_accumulator_save_and_resume() {
+        let pass = |k: usize| {
+            let mut image = Image::new(3, 2);
+            for (p, c) in image.img.iter_mut().enumerate() {
+                *c = Color(v3(0.1 * k as f64, 0.3 * p as f64, 1. / (k + p + 1) as f64));
+            }
+            image
+        };
+
+        let mut uninterrupted = Accumulator::new(3, 2);
+        for k in 0..4 {
+            uninterrupted.add_sample(&pass(k));
+        }
+
+        let mut first = Accumulator::new(3, 2).with_seed(42);
+        first.add_sample(&pass(0));
+        first.add_sample(&pass(1));
+        let mut saved = Vec::new();
+        first.save(&mut saved).unwrap();
+
+        let mut resumed = Accumulator::load(&mut &saved[..]).unwrap();
+        assert_eq!((resumed.samples(), resumed.seed()), (2, 42));
+        resumed.add_sample(&pass(2));
+        resumed.add_sample(&pass(3));
+        assert_eq!(resumed.samples(), 4);
+        assert_eq!(
+            resumed.current().img.mapv(|c| c.0),
+            uninterrupted.current().img.mapv(|c| c.0)
+        );
+
+        assert!(Accumulator::load(&mut &saved[..20]).is_err());
+        assert!(Accumulator::load(&mut &b"P6\n3 2\n255\n"[..]).is_err());
+        let mut huge = ACCUMULATOR_MAGIC.to_vec();
+        for value in &[u32::MAX, u32::MAX, 1] {
+            huge.extend(&value.to_le_bytes());
+        }
+        huge.extend(&0u64.to_le_bytes());
+        assert!(Accumulator::load(&mut &huge[..]).is_err());
+    }
+
+    #[test]
+    fn test_downsample() {
+        let mut solid = Image::new(4, 4);
+        solid.img.fill(Color(v3(0.2, 0.4, 0.6)));
+        let small = solid.downsample(2);
+        assert_eq!((small.width(), small.height()), (2, 2));
+        assert!(small.img.iter().all(|c| c.0 == v3(0.2, 0.4, 0.6)));
+
+        // A 3x1 image leaves a lone pixel in the last block
+        let mut ramp = Image::new(3, 1);
+        for (k, c) in ramp.img.iter_mut().enumerate() {
+            *c = Color(v3(k as f64, 0., 0.));
+        }
+        let halved = ramp.downsample(2);
+        assert_eq!((halved.width(), halved.height()), (2, 1));
+        assert_eq!(halved.img[(0, 0)].0.x, 0.5);
+        assert_eq!(halved.img[(1, 0)].0.x, 2.);
+    }
+
+    #[test]
+    #[should_panic(expected = "positive sigmas, got 2 and 0")]
+    fn test_denoise_bilateral_rejects_zero_sigma() {
+        Image::new(4, 4).denoise_bilateral(2., 0.);
+    }
+
+    #[test]
+    fn test_denoise_bilateral() {
+        use rand::{rngs::StdRng, Rng, SeedableRng};
+
+        // Noisy gray on the left half, clean white on the right
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut image = Image::new(16, 8);
+        for ((i, _), c) in image.img.indexed_iter_mut() {
+            let gray = if i < 8 {
+                0.3 + rng.gen_range(-0.05..0.05)
+            } else {
+                1.
+            };
+            *c = Color(v3(gray, gray, gray));
+        }
+        let denoised = image.denoise_bilateral(2., 0.1);
+
+        let variance = |img: &Image| {
+            let values: Vec<f64> = (0..6)
+                .flat_map(|i| (0..8).map(move |j| (i, j)))
+                .map(|p| img.img[p].0.x)
+                .collect();
+            let mean = values.iter().sum::<f64>() / values.len() as f64;
+            values.iter().map(|v| (v - mean).powi(2)).sum::<f64>() / values.len() as f64
+        };
+        assert!(variance(&denoised) < variance(&image) / 4.);
+
+        // Neither side of the edge bleeds into the other
+        for j in 0..8 {
+            assert!((denoised.img[(7, j)].0.x - 0.3).abs() < 0.06);
+            assert!((denoised.img[(8, j)].0.x - 1.).abs() < 1e-6);
+        }
+
+        // A normal guide keeps apart pixels whose colors agree
+        let flat = Image::new(16, 8);
+        let mut normal = Image::new(16, 8);
+        let mut noisy = Image::new(16, 8);
+        for ((i, j), c) in noisy.img.indexed_iter_mut() {
+            *c = Color(v3(0.5 + 0.01 * ((i + j) % 2) as f64, 0.5, 0.5));
+            normal.img[(i, j)] = Color(if i < 8 {
+                v3(1., 0., 0.)
+            } else {
+                v3(0., 1., 0.)
+            });
+        }
+        noisy.img[(7, 4)] = Color(v3(0.9, 0.5, 0.5));
+        let guided = noisy.denoise_guided(&flat, &normal, 2., 1.);
+        let unguided = noisy.denoise_bilateral(2., 1.);
+        assert!(guided.img[(8, 4)].0.x < unguided.img[(8, 4)].0.x);
+    }
+
+    #[test]
+    fn test_write_ppm_header() {
+        let mut buf = Vec::new();
+        Image::new(3, 2)
+            .write_ppm(&mut buf, ToneMap::None, 2.0)
+            .unwrap();
+        let text = String::from_utf8(buf).unwrap();
+
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(&lines[..3], &["P3", "3 2", "255"]);
+        assert_eq!(lines.len(), 3 + 6);
+    }
+
+    #[test]
+    fn test_write_ppm_binary_matches_ascii() {
+        let mut image = Image::new(3, 2);
+        for (k, c) in image.img.iter_mut().enumerate() {
+            *c = Color(v3(0.1 * k as f64, 0.5, 1. - 0.15 * k as f64));
+        }
+
+        let mut binary = Vec::new();
+        image
+            .write_ppm_binary(&mut binary, ToneMap::Reinhard, 2.2)
+            .unwrap();
+
+        let mut ascii = Vec::new();
+        image.write_ppm(&mut ascii, ToneMap::Reinhard, 2.2).unwrap();
+        let ascii = String::from_utf8(ascii).unwrap();
+
+        let header = b"P6\n3 2\n255\n";
+        assert_eq!(&binary[..header.len()], header);
+
+        let ascii_values: Vec<u8> = ascii
+            .split_whitespace()
+            .skip(4)
+            .map(|v| v.parse().unwrap())
+            .collect();
+        assert_eq!(&binary[header.len()..], &ascii_values[..]);
+    }
+
+    #[test]
+    fn test_exr_round_trip() {
+        let mut image = Image::new(3, 2);
+        for (k, c) in image.img.iter_mut().enumerate() {
+            *c = Color(v3(0.25 * k as f64, 0.5, -0.125));
+        }
+        image.img[(1, 0)] = Color(v3(4., 100., 0.));
+
+        let mut bytes = Vec::new();
+        image.write_exr(&mut bytes).unwrap();
+        let read = Image::read_exr(&mut &bytes[..]).unwrap();
+
+        assert_eq!((read.width(), read.height()), (3, 2));
+        assert_eq!(read.img[(1, 0)].0, v3(4., 100., 0.));
+        // Every value here fits exactly in an f32
+        assert_eq!(read.img.mapv(|c| c.0), image.img.mapv(|c| c.0));
+
+        // A data window spanning all of i32, which overflows `x1 - x0 + 1`
+        let window = bytes.windows(6).position(|w| w == b"box2i\0").unwrap() + 10;
+        let mut huge = bytes.clone();
+        huge[window..window + 4].copy_from_slice(&i32::MIN.to_le_bytes());
+        huge[window + 8..window + 12].copy_from_slice(&i32::MAX.to_le_bytes());
+        let err = Image::read_exr(&mut &huge[..]).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+
+        // A window far bigger than the file, with every offset pointing at
+        // the one real scanline, fails instead of allocating the window
+        let n = 40_000;
+        let name = b"screenWindowWidth\0";
+        let table = bytes.windows(name.len()).position(|w| w == name).unwrap() + name.len();
+        // Past the type name, size and value of the last attribute
+        let table = table + b"float\0".len() + 8 + 1;
+        let mut huge = bytes[..table].to_vec();
+        huge[window + 8..window + 12].copy_from_slice(&(n as i32 - 1).to_le_bytes());
+        huge[window + 12..window + 16].copy_from_slice(&(n as i32 - 1).to_le_bytes());
+        let line = (table + 8 * n) as u64;
+        for _ in 0..n {
+            huge.extend(&line.to_le_bytes());
+        }
+        huge.extend(&0i32.to_le_bytes());
+        huge.extend(&(12 * n as i32).to_le_bytes());
+        huge.resize(huge.len() + 12 * n, 0);
+        let err = Image::read_exr(&mut &huge[..]).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_read_ppm_round_trip() {
+        let mut image = Image::new(4, 3);
+        for (k, c) in image.img.iter_mut().enumerate() {
+            *c = Color(v3(0.08 * k as f64, 0.5, 0.95 - 0.07 * k as f64));
+        }
+
+        let mut binary = Vec::new();
+        image
+            .write_ppm_binary(&mut binary, ToneMap::None, 2.0)
+            .unwrap();
+        let from_binary = Image::read_ppm(&mut &binary[..]).unwrap();
+
+        // Same samples as ASCII, with a comment and ragged whitespace
+        let mut ascii = String::from("P3\n# made by hand\n4   3\n\t255\n");
+        for v in image.to_rgb8_rows(ToneMap::None, 2.0) {
+            ascii.push_str(&format!("{}\n", v));
+        }
+        let from_ascii = Image::read_ppm(&mut ascii.as_bytes()).unwrap();
+
+        for read in &[from_binary, from_ascii] {
+            assert_eq!(read.width(), 4);
+            assert_eq!(read.height(), 3);
+            for (a, b) in image.img.iter().zip(read.img.iter()) {
+                // One 8-bit step after gamma, at worst about 2 * sqrt(c) / 256
+                assert!((a.0 - b.0).amax() < 0.01, "{:?} vs {:?}", a, b);
+            }
+        }
+
+        assert!(Image::read_ppm(&mut &b"P5\n1 1\n255\n\0"[..]).is_err());
+        assert!(Image::read_ppm(&mut &b"P6\n2 2\n255\n\0\0"[..]).is_err());
+        let huge = format!("P6\n{} 2\n255\n\0", usize::MAX / 2);
+        let err = Image::read_ppm(&mut huge.as_bytes()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_sobel_edges() {
+        // Black on the left half, white on the right
+        let mut image = Image::new(8, 6);
+        for ((i, _), c) in image.img.indexed_iter_mut() {
+            if i >= 4 {
+                *c = Color(v3(1., 1., 1.));
+            }
+        }
+
+        let edges = image.sobel_magnitude();
+        for ((i, _), &m) in edges.indexed_iter() {
+            if i == 3 || i == 4 {
+                assert!(m > 3., "{}", m);
+            } else {
+                assert_eq!(m, 0.);
+            }
+        }
+    }
+
+    #[test]
+    fn test_bloom_spreads_bright_pixel() {
+        let mut image = Image::new(21, 21);
+        for c in image.img.iter_mut() {
+            *c = Color(v3(0.2, 0.2, 0.2));
+        }
+        image.img[(10, 10)] = Color(v3(50., 50., 50.));
+        let energy = |image: &Image| image.img.iter().map(|c| c.luminance()).sum::<f64>();
+
+        let bloomed = image.bloom(1., 1.5, 0.5);
+        assert!(bloomed.img[(11, 10)].luminance() > 1.);
+        assert!(bloomed.img[(10, 12)].luminance() > 0.5);
+        assert!(bloomed.img[(10, 10)].luminance() < 50.);
+        // Dim pixels far away are untouched
+        assert_eq!(bloomed.img[(0, 0)].0, image.img[(0, 0)].0);
+        assert!((energy(&bloomed) - energy(&image)).abs() < 1e-6 * energy(&image));
+
+        // All of the excess moved out still leaves the threshold behind
+        let full = image.bloom(1., 1.5, 1.);
+        assert!(full.img.iter().all(|c| c.0.min() >= 0.));
+    }
+
+    #[test]
+    #[should_panic(expected = "got 2")]
+    fn test_bloom_rejects_intensity_above_one() {
+        Image::new(4, 4).bloom(1., 1.5, 2.);
+    }
+
+    #[test]
+    fn test_chromatic_aberration() {
+        // Gray ramp from black on the left to white on the right
+        let mut image = Image::new(9, 5);
+        for ((i, _), c) in image.img.indexed_iter_mut() {
+            let gray = i as f64 / 8.;
+            *c = Color(v3(gray, gray, gray));
+        }
+
+        let fringed = image.chromatic_aberration(0.1);
+        assert_eq!(fringed.img[(4, 2)].0, image.img[(4, 2)].0);
+        // Red is sampled further out than blue, so it's brighter on the right
+        let edge = fringed.img[(7, 2)].0;
+        assert!(edge.x > edge.y && edge.y > edge.z, "{:?}", edge);
+        assert!((edge.x - edge.z - 2. * 0.1 * 3. / 8.).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_vignette() {
+        let mut image = Image::new(9, 5);
+        for c in image.img.iter_mut() {
+            *c = Color(v3(0.8, 0.4, 0.2));
+        }
+
+        let vignetted = image.vignette(0.6, 0.5);
+        assert_eq!(vignetted.img[(4, 2)].0, image.img[(4, 2)].0);
+        for &corner in &[(0, 0), (8, 0), (0, 4), (8, 4)] {
+            let expected = image.img[corner].0 * 0.4;
+            assert!((vignetted.img[corner].0 - expected).norm() < 1e-12);
+        }
+        // The middle of the right edge is partway into the falloff
+        let edge = vignetted.img[(8, 2)].0.x / image.img[(8, 2)].0.x;
+        let t = (4. / 4f64.hypot(2.) - 0.5) / 0.5;
+        assert!((edge - (1. - 0.6 * t * t)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn test_diff() {
+        let mut image = Image::new(4, 4);
+        image.img.fill(Color(v3(0.5, 0.5, 0.5)));
+
+        let (mse, map) = image.diff(&image.clone()).unwrap();
+        assert_eq!(mse, 0.);
+        assert!(map.img.iter().all(|c| c.0 == v3(0., 0., 0.)));
+
+        let mut changed = image.clone();
+        changed.img[(1, 2)] = Color(v3(0.5, 0.5, 0.8));
+        let (mse, map) = image.diff(&changed).unwrap();
+        assert!((mse - 0.09 / 48.).abs() < 1e-12);
+        assert_eq!(map.img[(1, 2)].0, v3(1., 1., 1.));
+        assert_eq!(map.img[(0, 0)].0, v3(0., 0., 0.));
+
+        assert!(image.diff(&Image::new(4, 5)).is_err());
+    }
 }
@@ -0,0 +1,420 @@
+use nalgebra::{Matrix4, Point3, Unit};
+use rand::RngCore;
+
+use crate::aabb::{surrounding_box, Aabb};
+use crate::geometry::{deg_to_rad, v3, Point, Ray, V3};
+use crate::hittable::{HitRecord, Hittable};
+
+/// An object moved by `offset`.
+pub struct Translate {
+    pub object: Box<dyn Hittable>,
+    pub offset: V3,
+}
+
+/// An object rotated about the y axis.
+pub struct RotateY {
+    pub object: Box<dyn Hittable>,
+    pub sin_theta: f64,
+    pub cos_theta: f64,
+}
+
+/// An object under an arbitrary affine transform. Build one with
+/// `TransformBuilder`.
+pub struct Transform {
+    pub object: Box<dyn Hittable>,
+    pub object_to_world: Matrix4<f64>,
+    pub world_to_object: Matrix4<f64>,
+}
+
+/// An object turned inside out, so its back face counts as the front. Only
+/// materials that read `front_face` notice, such as `Dielectric`, which
+/// then treats the other side as inside the glass.
+pub struct FlipNormals {
+    pub object: Box<dyn Hittable>,
+}
+
+/// Tags every hit on `object` with `id`, for the object ID AOV. An ID set
+/// by a wrapper further in is kept.
+pub struct ObjectId {
+    pub object: Box<dyn Hittable>,
+    pub id: u32,
+}
+
+/// Composes scales, rotations and translations, applied in the order given.
+pub struct TransformBuilder {
+    matrix: Matrix4<f64>,
+}
+
+/// Box around the eight corners of `bbox` after mapping them through `f`.
+fn transformed_box(bbox: &Aabb, f: impl Fn(&Point) -> Point) -> Aabb {
+    let corner = |k: usize| -> Point {
+        let pick = |axis: usize| {
+            if k >> axis & 1 == 0 {
+                bbox.min[axis]
+            } else {
+                bbox.max[axis]
+            }
+        };
+        f(&v3(pick(0), pick(1), pick(2)))
+    };
+    let first = Aabb::new(corner(0), corner(0));
+    (1..8).fold(first, |acc, k| {
+        surrounding_box(&acc, &Aabb::new(corner(k), corner(k)))
+    })
+}
+
+impl Hittable for Translate {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let moved = Ray {
+            orig: ray.orig - self.offset,
+            dir: ray.dir,
+            time: ray.time,
+            wavelength: ray.wavelength,
+        };
+
+        let mut rec = self.object.hit(&moved, t_min, t_max)?;
+        rec.point += self.offset;
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let bbox = self.object.bounding_box()?;
+        Some(Aabb::new(bbox.min + self.offset, bbox.max + self.offset))
+    }
+}
+
+impl Hittable for FlipNormals {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        // Hit normals always face the ray, so reversing the outward normal
+        // leaves `normal` as it is and only swaps which side is the front
+        let mut rec = self.object.hit(ray, t_min, t_max)?;
+        rec.front_face = !rec.front_face;
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: &Point, dir: &V3) -> f64 {
+        self.object.pdf_value(origin, dir)
+    }
+
+    fn random(&self, origin: &Point, rng: &mut dyn RngCore) -> V3 {
+        self.object.random(origin, rng)
+    }
+}
+
+impl Hittable for ObjectId {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut rec = self.object.hit(ray, t_min, t_max)?;
+        rec.object_id.get_or_insert(self.id);
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: &Point, dir: &V3) -> f64 {
+        self.object.pdf_value(origin, dir)
+    }
+
+    fn random(&self, origin: &Point, rng: &mut dyn RngCore) -> V3 {
+        self.object.random(origin, rng)
+    }
+}
+
+impl RotateY {
+    pub fn new(object: Box<dyn Hittable>, angle: f64) -> Self {
+        let theta = deg_to_rad(angle);
+        RotateY {
+            object,
+            sin_theta: theta.sin(),
+            cos_theta: theta.cos(),
+        }
+    }
+
+    /// Object space to world space.
+    fn rotate(&self, p: &V3) -> V3 {
+        v3(
+            self.cos_theta * p.x + self.sin_theta * p.z,
+            p.y,
+            -self.sin_theta * p.x + self.cos_theta * p.z,
+        )
+    }
+
+    /// World space to object space.
+    fn unrotate(&self, p: &V3) -> V3 {
+        v3(
+            self.cos_theta * p.x - self.sin_theta * p.z,
+            p.y,
+            self.sin_theta * p.x + self.cos_theta * p.z,
+        )
+    }
+}
+
+impl Hittable for RotateY {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let rotated = Ray {
+            orig: self.unrotate(&ray.orig),
+            dir: self.unrotate(&ray.dir),
+            time: ray.time,
+            wavelength: ray.wavelength,
+        };
+
+        // Rotation keeps angles, so front_face carries over unchanged
+        let mut rec = self.object.hit(&rotated, t_min, t_max)?;
+        rec.point = self.rotate(&rec.point);
+        rec.normal = self.rotate(&rec.normal);
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let bbox = self.object.bounding_box()?;
+        Some(transformed_box(&bbox, |p| self.rotate(p)))
+    }
+}
+
+impl TransformBuilder {
+    pub fn new() -> Self {
+        TransformBuilder {
+            matrix: Matrix4::identity(),
+        }
+    }
+
+    pub fn scale(mut self, factors: V3) -> Self {
+        self.matrix = Matrix4::new_nonuniform_scaling(&factors) * self.matrix;
+        self
+    }
+
+    /// Rotate by `angle` degrees about `axis`, right-handed.
+    pub fn rotate(mut self, axis: V3, angle: f64) -> Self {
+        let rotation = Matrix4::from_axis_angle(&Unit::new_normalize(axis), deg_to_rad(angle));
+        self.matrix = rotation * self.matrix;
+        self
+    }
+
+    pub fn translate(mut self, offset: V3) -> Self {
+        self.matrix = Matrix4::new_translation(&offset) * self.matrix;
+        self
+    }
+
+    /// Panics if the transform can't be inverted, e.g. after scaling by zero.
+    pub fn build(self, object: Box<dyn Hittable>) -> Transform {
+        let world_to_object = self
+            .matrix
+            .try_inverse()
+            .expect("transform must be invertible");
+        Transform {
+            object,
+            object_to_world: self.matrix,
+            world_to_object,
+        }
+    }
+}
+
+impl Default for TransformBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Hittable for Transform {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        // The direction isn't renormalized, so `t` means the same in both spaces
+        let local = Ray {
+            orig: self
+                .world_to_object
+                .transform_point(&Point3::from(ray.orig))
+                .coords,
+            dir: self.world_to_object.transform_vector(&ray.dir),
+            time: ray.time,
+            wavelength: ray.wavelength,
+        };
+
+        let mut rec = self.object.hit(&local, t_min, t_max)?;
+        rec.point = self
+            .object_to_world
+            .transform_point(&Point3::from(rec.point))
+            .coords;
+        // Normals go through the inverse transpose to stay perpendicular
+        rec.normal = self
+            .world_to_object
+            .transpose()
+            .transform_vector(&rec.normal)
+            .normalize();
+        Some(rec)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let bbox = self.object.bounding_box()?;
+        Some(transformed_box(&bbox, |p| {
+            self.object_to_world
+                .transform_point(&Point3::from(*p))
+                .coords
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::geometry::{Sphere, XzRect};
+    use crate::material::Lambertian;
+    use std::sync::Arc;
+
+    fn unit_sphere() -> Box<dyn Hittable> {
+        Box::new(Sphere {
+            center: v3(0., 0., 0.),
+            radius: 1.,
+            material: Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5)))),
+        })
+    }
+
+    #[test]
+    fn test_translate() {
+        let moved = Translate {
+            object: unit_sphere(),
+            offset: v3(3., 1., 0.),
+        };
+        let ray = Ray {
+            orig: v3(3., 1., 5.),
+            dir: v3(0., 0., -1.),
+            time: 0.,
+            wavelength: None,
+        };
+
+        match moved.hit(&ray, 0., 100.) {
+            Some(rec) => {
+                assert!((rec.point - v3(3., 1., 1.)).norm() < 1e-9);
+                assert!((rec.normal - v3(0., 0., 1.)).norm() < 1e-9);
+                assert!((rec.t - 4.).abs() < 1e-9);
+            }
+            None => panic!("Expected a hit to be recorded"),
+        }
+
+        // The sphere's original position is now empty
+        let old = Ray {
+            orig: v3(0., 0., 5.),
+            dir: v3(0., 0., -1.),
+            time: 0.,
+            wavelength: None,
+        };
+        assert!(moved.hit(&old, 0., 100.).is_none());
+        assert_eq!(
+            moved.bounding_box(),
+            Some(Aabb::new(v3(2., 0., -1.), v3(4., 2., 1.)))
+        );
+    }
+
+    #[test]
+    fn test_rotate_y() {
+        // Rotating a quarter turn carries the sphere from +x to -z
+        let offset = Translate {
+            object: unit_sphere(),
+            offset: v3(3., 0., 0.),
+        };
+        let rotated = RotateY::new(Box::new(offset), 90.);
+        let ray = Ray {
+            orig: v3(0., 0., 0.),
+            dir: v3(0., 0., -1.),
+            time: 0.,
+            wavelength: None,
+        };
+
+        match rotated.hit(&ray, 0., 100.) {
+            Some(rec) => {
+                assert!((rec.point - v3(0., 0., -2.)).norm() < 1e-9);
+                assert!((rec.normal - v3(0., 0., 1.)).norm() < 1e-9);
+            }
+            None => panic!("Expected a hit to be recorded"),
+        }
+
+        let bbox = rotated.bounding_box().unwrap();
+        assert!((bbox.min - v3(-1., -1., -4.)).norm() < 1e-9);
+        assert!((bbox.max - v3(1., 1., -2.)).norm() < 1e-9);
+    }
+
+    #[test]
+    fn test_transform_scale() {
+        let stretched = TransformBuilder::new()
+            .scale(v3(2., 1., 1.))
+            .build(unit_sphere());
+
+        // Hit at the far end of the stretched axis
+        let ray = Ray {
+            orig: v3(5., 0., 0.),
+            dir: v3(-1., 0., 0.),
+            time: 0.,
+            wavelength: None,
+        };
+        match stretched.hit(&ray, 0., 100.) {
+            Some(rec) => {
+                assert!((rec.point - v3(2., 0., 0.)).norm() < 1e-9);
+                assert!((rec.normal - v3(1., 0., 0.)).norm() < 1e-9);
+                assert!((rec.t - 3.).abs() < 1e-9);
+            }
+            None => panic!("Expected a hit to be recorded"),
+        }
+
+        // Past the original radius but inside the scaled one
+        let beside = Ray {
+            orig: v3(1.5, 0., 5.),
+            dir: v3(0., 0., -1.),
+            time: 0.,
+            wavelength: None,
+        };
+        assert!(stretched.hit(&beside, 0., 100.).is_some());
+        let bbox = stretched.bounding_box().unwrap();
+        assert!((bbox.max - v3(2., 1., 1.)).norm() < 1e-9);
+    }
+
+    #[test]
+    fn test_transform_matches_rotate_translate() {
+        let built = TransformBuilder::new()
+            .translate(v3(3., 0., 0.))
+            .rotate(v3(0., 1., 0.), 90.)
+            .build(unit_sphere());
+        let ray = Ray {
+            orig: v3(0., 0., 0.),
+            dir: v3(0., 0., -1.),
+            time: 0.,
+            wavelength: None,
+        };
+
+        let rec = built.hit(&ray, 0., 100.).unwrap();
+        assert!((rec.point - v3(0., 0., -2.)).norm() < 1e-9);
+        assert!((rec.normal - v3(0., 0., 1.)).norm() < 1e-9);
+    }
+
+    #[test]
+    fn test_flip_normals() {
+        let light = || XzRect {
+            x0: -1.,
+            x1: 1.,
+            z0: -1.,
+            z1: 1.,
+            k: 2.,
+            material: Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5)))),
+        };
+        let flipped = FlipNormals {
+            object: Box::new(light()),
+        };
+        let up = Ray {
+            orig: v3(0., 0., 0.),
+            dir: v3(0., 1., 0.),
+            time: 0.,
+            wavelength: None,
+        };
+
+        let unflipped = light();
+        let plain = unflipped.hit(&up, 0., 100.).unwrap();
+        let rec = flipped.hit(&up, 0., 100.).unwrap();
+        assert_eq!(rec.front_face, !plain.front_face);
+        assert_eq!(rec.t, plain.t);
+        // Still facing back along the ray, so materials scatter the right way
+        assert!(rec.normal.dot(&up.dir) < 0.);
+    }
+}
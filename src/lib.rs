@@ -0,0 +1,36 @@
+//! A path tracer following the Ray Tracing in One Weekend series.
+//!
+//! Build a world from the primitives in `geometry` (or load one with
+//! `scene`), point a `Camera` at it and hand both to `render`.
+
+pub mod aabb;
+pub mod background;
+pub mod bench;
+pub mod bvh;
+pub mod camera;
+pub mod color;
+pub mod exr;
+pub mod geometry;
+pub mod grid;
+pub mod hdr;
+pub mod hittable;
+pub mod image;
+pub mod instance;
+pub mod json;
+pub mod material;
+pub mod medium;
+pub mod mesh;
+pub mod pdf;
+pub mod perlin;
+pub mod png;
+pub mod render;
+pub mod scene;
+pub mod stats;
+pub mod texture;
+
+pub use render::{
+    ray_color, render, render_edge_adaptive, render_progressive, render_region, render_stereo,
+    render_tiled, render_with_aovs, AdaptiveConfig, AoConfig, Aovs, Checkpoint, DepthBudget,
+    Epsilon, LightingMode, PixelFilter, PointLight, RenderSettings, SamplingMode,
+};
+pub use scene::make_world;
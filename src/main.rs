@@ -1,217 +1,196 @@
-use material::Material;
 use rand::prelude::*;
+use std::io::{BufReader, BufWriter, Write};
 use std::sync::Arc;
-use std::thread;
 use std::time::Instant;
 use std::{error::Error, fs::File};
 
-mod camera;
-mod color;
-mod geometry;
-mod hittable;
-mod image;
-mod material;
-
-use camera::Camera;
-use color::Color;
-use geometry::{rand_in, unit, v3, Ray, Sphere};
-use hittable::{Hittable, HittableList};
-use image::{merge_samples, Image};
-
-use crate::material::{Dielectric, Lambertian, Metal};
-
-const INF: f64 = f64::INFINITY;
-
-fn ray_color(ray: &Ray, world: &impl Hittable, depth: i32) -> Color {
-    if depth <= 0 {
-        return Color::black();
-    }
-
-    if let Some(rec) = world.hit(ray, 0.001, INF) {
-        let col = match rec.material.scatter(&ray, &rec) {
-            None => Color::black(),
-            Some((att, sc_ray)) => att * ray_color(&sc_ray, world, depth - 1),
-        };
-        return col;
+use tracer::background::{Background, EnvironmentMap};
+use tracer::bench;
+use tracer::bvh::Bvh;
+use tracer::camera::Camera;
+use tracer::geometry::v3;
+use tracer::grid::Grid;
+use tracer::hittable::{Hittable, HittableList};
+use tracer::image::Accumulator;
+use tracer::stats;
+use tracer::{make_world, render, render_progressive, RenderSettings};
+
+mod cli;
+mod progress;
+
+use cli::{Accelerator, Options, OutputFormat, USAGE};
+use progress::ProgressBar;
+
+/// Samples per pixel between convergence checks with `--target-noise`.
+const CONVERGENCE_BATCH: i32 = 4;
+
+fn build_world(list: Vec<Arc<dyn Hittable>>, accel: Accelerator) -> Box<dyn Hittable> {
+    match accel {
+        Accelerator::Bvh => Box::new(Bvh::new_parallel(list)),
+        Accelerator::Grid => Box::new(Grid::new(list)),
+        Accelerator::List => Box::new(HittableList { list }),
     }
-
-    let unit_dir = unit(&ray.direction());
-    let t = 0.5 * (unit_dir.y + 1.0);
-    Color((1.0 - t) * v3(1.0, 1.0, 1.0) + t * v3(0.5, 0.7, 1.0))
 }
 
-fn render(
-    camera: &Camera,
-    world: &HittableList,
-    width: usize,
-    height: usize,
-    samples: i32,
-    max_depth: i32,
-) -> Image {
-    let mut image = Image::new(width, height);
-
-    for j in 0..height {
-        for i in 0..width {
-            let mut color = Color::black();
-
-            for _ in 0..samples {
-                let u = (i as f64 + random::<f64>()) / (width - 1) as f64;
-                let v = (j as f64 + random::<f64>()) / (height - 1) as f64;
-                let ray = camera.get_ray(u, v);
-
-                color = color + ray_color(&ray, world, max_depth);
-            }
-
-            image.img[(i, j)] = Color(color.0 / (samples as f64));
-        }
+fn run_bench(accel: Accelerator) -> Result<(), Box<dyn Error>> {
+    let world = build_world(bench::scene().list, accel);
+    let report = bench::run(&world, bench::WIDTH, bench::HEIGHT, bench::SAMPLES);
+
+    println!(
+        "{}x{} at {} samples, {:?}",
+        bench::WIDTH,
+        bench::HEIGHT,
+        bench::SAMPLES,
+        accel
+    );
+    println!("Primary rays: {}", report.primary_rays);
+    match report.primitive_tests {
+        Some(tests) => println!("Primitive intersection tests: {}", tests),
+        None => println!("Primitive intersection tests: build with --features stats"),
     }
-
-    image
+    println!("Time: {:?}", report.elapsed);
+    println!("Rays per second: {:.0}", report.rays_per_second());
+    Ok(())
 }
 
-fn make_world() -> HittableList {
-    let mut world = HittableList::new();
-
-    let ground_mat = Box::new(Lambertian {
-        albedo: Color(v3(0.5, 0.5, 0.5)),
-    });
-    let ground = Sphere {
-        center: v3(0., -1000., 0.),
-        radius: 1000.,
-        material: ground_mat,
-    };
-    world.add(Box::new(ground));
-
-    for a in -11..11 {
-        for b in -11..11 {
-            let choose_mat: f64 = random();
-            let cent = v3(
-                a as f64 + 0.9 * random::<f64>(),
-                0.2,
-                b as f64 + 0.9 * random::<f64>(),
-            );
-
-            if (cent - v3(4., 0.2, 0.)).norm() > 0.9 {
-                let mat: Box<dyn Material> = if choose_mat < 0.8 {
-                    Box::new(Lambertian {
-                        albedo: Color::random() * Color::random(),
-                    })
-                } else if choose_mat < 0.95 {
-                    Box::new(Metal {
-                        albedo: Color::random_in(0.5, 1.),
-                        fuzz: rand_in(0., 0.3),
-                    })
-                } else {
-                    Box::new(Dielectric { ir: 1.5 })
-                };
-
-                world.add(Box::new(Sphere {
-                    center: cent,
-                    radius: 0.2,
-                    material: mat,
-                }));
-            }
+fn main() -> Result<(), Box<dyn Error>> {
+    let options = match Options::parse(std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            eprintln!("error: {}\n\n{}", e, USAGE);
+            std::process::exit(2);
         }
+    };
+    if options.help {
+        println!("{}", USAGE);
+        return Ok(());
     }
 
-    let mat1 = Box::new(Dielectric { ir: 1.5 });
-    world.add(Box::new(Sphere {
-        center: v3(0., 1., 0.),
-        radius: 1.,
-        material: mat1,
-    }));
-
-    let mat2 = Box::new(Lambertian {
-        albedo: Color(v3(0.4, 0.2, 0.1)),
-    });
-    world.add(Box::new(Sphere {
-        center: v3(-4., 1., 0.),
-        radius: 1.,
-        material: mat2,
-    }));
-
-    let mat3 = Box::new(Metal {
-        albedo: Color(v3(0.7, 0.6, 0.5)),
-        fuzz: 0.0,
-    });
-    world.add(Box::new(Sphere {
-        center: v3(4., 1., 0.),
-        radius: 1.,
-        material: mat3,
-    }));
-
-    world
-}
+    if options.bench {
+        return run_bench(options.accel);
+    }
 
-fn main() -> Result<(), Box<dyn Error>> {
     let start = Instant::now();
 
-    let ratio: f64 = 16.0 / 9.0;
-    let width: usize = 400;
-    let height: usize = (width as f64 / ratio) as usize;
+    let width = options.width;
+    let height = options.height();
 
-    let sub_samples = 16;
+    let sub_samples = options.samples;
     let super_samples = 1;
-    let max_depth = 50;
-
-    let world = Arc::new(make_world());
-
-    let lookfrom = v3(13., 2., 3.);
-    let lookat = v3(0., 0., 0.);
-    let vup = v3(0., 1., 0.);
-
-    let focus_dist = (lookfrom - lookat).norm();
-
-    // Camera
-    let camera = Arc::new(Camera::new(
-        lookfrom,
-        lookat,
-        vup,
-        40.,
-        16. / 9.,
-        0.1,
-        focus_dist,
-    ));
-
-    let mut handles = Vec::with_capacity(super_samples);
-    let mut images = Vec::with_capacity(super_samples);
-
-    //for sup in 0..super_samples {
-    //    println!("Running {} of {} samples.", sup, super_samples);
-    //    images.push(render(
-    //        &camera,
-    //        &world,
-    //        width,
-    //        height,
-    //        sub_samples,
-    //        max_depth,
-    //    ))
-    //}
-
-    println!("Starting threads");
-    for _ in 0..super_samples {
-        let cam = camera.clone();
-        let wo = world.clone();
-        handles.push(thread::spawn(move || {
-            render(&cam, &wo, width, height, sub_samples, max_depth)
-        }));
-    }
+    let max_depth = options.max_depth;
 
-    for handle in handles {
-        images.push(handle.join().unwrap());
-    }
+    let resumed = match &options.resume {
+        Some(path) if path.exists() => {
+            let acc = Accumulator::load(&mut BufReader::new(File::open(path)?))?;
+            if acc.dim() != (width, height) {
+                return Err(format!("{} holds a different image size", path.display()).into());
+            }
+            // The seed builds the scene, so another one would mix two scenes
+            if options.seed.is_some_and(|seed| seed != acc.seed()) {
+                let msg = format!("{} was rendered with --seed {}", path.display(), acc.seed());
+                return Err(msg.into());
+            }
+            println!("Resuming from {} samples", acc.samples());
+            Some(acc)
+        }
+        _ => None,
+    };
 
-    println!("Joined all threads");
+    // Without --seed every run differs; print the seed so it can be repeated
+    let seed = match &resumed {
+        Some(acc) => acc.seed(),
+        None => options.seed.unwrap_or_else(random),
+    };
+    println!("Seed: {}", seed);
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let world = build_world(make_world(&mut rng).list, options.accel);
+    // The default scene is lit by the sky alone
+    let lights = HittableList::new();
+
+    let camera = Camera::builder(v3(13., 2., 3.), v3(0., 0., 0.))
+        .vfov(20.)
+        .aspect(options.aspect)
+        .aperture(0.1)
+        .shutter(0., 1.)
+        .build();
+
+    let background = match &options.environment {
+        Some(path) => Background::Environment(EnvironmentMap::load(path)?),
+        None => Background::default(),
+    };
 
-    let final_image = merge_samples(images);
+    let mut acc = resumed.unwrap_or_else(|| Accumulator::new(width, height).with_seed(seed));
+    // Passes from earlier runs used the seeds before this one
+    let first_pass = acc.samples() as u64;
+
+    for sup in 0..super_samples {
+        println!("Running {} of {} samples.", sup + 1, super_samples);
+        let mut progress = ProgressBar::new();
+        let settings = RenderSettings {
+            width: width * options.ssaa,
+            height: height * options.ssaa,
+            samples: sub_samples,
+            max_depth,
+            seed: seed.wrapping_add(first_pass + sup as u64 + 1),
+            background: background.clone(),
+            firefly_clamp: options.firefly_clamp,
+            ..Default::default()
+        };
+        match options.target_noise {
+            Some(target) => {
+                let mut reached = None;
+                let image = render_progressive(
+                    &camera,
+                    &world,
+                    &lights,
+                    &settings,
+                    CONVERGENCE_BATCH,
+                    Some(target),
+                    |checkpoint| {
+                        progress.update(checkpoint.samples as usize, sub_samples as usize);
+                        reached = Some(checkpoint);
+                    },
+                );
+                progress.finish();
+                if let Some(c) = reached {
+                    println!("Stopped at {} samples, change {:.4}", c.samples, c.change);
+                    acc.add_samples(&image.downsample(options.ssaa), c.samples as u32);
+                }
+            }
+            None => {
+                let image = render(&camera, &world, &lights, &settings, None, |done, total| {
+                    progress.update(done, total)
+                });
+                progress.finish();
+                acc.add_samples(&image.downsample(options.ssaa), sub_samples as u32);
+            }
+        }
+    }
 
-    let mut file = File::create("out.ppm")?;
-    final_image.write_ppm(&mut file)?;
+    let final_image = acc.current();
+    if let Some(path) = &options.resume {
+        let mut w = BufWriter::new(File::create(path)?);
+        acc.save(&mut w)?;
+        w.flush()?;
+    }
 
-    println!("Wrote file!");
+    let mut file = File::create(&options.output)?;
+    match options.format() {
+        OutputFormat::Ppm => {
+            final_image.write_ppm_binary(&mut file, options.tone_map, options.gamma)?
+        }
+        OutputFormat::Png => final_image.write_png(&mut file, options.tone_map, options.gamma)?,
+        OutputFormat::Exr => final_image.write_exr(&mut file)?,
+    }
+
+    println!("Wrote {}!", options.output.display());
 
     let end = Instant::now();
     println!("Finished running in {:?}", end.duration_since(start));
+    if stats::ENABLED {
+        println!("Primitive intersection tests: {}", stats::primitive_tests());
+    }
 
     Ok(())
 }
@@ -1,20 +1,59 @@
-use rand::random;
+use rand::{Rng, RngCore};
+use std::sync::Arc;
 
 use crate::color::Color;
 use crate::geometry::Ray;
+use crate::pdf::CosinePdf;
+use crate::texture::{SolidColor, Texture};
 use crate::{
-    geometry::{near_zero, random_in_unit_sphere, random_unit_vec, reflect, refract, unit, v3},
+    geometry::{
+        near_zero, perpendicular_basis, random_in_unit_sphere, random_unit_vec, reflect, refract,
+        unit, v3, V3,
+    },
     hittable::HitRecord,
 };
 
 pub type Scatter = (Color, Ray);
 
 pub trait Material: Send + Sync {
-    fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<Scatter>;
+    fn scatter(&self, ray: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<Scatter>;
+
+    fn emitted(&self, _rec: &HitRecord) -> Color {
+        Color::black()
+    }
+
+    /// Base color at the hit, ignoring lighting, for the albedo AOV.
+    /// Materials without one, like clear glass, count as white.
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        Color(v3(1., 1., 1.))
+    }
+
+    /// Whether `scatter` is ideal diffuse reflection, cosine weighted, so
+    /// lights can also be sampled directly from the hit.
+    fn is_diffuse(&self) -> bool {
+        false
+    }
+
+    /// The distribution `scatter` draws directions from at `rec`, for
+    /// materials that can report one, so it can be mixed with light sampling.
+    fn scatter_pdf(&self, _rec: &HitRecord) -> Option<CosinePdf> {
+        None
+    }
 }
 
+/// Ideal diffuse reflector. Hit normals face the incoming ray, so it reflects
+/// off whichever face was hit; for light passing through the surface, use
+/// `TwoSidedLambertian`.
 pub struct Lambertian {
-    pub albedo: Color,
+    pub albedo: Box<dyn Texture>,
+}
+
+/// Diffuse sheet that also lets light through, like paper or a lampshade:
+/// a `translucency` fraction of bounces leaves from the far side, so a light
+/// behind the sheet shows through it.
+pub struct TwoSidedLambertian {
+    pub albedo: Box<dyn Texture>,
+    pub translucency: f64,
 }
 
 pub struct Metal {
@@ -22,33 +61,160 @@ pub struct Metal {
     pub fuzz: f64,
 }
 
+/// Brushed metal: the reflection is blurred by `roughness_u` along `tangent`
+/// and by `roughness_v` across it, drawing streaks along the brushing.
+pub struct AnisotropicMetal {
+    pub albedo: Color,
+    pub roughness_u: f64,
+    pub roughness_v: f64,
+    pub tangent: V3,
+}
+
 pub struct Dielectric {
     pub ir: f64,
+    /// Light lost per unit distance travelled inside; black for clear glass.
+    pub absorption: Color,
+    /// Cauchy coefficients `(b, c)` giving the index `b + c / λ²`, with `λ` in
+    /// micrometres, for rays that carry a wavelength.
+    pub cauchy: Option<(f64, f64)>,
 }
 
-impl Material for Lambertian {
-    fn scatter(&self, _ray: &Ray, rec: &HitRecord) -> Option<Scatter> {
-        let mut scatter_dir = rec.normal + random_unit_vec();
+pub struct DiffuseLight {
+    pub emit: Box<dyn Texture>,
+}
 
-        if near_zero(&scatter_dir) {
-            scatter_dir = rec.normal;
+/// Metallic-roughness material: `metallic` picks between a diffuse and a
+/// mirror-like lobe, and `roughness` blurs the mirror.
+pub struct Pbr {
+    pub albedo: Color,
+    pub metallic: f64,
+    pub roughness: f64,
+}
+
+/// Wraps `base`, bending the shading normal by the tangent-space normal read
+/// from `map`, encoded as in normal-map images: `(0.5, 0.5, 1)` is flat.
+pub struct NormalMapped {
+    pub base: Arc<dyn Material>,
+    pub map: Box<dyn Texture>,
+}
+
+/// Scatters like `a` with probability `ratio` and like `b` otherwise, for
+/// layered looks such as partly metallic paint.
+pub struct MixMaterial {
+    pub a: Arc<dyn Material>,
+    pub b: Arc<dyn Material>,
+    pub ratio: f64,
+}
+
+/// Emits its surface normal as a color, for debugging geometry.
+pub struct NormalMaterial;
+
+/// Scatters equally in every direction; the phase function of fog and smoke.
+pub struct Isotropic {
+    pub albedo: Color,
+}
+
+impl Lambertian {
+    pub fn solid(albedo: Color) -> Self {
+        Lambertian {
+            albedo: Box::new(SolidColor { color: albedo }),
         }
+    }
+}
+
+fn diffuse_ray(ray: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Ray {
+    let mut scatter_dir = rec.normal + random_unit_vec(rng);
+
+    if near_zero(&scatter_dir) {
+        scatter_dir = rec.normal;
+    }
+
+    Ray {
+        orig: rec.point,
+        dir: scatter_dir,
+        time: ray.time,
+        wavelength: ray.wavelength,
+    }
+}
 
+impl Material for Lambertian {
+    fn scatter(&self, ray: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<Scatter> {
         let scattered = Ray {
             orig: rec.point,
-            dir: scatter_dir,
+            dir: CosinePdf::new(&rec.normal).generate(rng),
+            time: ray.time,
+            wavelength: ray.wavelength,
         };
-        let color = self.albedo;
+        let color = self.albedo.value(rec.u, rec.v, &rec.point);
         Some((color, scattered))
     }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.albedo.value(rec.u, rec.v, &rec.point)
+    }
+
+    fn is_diffuse(&self) -> bool {
+        true
+    }
+
+    fn scatter_pdf(&self, rec: &HitRecord) -> Option<CosinePdf> {
+        Some(CosinePdf::new(&rec.normal))
+    }
+}
+
+impl TwoSidedLambertian {
+    /// `translucency` is clamped to `[0, 1]`.
+    pub fn solid(albedo: Color, translucency: f64) -> Self {
+        TwoSidedLambertian {
+            albedo: Box::new(SolidColor { color: albedo }),
+            translucency: translucency.clamp(0., 1.),
+        }
+    }
+}
+
+impl Material for TwoSidedLambertian {
+    fn scatter(&self, ray: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<Scatter> {
+        let normal = if rng.gen::<f64>() < self.translucency {
+            -rec.normal
+        } else {
+            rec.normal
+        };
+        let scattered = Ray {
+            orig: rec.point,
+            dir: CosinePdf::new(&normal).generate(rng),
+            time: ray.time,
+            wavelength: ray.wavelength,
+        };
+        Some((self.albedo.value(rec.u, rec.v, &rec.point), scattered))
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.albedo.value(rec.u, rec.v, &rec.point)
+    }
+
+    // Not `is_diffuse`: light sampling only covers the hemisphere facing the
+    // ray, and would miss what comes through the sheet
+}
+
+impl Metal {
+    /// `fuzz` is clamped to `[0, 1]`; rougher values would scatter below the
+    /// surface.
+    pub fn new(albedo: Color, fuzz: f64) -> Self {
+        Metal {
+            albedo,
+            fuzz: fuzz.clamp(0.0, 1.0),
+        }
+    }
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<Scatter> {
+    fn scatter(&self, ray: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<Scatter> {
         let reflected = reflect(&unit(&ray.direction()), &rec.normal);
         let scattered = Ray {
             orig: rec.point,
-            dir: reflected + self.fuzz * random_in_unit_sphere(),
+            dir: reflected + self.fuzz * random_in_unit_sphere(rng),
+            time: ray.time,
+            wavelength: ray.wavelength,
         };
         let color = self.albedo;
 
@@ -58,9 +224,102 @@ impl Material for Metal {
             None
         }
     }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+}
+
+impl AnisotropicMetal {
+    /// Both roughness values are clamped to `[0, 1]`, like `Metal`'s fuzz.
+    pub fn new(albedo: Color, roughness_u: f64, roughness_v: f64, tangent: V3) -> Self {
+        AnisotropicMetal {
+            albedo,
+            roughness_u: roughness_u.clamp(0., 1.),
+            roughness_v: roughness_v.clamp(0., 1.),
+            tangent,
+        }
+    }
+
+    /// Unit tangent and bitangent perpendicular to `normal`, with the tangent
+    /// as close to `self.tangent` as possible.
+    fn frame(&self, normal: &V3) -> (V3, V3) {
+        let mut t = self.tangent - self.tangent.dot(normal) * normal;
+        if near_zero(&t) {
+            // The tangent runs along the normal, so any perpendicular will do
+            t = perpendicular_basis(normal).0;
+        }
+        let t = unit(&t);
+        (t, normal.cross(&t))
+    }
+}
+
+impl Material for AnisotropicMetal {
+    fn scatter(&self, ray: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<Scatter> {
+        let reflected = reflect(&unit(&ray.direction()), &rec.normal);
+        let (t, b) = self.frame(&rec.normal);
+
+        // Stretch the fuzz sphere into an ellipsoid, keeping the normal axis
+        // at the mean so equal roughnesses give back `Metal`'s sphere
+        let p = random_in_unit_sphere(rng);
+        let normal_roughness = 0.5 * (self.roughness_u + self.roughness_v);
+        let fuzz = self.roughness_u * p.dot(&t) * t
+            + self.roughness_v * p.dot(&b) * b
+            + normal_roughness * p.dot(&rec.normal) * rec.normal;
+        let scattered = Ray {
+            orig: rec.point,
+            dir: reflected + fuzz,
+            time: ray.time,
+            wavelength: ray.wavelength,
+        };
+
+        if scattered.direction().dot(&rec.normal) > 0. {
+            Some((self.albedo, scattered))
+        } else {
+            None
+        }
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
 }
 
 impl Dielectric {
+    pub fn new(ir: f64) -> Self {
+        Dielectric {
+            ir,
+            absorption: Color::black(),
+            cauchy: None,
+        }
+    }
+
+    /// Tinted glass, absorbing by Beer's law.
+    pub fn colored(ir: f64, absorption: Color) -> Self {
+        Dielectric {
+            ir,
+            absorption,
+            cauchy: None,
+        }
+    }
+
+    /// Glass whose index depends on the wavelength, splitting white light
+    /// into a rainbow. Rays without a wavelength see the index at 550nm.
+    pub fn dispersive(b: f64, c: f64) -> Self {
+        Dielectric {
+            ir: b + c / 0.55f64.powi(2),
+            absorption: Color::black(),
+            cauchy: Some((b, c)),
+        }
+    }
+
+    fn index(&self, wavelength: Option<f64>) -> f64 {
+        match (self.cauchy, wavelength) {
+            (Some((b, c)), Some(nm)) => b + c / (nm / 1000.).powi(2),
+            _ => self.ir,
+        }
+    }
+
     fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
         let r0 = ((1. - ref_idx) / (1. + ref_idx)).powf(2.);
         r0 + (1. - r0) * (1. - cosine).powf(5.)
@@ -68,21 +327,25 @@ impl Dielectric {
 }
 
 impl Material for Dielectric {
-    fn scatter(&self, ray: &Ray, rec: &HitRecord) -> Option<Scatter> {
-        let attenuation = Color(v3(1.0, 1.0, 1.0));
-        let refraction_ratio = if rec.front_face {
-            1.0 / self.ir
+    fn scatter(&self, ray: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<Scatter> {
+        // Hitting a back face means the ray has just crossed the interior, from
+        // where the previous bounce started
+        let attenuation = if rec.front_face {
+            Color(v3(1.0, 1.0, 1.0))
         } else {
-            self.ir
+            let distance = rec.t * ray.direction().norm();
+            Color((-self.absorption.0 * distance).map(f64::exp))
         };
+        let ir = self.index(ray.wavelength);
+        let refraction_ratio = if rec.front_face { 1.0 / ir } else { ir };
 
         let unit_dir = unit(&ray.direction());
 
-        let cos_theta = unit_dir.dot(&rec.normal).min(1.0);
+        let cos_theta = (-unit_dir).dot(&rec.normal).min(1.0);
         let sin_theta = (1. - cos_theta * cos_theta).sqrt();
 
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let should_reflect = Dielectric::reflectance(cos_theta, refraction_ratio) > random();
+        let should_reflect = Dielectric::reflectance(cos_theta, refraction_ratio) > rng.gen();
 
         let direction = if cannot_refract || should_reflect {
             reflect(&unit_dir, &rec.normal)
@@ -95,7 +358,577 @@ impl Material for Dielectric {
             Ray {
                 orig: rec.point,
                 dir: direction,
+                time: ray.time,
+                wavelength: ray.wavelength,
             },
         ))
     }
 }
+
+impl DiffuseLight {
+    pub fn solid(emit: Color) -> Self {
+        DiffuseLight {
+            emit: Box::new(SolidColor { color: emit }),
+        }
+    }
+}
+
+impl Material for DiffuseLight {
+    fn scatter(&self, _ray: &Ray, _rec: &HitRecord, _rng: &mut dyn RngCore) -> Option<Scatter> {
+        None
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.emit.value(rec.u, rec.v, &rec.point)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.emitted(rec)
+    }
+}
+
+impl Material for Isotropic {
+    fn scatter(&self, ray: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<Scatter> {
+        let scattered = Ray {
+            orig: rec.point,
+            dir: random_in_unit_sphere(rng),
+            time: ray.time,
+            wavelength: ray.wavelength,
+        };
+        Some((self.albedo, scattered))
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+}
+
+impl Material for Pbr {
+    fn scatter(&self, ray: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<Scatter> {
+        // Pick one lobe at random rather than weighting both, so the
+        // attenuation never exceeds the albedo
+        if rng.gen::<f64>() >= self.metallic {
+            return Some((self.albedo, diffuse_ray(ray, rec, rng)));
+        }
+
+        // Squaring matches how GGX maps perceptual roughness to the lobe width
+        let alpha = self.roughness.clamp(0., 1.).powi(2);
+        let reflected = reflect(&unit(&ray.direction()), &rec.normal);
+        let scattered = Ray {
+            orig: rec.point,
+            dir: reflected + alpha * random_in_unit_sphere(rng),
+            time: ray.time,
+            wavelength: ray.wavelength,
+        };
+
+        if scattered.direction().dot(&rec.normal) > 0. {
+            Some((self.albedo, scattered))
+        } else {
+            None
+        }
+    }
+
+    fn albedo(&self, _rec: &HitRecord) -> Color {
+        self.albedo
+    }
+}
+
+impl NormalMapped {
+    /// The mapped normal at `rec`, in world space.
+    ///
+    /// The tangent follows increasing `u` as laid out by `sphere_uv`, around
+    /// the y axis; that is exact for spheres and a fair guess elsewhere.
+    fn shading_normal(&self, rec: &HitRecord) -> V3 {
+        let n = rec.normal;
+        let mut tangent = v3(0., 1., 0.).cross(&n);
+        if near_zero(&tangent) {
+            // At the poles every direction is around the axis
+            tangent = perpendicular_basis(&n).0;
+        }
+        let tangent = unit(&tangent);
+        let bitangent = n.cross(&tangent);
+
+        let m = 2. * self.map.value(rec.u, rec.v, &rec.point).0 - v3(1., 1., 1.);
+        unit(&(m.x * tangent + m.y * bitangent + m.z * n))
+    }
+
+    fn mapped<'a>(&self, rec: &HitRecord<'a>) -> HitRecord<'a> {
+        HitRecord {
+            normal: self.shading_normal(rec),
+            ..rec.clone()
+        }
+    }
+}
+
+impl Material for NormalMapped {
+    fn scatter(&self, ray: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<Scatter> {
+        self.base.scatter(ray, &self.mapped(rec), rng)
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        self.base.emitted(&self.mapped(rec))
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.base.albedo(rec)
+    }
+
+    fn is_diffuse(&self) -> bool {
+        self.base.is_diffuse()
+    }
+}
+
+impl MixMaterial {
+    /// `ratio` is clamped to `[0, 1]`.
+    pub fn new(a: Arc<dyn Material>, b: Arc<dyn Material>, ratio: f64) -> Self {
+        MixMaterial {
+            a,
+            b,
+            ratio: ratio.clamp(0., 1.),
+        }
+    }
+}
+
+impl Material for MixMaterial {
+    fn scatter(&self, ray: &Ray, rec: &HitRecord, rng: &mut dyn RngCore) -> Option<Scatter> {
+        // No coin is tossed at either end, so a pure mix draws the same
+        // random numbers as the material it stands for
+        let use_a = match self.ratio {
+            r if r >= 1. => true,
+            r if r <= 0. => false,
+            r => rng.gen::<f64>() < r,
+        };
+        if use_a {
+            self.a.scatter(ray, rec, rng)
+        } else {
+            self.b.scatter(ray, rec, rng)
+        }
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        Color::lerp(self.b.emitted(rec), self.a.emitted(rec), self.ratio)
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        Color::lerp(self.b.albedo(rec), self.a.albedo(rec), self.ratio)
+    }
+
+    /// Light sampling assumes a diffuse bounce, so only a mix of two diffuse
+    /// materials counts.
+    fn is_diffuse(&self) -> bool {
+        self.a.is_diffuse() && self.b.is_diffuse()
+    }
+}
+
+impl Material for NormalMaterial {
+    fn scatter(&self, _ray: &Ray, _rec: &HitRecord, _rng: &mut dyn RngCore) -> Option<Scatter> {
+        None
+    }
+
+    fn emitted(&self, rec: &HitRecord) -> Color {
+        Color(0.5 * (rec.normal + v3(1., 1., 1.)))
+    }
+
+    fn albedo(&self, rec: &HitRecord) -> Color {
+        self.emitted(rec)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::{Sphere, XyRect};
+    use crate::hittable::Hittable;
+    use crate::texture::ImageTexture;
+    use rand::{rngs::StdRng, SeedableRng};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_normal_material() {
+        let sphere = Sphere {
+            center: v3(0., 0., 0.),
+            radius: 1.,
+            material: Arc::new(NormalMaterial),
+        };
+        // Looking down +z at the face that points toward -z
+        let ray = Ray {
+            orig: v3(0., 0., -5.),
+            dir: v3(0., 0., 1.),
+            time: 0.,
+            wavelength: None,
+        };
+
+        let rec = sphere.hit(&ray, 0.001, 100.).unwrap();
+        assert!(rec
+            .material
+            .scatter(&ray, &rec, &mut StdRng::seed_from_u64(0))
+            .is_none());
+        assert_eq!(rec.material.emitted(&rec).0, v3(0.5, 0.5, 0.));
+    }
+
+    #[test]
+    fn test_lambertian_scatters_from_back_face() {
+        let paper = XyRect {
+            x0: -1.,
+            x1: 1.,
+            y0: -1.,
+            y1: 1.,
+            k: 0.,
+            material: Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5)))),
+        };
+        let mut rng = StdRng::seed_from_u64(3);
+
+        for &dir in &[v3(0., 0., -1.), v3(0., 0., 1.)] {
+            let ray = Ray {
+                orig: -2. * dir,
+                dir,
+                time: 0.,
+                wavelength: None,
+            };
+            let rec = paper.hit(&ray, 0.001, 100.).unwrap();
+            assert_eq!(rec.front_face, dir.z < 0.);
+            for _ in 0..100 {
+                // Always back toward the side the ray came from
+                let (_, scattered) = rec.material.scatter(&ray, &rec, &mut rng).unwrap();
+                assert!(scattered.dir.dot(&ray.dir) < 0.);
+            }
+        }
+    }
+
+    #[test]
+    fn test_diffuse_ray_is_cosine_weighted() {
+        let floor = XyRect {
+            x0: -1.,
+            x1: 1.,
+            y0: -1.,
+            y1: 1.,
+            k: 0.,
+            material: Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5)))),
+        };
+        let ray = Ray {
+            orig: v3(0., 0., 2.),
+            dir: v3(0., 0., -1.),
+            time: 0.,
+            wavelength: None,
+        };
+        let rec = floor.hit(&ray, 0.001, 100.).unwrap();
+        let mut rng = StdRng::seed_from_u64(6);
+
+        // Normal plus a uniform unit vector: cos theta averages 2/3, and the
+        // directions lean no way along the surface
+        let n = 20_000;
+        let mut sum = v3(0., 0., 0.);
+        for _ in 0..n {
+            sum += unit(&diffuse_ray(&ray, &rec, &mut rng).dir);
+        }
+        let mean = sum / n as f64;
+        assert!((mean.z - 2. / 3.).abs() < 0.01, "{:?}", mean);
+        assert!(mean.x.abs() < 0.01 && mean.y.abs() < 0.01, "{:?}", mean);
+    }
+
+    #[test]
+    fn test_two_sided_lambertian_transmits() {
+        let sheet = |translucency| XyRect {
+            x0: -1.,
+            x1: 1.,
+            y0: -1.,
+            y1: 1.,
+            k: 0.,
+            material: Arc::new(TwoSidedLambertian::solid(
+                Color(v3(0.5, 0.5, 0.5)),
+                translucency,
+            )),
+        };
+        // Lit from behind: the light is at z < 0, the ray arrives from z > 0
+        let ray = Ray {
+            orig: v3(0., 0., 2.),
+            dir: v3(0., 0., -1.),
+            time: 0.,
+            wavelength: None,
+        };
+        let mut rng = StdRng::seed_from_u64(5);
+
+        let paper = sheet(1.);
+        let rec = paper.hit(&ray, 0.001, 100.).unwrap();
+        for _ in 0..100 {
+            let (_, scattered) = rec.material.scatter(&ray, &rec, &mut rng).unwrap();
+            assert!(scattered.dir.z < 0.);
+        }
+
+        let paper = sheet(0.25);
+        let rec = paper.hit(&ray, 0.001, 100.).unwrap();
+        let through = (0..1000)
+            .filter(|_| rec.material.scatter(&ray, &rec, &mut rng).unwrap().1.dir.z < 0.)
+            .count();
+        assert!((200..300).contains(&through), "{}", through);
+    }
+
+    #[test]
+    fn test_colored_glass_absorption() {
+        let glass: Arc<dyn Material> = Arc::new(Dielectric::colored(1.5, Color(v3(0.1, 0.5, 1.0))));
+        let mut rng = StdRng::seed_from_u64(0);
+
+        // Leave a glass ball from its centre, crossing `radius` of glass
+        let mut exit_attenuation = |radius: f64| {
+            let sphere = Sphere {
+                center: v3(0., 0., 0.),
+                radius,
+                material: glass.clone(),
+            };
+            let ray = Ray {
+                orig: v3(0., 0., 0.),
+                dir: v3(0., 0., 2.),
+                time: 0.,
+                wavelength: None,
+            };
+            let rec = sphere.hit(&ray, 0.001, 100.).unwrap();
+            assert!(!rec.front_face);
+            rec.material.scatter(&ray, &rec, &mut rng).unwrap().0
+        };
+
+        let thin = exit_attenuation(0.5);
+        let thick = exit_attenuation(2.);
+        assert!((thin.0.x - (-0.05f64).exp()).abs() < 1e-12);
+        assert!(thick.0.x < thin.0.x && thick.0.y < thin.0.y && thick.0.z < thin.0.z);
+
+        // Entering the glass costs nothing
+        let ray = Ray {
+            orig: v3(0., 0., -5.),
+            dir: v3(0., 0., 1.),
+            time: 0.,
+            wavelength: None,
+        };
+        let sphere = Sphere {
+            center: v3(0., 0., 0.),
+            radius: 1.,
+            material: glass,
+        };
+        let rec = sphere.hit(&ray, 0.001, 100.).unwrap();
+        let entry = rec.material.scatter(&ray, &rec, &mut rng).unwrap().0;
+        assert_eq!(entry.0, v3(1., 1., 1.));
+    }
+
+    #[test]
+    fn test_pbr_lobes() {
+        let albedo = Color(v3(0.9, 0.6, 0.3));
+        let sphere = |metallic| Sphere {
+            center: v3(0., 0., 0.),
+            radius: 1.,
+            material: Arc::new(Pbr {
+                albedo,
+                metallic,
+                roughness: 0.,
+            }),
+        };
+        let ray = Ray {
+            orig: v3(-1., 2., 0.),
+            dir: v3(1., -1., 0.),
+            time: 0.,
+            wavelength: None,
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let mirror = sphere(1.);
+        let rec = mirror.hit(&ray, 0.001, 100.).unwrap();
+        let expected = reflect(&unit(&ray.dir), &rec.normal);
+        for _ in 0..10 {
+            let (att, out) = rec.material.scatter(&ray, &rec, &mut rng).unwrap();
+            assert!((out.dir - expected).norm() < 1e-12);
+            assert_eq!(att.0, albedo.0);
+        }
+
+        // Diffuse bounces spread out over the hemisphere around the normal
+        let diffuse = sphere(0.);
+        let rec = diffuse.hit(&ray, 0.001, 100.).unwrap();
+        let dirs: Vec<_> = (0..100)
+            .map(|_| rec.material.scatter(&ray, &rec, &mut rng).unwrap().1.dir)
+            .collect();
+        assert!(dirs.iter().all(|d| d.dot(&rec.normal) >= 0.));
+        assert!(dirs.iter().any(|d| (unit(d) - expected).norm() > 0.5));
+    }
+
+    #[test]
+    fn test_full_mix_is_first_material() {
+        let metal: Arc<dyn Material> = Arc::new(Metal::new(Color(v3(0.9, 0.8, 0.7)), 0.4));
+        let diffuse: Arc<dyn Material> = Arc::new(Lambertian::solid(Color(v3(0.2, 0.3, 0.4))));
+        let mix = MixMaterial::new(metal.clone(), diffuse, 1.);
+        let sphere = Sphere {
+            center: v3(0., 0., 0.),
+            radius: 1.,
+            material: metal.clone(),
+        };
+        let ray = Ray {
+            orig: v3(0.3, 0.2, -5.),
+            dir: v3(0., 0., 1.),
+            time: 0.,
+            wavelength: None,
+        };
+        let rec = sphere.hit(&ray, 0.001, 100.).unwrap();
+
+        let (mut rng_a, mut rng_mix) = (StdRng::seed_from_u64(5), StdRng::seed_from_u64(5));
+        for _ in 0..1000 {
+            let expected = metal.scatter(&ray, &rec, &mut rng_a);
+            let actual = mix.scatter(&ray, &rec, &mut rng_mix);
+            match (expected, actual) {
+                (Some((ea, er)), Some((aa, ar))) => {
+                    assert_eq!(ea.0, aa.0);
+                    assert_eq!(er.dir, ar.dir);
+                }
+                (e, a) => assert_eq!(e.is_some(), a.is_some()),
+            }
+        }
+        assert_eq!(mix.albedo(&rec).0, metal.albedo(&rec).0);
+        assert!(!mix.is_diffuse());
+        assert_eq!(MixMaterial::new(metal.clone(), metal, 3.).ratio, 1.);
+    }
+
+    #[test]
+    fn test_metal_fuzz_clamped() {
+        let albedo = Color(v3(0.8, 0.8, 0.8));
+        assert_eq!(Metal::new(albedo, 5.0).fuzz, 1.0);
+        assert_eq!(Metal::new(albedo, -1.0).fuzz, 0.0);
+        assert_eq!(Metal::new(albedo, 0.3).fuzz, 0.3);
+    }
+
+    #[test]
+    fn test_anisotropic_metal_equal_roughness_is_metal() {
+        let albedo = Color(v3(0.8, 0.6, 0.2));
+        let sphere = |material: Arc<dyn Material>| Sphere {
+            center: v3(0., 0., 0.),
+            radius: 1.,
+            material,
+        };
+        let metal = sphere(Arc::new(Metal::new(albedo, 0.4)));
+        let brushed = sphere(Arc::new(AnisotropicMetal::new(
+            albedo,
+            0.4,
+            0.4,
+            v3(0., 1., 1.),
+        )));
+        let ray = Ray {
+            orig: v3(-1., 2., 0.5),
+            dir: v3(1., -1., -0.5),
+            time: 0.,
+            wavelength: None,
+        };
+        let rec = metal.hit(&ray, 0.001, 100.).unwrap();
+        let brushed_rec = brushed.hit(&ray, 0.001, 100.).unwrap();
+
+        for seed in 0..100 {
+            let expected = rec
+                .material
+                .scatter(&ray, &rec, &mut StdRng::seed_from_u64(seed));
+            let actual =
+                brushed_rec
+                    .material
+                    .scatter(&ray, &brushed_rec, &mut StdRng::seed_from_u64(seed));
+            match (expected, actual) {
+                (Some((_, e)), Some((att, a))) => {
+                    assert!((e.dir - a.dir).norm() < 1e-9);
+                    assert_eq!(att.0, albedo.0);
+                }
+                (e, a) => assert_eq!(e.is_some(), a.is_some()),
+            }
+        }
+
+        let clamped = AnisotropicMetal::new(albedo, 2., -1., v3(1., 0., 0.));
+        assert_eq!((clamped.roughness_u, clamped.roughness_v), (1., 0.));
+    }
+
+    #[test]
+    fn test_flat_normal_map_keeps_normal() {
+        let mirror: Arc<dyn Material> = Arc::new(Metal::new(Color(v3(0.9, 0.9, 0.9)), 0.));
+        let mapped = NormalMapped {
+            base: mirror.clone(),
+            map: Box::new(SolidColor {
+                color: Color(v3(0.5, 0.5, 1.)),
+            }),
+        };
+        let sphere = Sphere {
+            center: v3(0., 0., 0.),
+            radius: 1.,
+            material: mirror,
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+
+        // Aimed at the centre; the second hits the pole, where the tangent
+        // has no preferred way
+        for dir in &[v3(1., -1., 0.2), v3(0., -1., 0.), v3(-0.3, -1., 0.9)] {
+            let ray = Ray {
+                orig: -3. * dir,
+                dir: *dir,
+                time: 0.,
+                wavelength: None,
+            };
+            let rec = sphere.hit(&ray, 0.001, 100.).unwrap();
+            assert!((mapped.shading_normal(&rec) - rec.normal).norm() < 1e-12);
+            let expected = rec.material.scatter(&ray, &rec, &mut rng).unwrap().1;
+            let actual = mapped.scatter(&ray, &rec, &mut rng).unwrap().1;
+            assert!((actual.dir - expected.dir).norm() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn test_textured_light() {
+        // A two-pixel screen, red on the left and blue on the right
+        let screen = DiffuseLight {
+            emit: Box::new(ImageTexture::from_rgb8(2, 1, &[255, 0, 0, 0, 0, 255])),
+        };
+        let rect = XyRect {
+            x0: 0.,
+            x1: 2.,
+            y0: 0.,
+            y1: 1.,
+            k: 0.,
+            material: Arc::new(screen),
+        };
+        let emitted_at = |x: f64| {
+            let ray = Ray {
+                orig: v3(x, 0.5, 1.),
+                dir: v3(0., 0., -1.),
+                time: 0.,
+                wavelength: None,
+            };
+            let rec = rect.hit(&ray, 0.001, 100.).unwrap();
+            rec.material.emitted(&rec).0
+        };
+
+        assert_eq!(emitted_at(0.5), v3(1., 0., 0.));
+        assert_eq!(emitted_at(1.5), v3(0., 0., 1.));
+    }
+
+    #[test]
+    fn test_dispersion_splits_colors() {
+        let sphere = Sphere {
+            center: v3(0., 0., 0.),
+            radius: 1.,
+            // Dense flint glass
+            material: Arc::new(Dielectric::dispersive(1.7, 0.02)),
+        };
+        let refracted = |nm| {
+            let ray = Ray {
+                orig: v3(-0.6, 0., -5.),
+                dir: v3(0., 0., 1.),
+                time: 0.,
+                wavelength: Some(nm),
+            };
+            let rec = sphere.hit(&ray, 0.001, 100.).unwrap();
+            // Skip the seeds that pick the reflection
+            (0..)
+                .filter_map(|seed| {
+                    let mut rng = StdRng::seed_from_u64(seed);
+                    let (_, out) = rec.material.scatter(&ray, &rec, &mut rng).unwrap();
+                    Some(unit(&out.dir)).filter(|d| d.dot(&rec.normal) < 0.)
+                })
+                .next()
+                .unwrap()
+        };
+
+        let red = refracted(700.);
+        let blue = refracted(450.);
+        // Blue bends more, towards the normal at the centre
+        let angle = red.dot(&blue).min(1.).acos();
+        assert!(angle > 0.005, "{}", angle);
+        assert!(blue.x > red.x);
+    }
+}
@@ -0,0 +1,127 @@
+use std::sync::Arc;
+
+use crate::aabb::Aabb;
+use crate::geometry::{v3, Ray};
+use crate::hittable::{HitRecord, Hittable};
+use crate::material::Material;
+
+const INF: f64 = f64::INFINITY;
+
+/// Fog of uniform `density` filling `boundary`, which must be convex.
+pub struct ConstantMedium {
+    pub boundary: Box<dyn Hittable>,
+    pub density: f64,
+    pub phase: Arc<dyn Material>,
+}
+
+/// Uniform value in `(0, 1]` derived from the ray. `hit` has no RNG to draw
+/// from, and hashing keeps renders reproducible for a given seed.
+fn ray_random(ray: &Ray) -> f64 {
+    let coords = [
+        ray.orig.x, ray.orig.y, ray.orig.z, ray.dir.x, ray.dir.y, ray.dir.z, ray.time,
+    ];
+    let mut h = 0u64;
+    for c in coords.iter() {
+        // splitmix64 over each coordinate's bits
+        h = (h ^ c.to_bits()).wrapping_add(0x9e37_79b9_7f4a_7c15);
+        h = (h ^ (h >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        h = (h ^ (h >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        h ^= h >> 31;
+    }
+    ((h >> 11) + 1) as f64 / (1u64 << 53) as f64
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        // Where the ray enters and leaves the boundary, wherever it starts
+        let enter = self.boundary.hit(ray, -INF, INF)?;
+        let exit = self.boundary.hit(ray, enter.t + 0.0001, INF)?;
+
+        let t0 = enter.t.max(t_min).max(0.);
+        let t1 = exit.t.min(t_max);
+        if t0 >= t1 {
+            return None;
+        }
+
+        let ray_length = ray.dir.norm();
+        let distance_inside = (t1 - t0) * ray_length;
+        let hit_distance = -(1. / self.density) * ray_random(ray).ln();
+        if hit_distance > distance_inside {
+            return None;
+        }
+
+        // Normal and facing are meaningless inside a volume
+        let t = t0 + hit_distance / ray_length;
+        Some(HitRecord {
+            point: ray.at(t),
+            normal: v3(1., 0., 0.),
+            material: self.phase.as_ref(),
+            t,
+            u: 0.,
+            v: 0.,
+            front_face: true,
+            object_id: enter.object_id,
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::geometry::Sphere;
+    use crate::material::Isotropic;
+
+    fn fog(density: f64) -> ConstantMedium {
+        ConstantMedium {
+            boundary: Box::new(Sphere {
+                center: v3(0., 0., 0.),
+                radius: 1.,
+                material: Arc::new(Isotropic {
+                    albedo: Color(v3(1., 1., 1.)),
+                }),
+            }),
+            density,
+            phase: Arc::new(Isotropic {
+                albedo: Color(v3(1., 1., 1.)),
+            }),
+        }
+    }
+
+    // Rays from a grid of origins in front of the sphere, all aimed through it
+    fn scattered_fraction(medium: &ConstantMedium) -> f64 {
+        let n = 20;
+        let mut hits = 0;
+        for i in 0..n {
+            for j in 0..n {
+                let offset = v3(i as f64 / n as f64 - 0.5, j as f64 / n as f64 - 0.5, 0.);
+                let ray = Ray {
+                    orig: v3(0., 0., 5.) + offset,
+                    dir: v3(0., 0., -1.),
+                    time: 0.,
+                    wavelength: None,
+                };
+                if let Some(rec) = medium.hit(&ray, 0.001, INF) {
+                    assert!(rec.point.norm() <= 1. + 1e-9);
+                    hits += 1;
+                }
+            }
+        }
+        hits as f64 / (n * n) as f64
+    }
+
+    #[test]
+    fn test_dense_medium_scatters() {
+        assert!(scattered_fraction(&fog(10.)) > 0.95);
+    }
+
+    #[test]
+    fn test_thin_medium_is_mostly_clear() {
+        let fraction = scattered_fraction(&fog(0.05));
+        assert!(fraction > 0. && fraction < 0.3, "{}", fraction);
+    }
+}
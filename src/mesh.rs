@@ -0,0 +1,130 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Result};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::{
+    geometry::{v3, Point, Triangle},
+    hittable::HittableList,
+    material::Material,
+};
+
+/// Load the triangles of a Wavefront OBJ file, all sharing `material`.
+pub fn load_obj<P: AsRef<Path>>(path: P, material: Arc<dyn Material>) -> Result<HittableList> {
+    parse_obj(BufReader::new(File::open(path)?), material)
+}
+
+/// Parse OBJ vertex (`v`) and face (`f`) lines. Faces with more than three
+/// vertices are split into a triangle fan. Normals, texture coordinates and
+/// every other statement are skipped.
+pub fn parse_obj<R: BufRead>(reader: R, material: Arc<dyn Material>) -> Result<HittableList> {
+    let mut vertices: Vec<Point> = Vec::new();
+    let mut list = HittableList::new();
+
+    for (line_no, line) in reader.lines().enumerate() {
+        let line = line?;
+        let mut tokens = line.split_whitespace();
+        let invalid = |msg: &str| {
+            Error::new(
+                ErrorKind::InvalidData,
+                format!("line {}: {}", line_no + 1, msg),
+            )
+        };
+
+        match tokens.next() {
+            Some("v") => {
+                let coords = tokens
+                    .take(3)
+                    .map(|t| t.parse::<f64>())
+                    .collect::<std::result::Result<Vec<_>, _>>()
+                    .map_err(|_| invalid("bad vertex coordinate"))?;
+                if coords.len() != 3 {
+                    return Err(invalid("vertex needs three coordinates"));
+                }
+                vertices.push(v3(coords[0], coords[1], coords[2]));
+            }
+            Some("f") => {
+                let face = tokens
+                    .map(|t| face_index(t, vertices.len()))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| invalid("bad face index"))?;
+                if face.len() < 3 {
+                    return Err(invalid("face needs at least three vertices"));
+                }
+
+                for k in 1..face.len() - 1 {
+                    list.add(Arc::new(Triangle {
+                        v0: vertices[face[0]],
+                        v1: vertices[face[k]],
+                        v2: vertices[face[k + 1]],
+                        material: material.clone(),
+                    }));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(list)
+}
+
+// Resolve a face vertex reference like `3`, `3/1`, `3//2` or `-1` to a
+// zero-based index into the vertices read so far.
+fn face_index(token: &str, vertex_count: usize) -> Option<usize> {
+    let idx: i64 = token.split('/').next()?.parse().ok()?;
+    let idx = if idx < 0 {
+        vertex_count as i64 + idx
+    } else {
+        idx - 1
+    };
+
+    if (0..vertex_count as i64).contains(&idx) {
+        Some(idx as usize)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{color::Color, hittable::Hittable, material::Lambertian};
+
+    const CUBE: &str = "\
+# unit cube
+v 0 0 0
+v 1 0 0
+v 1 1 0
+v 0 1 0
+v 0 0 1
+v 1 0 1
+v 1 1 1
+v 0 1 1
+vn 0 0 -1
+vt 0 0
+f 1/1/1 4/1/1 3/1/1 2/1/1
+f 5 6 7 8
+f 1 2 6 5
+f 2 3 7 6
+f 3 4 8 7
+f -8 -4 -1 -5
+";
+
+    #[test]
+    fn test_parse_obj_cube() {
+        let material = Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5))));
+        let list = parse_obj(CUBE.as_bytes(), material).unwrap();
+
+        assert_eq!(list.list.len(), 12);
+
+        let bbox = list.bounding_box().unwrap();
+        assert!((bbox.min - v3(0., 0., 0.)).norm() < 0.01);
+        assert!((bbox.max - v3(1., 1., 1.)).norm() < 0.01);
+    }
+
+    #[test]
+    fn test_parse_obj_bad_index() {
+        let material = Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5))));
+        assert!(parse_obj("v 0 0 0\nf 1 2 3\n".as_bytes(), material).is_err());
+    }
+}
@@ -0,0 +1,72 @@
+use rand::{Rng, RngCore};
+use std::f64::consts::PI;
+
+use crate::geometry::{perpendicular_basis, unit, v3, V3};
+
+/// Cosine-weighted directions around a surface normal, the distribution a
+/// Lambertian surface scatters into.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CosinePdf {
+    // Orthonormal basis with `w` along the normal
+    u: V3,
+    v: V3,
+    w: V3,
+}
+
+impl CosinePdf {
+    pub fn new(normal: &V3) -> Self {
+        let w = unit(normal);
+        let (u, v) = perpendicular_basis(&w);
+        CosinePdf { u, v, w }
+    }
+
+    /// Density over solid angle, `cos(theta) / pi`, and zero below the surface.
+    pub fn value(&self, dir: &V3) -> f64 {
+        let cosine = unit(dir).dot(&self.w);
+        cosine.max(0.) / PI
+    }
+
+    /// A unit direction drawn with density `value`.
+    pub fn generate(&self, rng: &mut dyn RngCore) -> V3 {
+        let (r1, r2): (f64, f64) = (rng.gen(), rng.gen());
+        let phi = 2. * PI * r1;
+        let (x, y, z) = (
+            phi.cos() * r2.sqrt(),
+            phi.sin() * r2.sqrt(),
+            (1. - r2).sqrt(),
+        );
+        self.at(&v3(x, y, z))
+    }
+
+    fn at(&self, local: &V3) -> V3 {
+        local.x * self.u + local.y * self.v + local.z * self.w
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::random_unit_vec;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    #[test]
+    fn test_cosine_pdf() {
+        let normal = v3(1., 2., -0.5);
+        let pdf = CosinePdf::new(&normal);
+        let mut rng = StdRng::seed_from_u64(1);
+
+        for _ in 0..1000 {
+            let dir = pdf.generate(&mut rng);
+            assert!((dir.norm() - 1.).abs() < 1e-12);
+            assert!(dir.dot(&normal) >= 0.);
+        }
+
+        // Estimate the integral over the sphere from uniform directions
+        let n = 100_000;
+        let integral = (0..n)
+            .map(|_| 4. * PI * pdf.value(&random_unit_vec(&mut rng)))
+            .sum::<f64>()
+            / n as f64;
+        assert!((integral - 1.).abs() < 0.02, "{}", integral);
+    }
+}
@@ -0,0 +1,466 @@
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+// Largest payload a single stored (uncompressed) deflate block can carry.
+const MAX_STORED_BLOCK: usize = 0xffff;
+
+/// Encode 8-bit RGB pixel rows, given top-to-bottom, as a PNG.
+///
+/// Image data is stored uncompressed inside the zlib stream, which keeps the
+/// encoder dependency free at the cost of file size.
+/// PNGs can't be empty, so a zero width or height is an error.
+pub fn write_rgb8<W: Write>(w: &mut W, width: usize, height: usize, rows: &[u8]) -> Result<()> {
+    if width == 0 || height == 0 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "PNG needs a nonzero size",
+        ));
+    }
+    assert_eq!(rows.len(), width * height * 3);
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend(&(width as u32).to_be_bytes());
+    ihdr.extend(&(height as u32).to_be_bytes());
+    // Bit depth 8, color type 2 (RGB), default compression, filter and no interlace
+    ihdr.extend(&[8, 2, 0, 0, 0]);
+
+    // Each scanline is prefixed with its filter type, 0 for none
+    let mut raw = Vec::with_capacity(height * (width * 3 + 1));
+    for row in rows.chunks(width * 3) {
+        raw.push(0);
+        raw.extend(row);
+    }
+
+    w.write_all(&SIGNATURE)?;
+    write_chunk(w, b"IHDR", &ihdr)?;
+    write_chunk(w, b"IDAT", &zlib_stored(&raw))?;
+    write_chunk(w, b"IEND", &[])?;
+
+    Ok(())
+}
+
+fn write_chunk<W: Write>(w: &mut W, kind: &[u8; 4], data: &[u8]) -> Result<()> {
+    w.write_all(&(data.len() as u32).to_be_bytes())?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+
+    let crc = crc32(kind.iter().chain(data.iter()));
+    w.write_all(&crc.to_be_bytes())?;
+
+    Ok(())
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = (data.len() / MAX_STORED_BLOCK).max(1);
+    let mut out = Vec::with_capacity(data.len() + 5 * (blocks + 1) + 6);
+
+    // Deflate with a 32K window, no preset dictionary, fastest compression level
+    out.extend(&[0x78, 0x01]);
+
+    let mut chunks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if chunks.peek().is_none() {
+        out.extend(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+        out.push(last as u8);
+        out.extend(&len.to_le_bytes());
+        out.extend(&(!len).to_le_bytes());
+        out.extend(chunk);
+    }
+
+    out.extend(&adler32(data).to_be_bytes());
+    out
+}
+
+fn crc32<'a>(bytes: impl Iterator<Item = &'a u8>) -> u32 {
+    let mut crc = 0xffff_ffffu32;
+    for &b in bytes {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xedb8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1u32, 0u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+/// Decode an 8-bit, non-interlaced PNG into RGB pixel rows, top-to-bottom.
+///
+/// Grayscale and alpha channels are expanded or dropped to give three bytes
+/// per pixel. Palette images and 16-bit depths are not supported.
+pub fn read_rgb8<R: Read>(r: &mut R) -> Result<(usize, usize, Vec<u8>)> {
+    let mut bytes = Vec::new();
+    r.read_to_end(&mut bytes)?;
+
+    if bytes.len() < 8 || bytes[..8] != SIGNATURE {
+        return Err(invalid("missing PNG signature"));
+    }
+
+    let mut header = None;
+    let mut idat = Vec::new();
+    let mut pos = 8;
+    while pos + 8 <= bytes.len() {
+        let len = u32::from_be_bytes([bytes[pos], bytes[pos + 1], bytes[pos + 2], bytes[pos + 3]]);
+        let len = len as usize;
+        let kind = &bytes[pos + 4..pos + 8];
+        let data = bytes
+            .get(pos + 8..pos + 8 + len)
+            .ok_or_else(|| invalid("truncated chunk"))?;
+
+        match kind {
+            b"IHDR" if len == 13 => header = Some(data.to_vec()),
+            b"IDAT" => idat.extend(data),
+            b"IEND" => break,
+            _ => (),
+        }
+        pos += 12 + len;
+    }
+
+    let header = header.ok_or_else(|| invalid("missing IHDR"))?;
+    let width = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as usize;
+    let height = u32::from_be_bytes([header[4], header[5], header[6], header[7]]) as usize;
+    let (depth, color_type, interlace) = (header[8], header[9], header[12]);
+
+    if depth != 8 || interlace != 0 {
+        return Err(invalid("only 8-bit non-interlaced PNGs are supported"));
+    }
+    let channels = match color_type {
+        0 => 1,
+        2 => 3,
+        4 => 2,
+        6 => 4,
+        _ => return Err(invalid("unsupported PNG color type")),
+    };
+
+    if idat.len() < 2 || idat[0] & 0x0f != 8 || idat[1] & 0x20 != 0 {
+        return Err(invalid("bad zlib header"));
+    }
+    let raw = inflate(&idat[2..])?;
+
+    // Each scanline is its filter byte and `stride` bytes of pixels
+    let (stride, size) = width
+        .checked_mul(channels)
+        .and_then(|stride| Some((stride, stride.checked_add(1)?.checked_mul(height)?)))
+        .ok_or_else(|| invalid("PNG too large"))?;
+    if raw.len() < size {
+        return Err(invalid("not enough image data"));
+    }
+
+    let mut pixels = vec![0u8; height * stride];
+    for j in 0..height {
+        let filter = raw[j * (stride + 1)];
+        let line = &raw[j * (stride + 1) + 1..(j + 1) * (stride + 1)];
+        let (done, rest) = pixels.split_at_mut(j * stride);
+        let prev = if j > 0 {
+            &done[(j - 1) * stride..]
+        } else {
+            &[][..]
+        };
+        unfilter(filter, channels, line, prev, &mut rest[..stride])?;
+    }
+
+    let rgb = pixels
+        .chunks(channels)
+        .flat_map(|px| match channels {
+            1 | 2 => [px[0], px[0], px[0]],
+            _ => [px[0], px[1], px[2]],
+        })
+        .collect();
+
+    Ok((width, height, rgb))
+}
+
+fn unfilter(filter: u8, bpp: usize, line: &[u8], prev: &[u8], out: &mut [u8]) -> Result<()> {
+    for i in 0..line.len() {
+        let a = if i >= bpp { out[i - bpp] } else { 0 };
+        let b = prev.get(i).copied().unwrap_or(0);
+        let c = if i >= bpp {
+            prev.get(i - bpp).copied().unwrap_or(0)
+        } else {
+            0
+        };
+
+        let predicted = match filter {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            4 => paeth(a, b, c),
+            _ => return Err(invalid("unknown PNG filter")),
+        };
+        out[i] = line[i].wrapping_add(predicted);
+    }
+    Ok(())
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, msg)
+}
+
+const LEN_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LEN_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DIST_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DIST_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl BitReader<'_> {
+    fn bits(&mut self, n: u32) -> Result<u32> {
+        let mut value = 0;
+        for i in 0..n {
+            let byte = *self
+                .data
+                .get(self.pos / 8)
+                .ok_or_else(|| invalid("unexpected end of deflate stream"))?;
+            value |= (((byte >> (self.pos % 8)) & 1) as u32) << i;
+            self.pos += 1;
+        }
+        Ok(value)
+    }
+
+    fn align(&mut self) {
+        self.pos = self.pos.div_ceil(8) * 8;
+    }
+}
+
+/// Canonical Huffman code, decoded one bit at a time.
+struct Huffman {
+    counts: [u16; 16],
+    symbols: Vec<u16>,
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Self {
+        let mut counts = [0u16; 16];
+        for &len in lengths {
+            counts[len as usize] += 1;
+        }
+        counts[0] = 0;
+
+        let mut symbols = Vec::with_capacity(lengths.len());
+        for len in 1..16 {
+            for (sym, &l) in lengths.iter().enumerate() {
+                if l as usize == len {
+                    symbols.push(sym as u16);
+                }
+            }
+        }
+
+        Huffman { counts, symbols }
+    }
+
+    fn decode(&self, bits: &mut BitReader) -> Result<u16> {
+        let (mut code, mut first, mut index) = (0i32, 0i32, 0i32);
+        for len in 1..16 {
+            code |= bits.bits(1)? as i32;
+            let count = self.counts[len] as i32;
+            if code - first < count {
+                return Ok(self.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first = (first + count) << 1;
+            code <<= 1;
+        }
+        Err(invalid("bad Huffman code"))
+    }
+}
+
+fn inflate(data: &[u8]) -> Result<Vec<u8>> {
+    let mut bits = BitReader { data, pos: 0 };
+    let mut out = Vec::new();
+
+    loop {
+        let last = bits.bits(1)? == 1;
+        match bits.bits(2)? {
+            0 => {
+                bits.align();
+                let len = bits.bits(16)? as usize;
+                let _nlen = bits.bits(16)?;
+                let start = bits.pos / 8;
+                let block = data
+                    .get(start..start + len)
+                    .ok_or_else(|| invalid("truncated stored block"))?;
+                out.extend(block);
+                bits.pos += 8 * len;
+            }
+            1 => {
+                let mut lengths = [0u8; 288];
+                lengths[..144].iter_mut().for_each(|l| *l = 8);
+                lengths[144..256].iter_mut().for_each(|l| *l = 9);
+                lengths[256..280].iter_mut().for_each(|l| *l = 7);
+                lengths[280..].iter_mut().for_each(|l| *l = 8);
+                let lit = Huffman::new(&lengths);
+                let dist = Huffman::new(&[5; 30]);
+                inflate_block(&mut bits, &mut out, &lit, &dist)?;
+            }
+            2 => {
+                let (lit, dist) = read_dynamic_tables(&mut bits)?;
+                inflate_block(&mut bits, &mut out, &lit, &dist)?;
+            }
+            _ => return Err(invalid("invalid deflate block type")),
+        }
+
+        if last {
+            return Ok(out);
+        }
+    }
+}
+
+fn read_dynamic_tables(bits: &mut BitReader) -> Result<(Huffman, Huffman)> {
+    let hlit = bits.bits(5)? as usize + 257;
+    let hdist = bits.bits(5)? as usize + 1;
+    let hclen = bits.bits(4)? as usize + 4;
+
+    let mut code_lengths = [0u8; 19];
+    for &i in CODE_LENGTH_ORDER.iter().take(hclen) {
+        code_lengths[i] = bits.bits(3)? as u8;
+    }
+    let code_huffman = Huffman::new(&code_lengths);
+
+    let mut lengths = Vec::with_capacity(hlit + hdist);
+    while lengths.len() < hlit + hdist {
+        let (value, repeat) = match code_huffman.decode(bits)? {
+            sym @ 0..=15 => (sym as u8, 1),
+            16 => {
+                let prev = *lengths
+                    .last()
+                    .ok_or_else(|| invalid("repeat with no previous length"))?;
+                (prev, 3 + bits.bits(2)?)
+            }
+            17 => (0, 3 + bits.bits(3)?),
+            _ => (0, 11 + bits.bits(7)?),
+        };
+        lengths.extend(std::iter::repeat_n(value, repeat as usize));
+    }
+    if lengths.len() > hlit + hdist {
+        return Err(invalid("too many code lengths"));
+    }
+
+    Ok((
+        Huffman::new(&lengths[..hlit]),
+        Huffman::new(&lengths[hlit..]),
+    ))
+}
+
+fn inflate_block(
+    bits: &mut BitReader,
+    out: &mut Vec<u8>,
+    lit: &Huffman,
+    dist: &Huffman,
+) -> Result<()> {
+    loop {
+        let sym = lit.decode(bits)? as usize;
+        match sym {
+            0..=255 => out.push(sym as u8),
+            256 => return Ok(()),
+            _ => {
+                let idx = sym - 257;
+                if idx >= LEN_BASE.len() {
+                    return Err(invalid("bad length symbol"));
+                }
+                let len = LEN_BASE[idx] as usize + bits.bits(LEN_EXTRA[idx] as u32)? as usize;
+
+                let idx = dist.decode(bits)? as usize;
+                if idx >= DIST_BASE.len() {
+                    return Err(invalid("bad distance symbol"));
+                }
+                let distance =
+                    DIST_BASE[idx] as usize + bits.bits(DIST_EXTRA[idx] as u32)? as usize;
+                if distance > out.len() {
+                    return Err(invalid("distance too far back"));
+                }
+
+                let start = out.len() - distance;
+                for k in 0..len {
+                    out.push(out[start + k]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_crc32_known_value() {
+        // CRC of the empty IEND chunk is fixed by the spec
+        assert_eq!(crc32(b"IEND".iter()), 0xae42_6082);
+    }
+
+    #[test]
+    fn test_adler32_known_value() {
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+    }
+
+    #[test]
+    fn test_read_round_trip() {
+        let rows: Vec<u8> = (0..3 * 5 * 4).map(|i| (i * 7) as u8).collect();
+        let mut buf = Vec::new();
+        write_rgb8(&mut buf, 5, 4, &rows).unwrap();
+
+        let (w, h, decoded) = read_rgb8(&mut buf.as_slice()).unwrap();
+        assert_eq!((w, h), (5, 4));
+        assert_eq!(decoded, rows);
+    }
+
+    #[test]
+    fn test_rejects_bad_sizes() {
+        assert!(write_rgb8(&mut Vec::new(), 0, 4, &[]).is_err());
+
+        // 2^32 - 1 RGBA pixels a row overflow the scanline arithmetic
+        let mut ihdr = u32::MAX.to_be_bytes().to_vec();
+        ihdr.extend(&u32::MAX.to_be_bytes());
+        ihdr.extend(&[8, 6, 0, 0, 0]);
+        let mut buf = SIGNATURE.to_vec();
+        write_chunk(&mut buf, b"IHDR", &ihdr).unwrap();
+        write_chunk(&mut buf, b"IDAT", &zlib_stored(&[0; 16])).unwrap();
+        write_chunk(&mut buf, b"IEND", &[]).unwrap();
+        let err = read_rgb8(&mut buf.as_slice()).err().unwrap();
+        assert_eq!(err.kind(), ErrorKind::InvalidData);
+    }
+}
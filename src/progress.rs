@@ -0,0 +1,58 @@
+use std::io::{stderr, Write};
+use std::time::{Duration, Instant};
+
+const BAR_WIDTH: usize = 40;
+
+/// Single-line terminal progress bar with an ETA, drawn on stderr.
+pub struct ProgressBar {
+    start: Instant,
+    last_percent: Option<usize>,
+}
+
+impl ProgressBar {
+    pub fn new() -> Self {
+        ProgressBar {
+            start: Instant::now(),
+            last_percent: None,
+        }
+    }
+
+    pub fn update(&mut self, done: usize, total: usize) {
+        let fraction = done as f64 / total.max(1) as f64;
+        let percent = (100. * fraction) as usize;
+
+        // Only redraw when the visible percentage changes
+        if self.last_percent == Some(percent) {
+            return;
+        }
+        self.last_percent = Some(percent);
+
+        let filled = (fraction * BAR_WIDTH as f64) as usize;
+        let elapsed = self.start.elapsed();
+        let eta = if done > 0 {
+            elapsed.mul_f64((total - done) as f64 / done as f64)
+        } else {
+            Duration::ZERO
+        };
+
+        let mut err = stderr();
+        let _ = write!(
+            err,
+            "\r[{}{}] {:3}% ETA {}",
+            "#".repeat(filled),
+            " ".repeat(BAR_WIDTH - filled),
+            percent,
+            format_duration(eta),
+        );
+        let _ = err.flush();
+    }
+
+    pub fn finish(&self) {
+        eprintln!(" done in {}", format_duration(self.start.elapsed()));
+    }
+}
+
+fn format_duration(d: Duration) -> String {
+    let secs = d.as_secs();
+    format!("{:02}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
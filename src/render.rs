@@ -0,0 +1,2495 @@
+use ndarray::{s, Array2};
+use rand::prelude::*;
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::background::Background;
+use crate::camera::Camera;
+use crate::color::{Color, MAX_WAVELENGTH, MIN_WAVELENGTH};
+use crate::geometry::{unit, v3, Point, Ray};
+use crate::hittable::{HitRecord, Hittable, HittableList, BACKGROUND_ID, UNTAGGED_ID};
+use crate::image::{Accumulator, Image};
+use crate::pdf::CosinePdf;
+use crate::stats;
+
+const INF: f64 = f64::INFINITY;
+
+/// Radiance arriving along `ray`.
+///
+/// Diffuse hits also aim shadow rays at `lights`, which is separate from
+/// `world`: a light is only seen if it is in the world as well, and an object
+/// in both is both visible and importance-sampled. An empty list gives pure
+/// path tracing.
+pub fn ray_color(
+    ray: &Ray,
+    world: &impl Hittable,
+    lights: &HittableList,
+    settings: &RenderSettings,
+    rng: &mut dyn RngCore,
+) -> Color {
+    trace_primary(ray, world, lights, settings, rng).0
+}
+
+/// Like `ray_color`, but also hands back the primary hit for the AOVs.
+///
+/// Bounces are followed in a loop rather than by recursion, carrying
+/// `throughput`, the fraction of the current ray's light that reaches the
+/// camera.
+fn trace_primary<'w>(
+    ray: &Ray,
+    world: &'w impl Hittable,
+    lights: &HittableList,
+    settings: &RenderSettings,
+    rng: &mut dyn RngCore,
+) -> (Color, Option<HitRecord<'w>>) {
+    let mut radiance = Color::black();
+    let mut throughput = Color(v3(1., 1., 1.));
+    let mut primary = None;
+    let mut ray = Ray { ..*ray };
+    // Set when the last hit already sampled the lights directly, so hitting
+    // one of them now adds no emission
+    let mut sampled_lights = false;
+    // Bounces so far of each kind, indexed like `DepthBudget::caps`
+    let mut bounces = [0; 3];
+
+    for bounce in 0..settings.max_depth {
+        let t_min = settings.epsilon.t_min(&ray);
+        let Some(rec) = world.hit(&ray, t_min, INF) else {
+            radiance = radiance + throughput * settings.background.value(&ray);
+            break;
+        };
+        if primary.is_none() {
+            primary = Some(rec.clone());
+        }
+
+        if !(sampled_lights && is_light_hit(&ray, &rec, lights, t_min)) {
+            radiance = radiance + throughput * rec.material.emitted(&rec);
+        }
+        let Some((att, sc_ray)) = rec.material.scatter(&ray, &rec, rng) else {
+            break;
+        };
+
+        if let LightingMode::PointLights(point_lights) = &settings.lighting {
+            if rec.material.is_diffuse() {
+                let direct = point_lighting(&rec, att, world, point_lights, ray.time, t_min);
+                radiance = radiance + throughput * direct;
+                break;
+            }
+        }
+
+        sampled_lights = rec.material.is_diffuse() && !lights.list.is_empty();
+        if sampled_lights {
+            let direct = direct_light(&ray, &rec, att, world, lights, settings.epsilon, rng);
+            radiance = radiance + throughput * direct;
+        }
+
+        let kind = DepthBudget::kind(&rec, &sc_ray);
+        bounces[kind] += 1;
+        if settings.depth_budget.caps()[kind].is_some_and(|cap| bounces[kind] > cap) {
+            break;
+        }
+
+        // Russian roulette: dim paths are stopped at random, and survivors
+        // are boosted by the odds of surviving to keep the estimate unbiased
+        let mut weight = 1.;
+        if settings.roulette_depth.is_some_and(|start| bounce >= start) {
+            let survival = (throughput * att).0.max().min(1.);
+            if rng.gen::<f64>() >= survival {
+                break;
+            }
+            weight = 1. / survival;
+        }
+
+        throughput = throughput * att * weight;
+        ray = sc_ray;
+    }
+
+    (radiance, primary)
+}
+
+/// Whether `rec`, the closest hit along `ray` past `t_min`, lies on one of
+/// the lights that `direct_light` can pick. Lights with no `pdf_value` are
+/// never sampled, so their emission must still be picked up by bounces.
+fn is_light_hit(ray: &Ray, rec: &HitRecord, lights: &HittableList, t_min: f64) -> bool {
+    lights.list.iter().any(|light| {
+        light
+            .hit(ray, t_min, INF)
+            .is_some_and(|light_rec| (light_rec.t - rec.t).abs() < 1e-9)
+            && light.pdf_value(&ray.orig, &ray.dir) > 0.
+    })
+}
+
+/// Next-event estimation: light reaching a diffuse hit straight from a point
+/// picked on `lights`, or black if something is in the way.
+fn direct_light(
+    ray: &Ray,
+    rec: &HitRecord,
+    albedo: Color,
+    world: &impl Hittable,
+    lights: &HittableList,
+    epsilon: Epsilon,
+    rng: &mut dyn RngCore,
+) -> Color {
+    let to_light = lights.random(&rec.point, rng);
+    let pdf = lights.pdf_value(&rec.point, &to_light);
+    let cosine = unit(&to_light).dot(&rec.normal);
+    if pdf <= 0. || cosine <= 0. {
+        return Color::black();
+    }
+
+    let shadow = Ray {
+        orig: rec.point,
+        dir: to_light,
+        time: ray.time,
+        wavelength: ray.wavelength,
+    };
+    let t_min = epsilon.t_min(&shadow);
+    match world.hit(&shadow, t_min, INF) {
+        // The diffuse BRDF is albedo / pi
+        Some(light_rec) if is_light_hit(&shadow, &light_rec, lights, t_min) => {
+            albedo * light_rec.material.emitted(&light_rec) * (cosine / (PI * pdf))
+        }
+        _ => Color::black(),
+    }
+}
+
+/// Light from `point_lights` reaching the diffuse hit `rec` unobstructed,
+/// with no `1 / pi` or bounce light: the classic ray tracer's shading.
+fn point_lighting(
+    rec: &HitRecord,
+    albedo: Color,
+    world: &impl Hittable,
+    point_lights: &[PointLight],
+    time: f64,
+    t_min: f64,
+) -> Color {
+    let mut total = Color::black();
+    for light in point_lights {
+        let to_light = light.position - rec.point;
+        let cosine = unit(&to_light).dot(&rec.normal);
+        if cosine <= 0. {
+            continue;
+        }
+        // The light sits at t = 1, so any hit before it is in the way
+        let shadow = Ray {
+            orig: rec.point,
+            dir: to_light,
+            time,
+            wavelength: None,
+        };
+        if world.hit(&shadow, t_min, 1.).is_none() {
+            total = total + light.intensity * (cosine / to_light.norm_squared());
+        }
+    }
+    albedo * total
+}
+
+/// Fraction of rays from the diffuse hit `rec` blocked within `ao.radius`;
+/// 0 for other materials.
+fn ambient_occlusion(
+    rec: &HitRecord,
+    world: &impl Hittable,
+    ao: &AoConfig,
+    epsilon: Epsilon,
+    time: f64,
+    rng: &mut dyn RngCore,
+) -> f64 {
+    if !rec.material.is_diffuse() || ao.samples == 0 {
+        return 0.;
+    }
+    let pdf = CosinePdf::new(&rec.normal);
+    let blocked = (0..ao.samples)
+        .filter(|_| {
+            let probe = Ray {
+                orig: rec.point,
+                dir: pdf.generate(rng),
+                time,
+                wavelength: None,
+            };
+            // The probe direction is a unit vector, so `t` is distance
+            world
+                .hit(&probe, epsilon.t_min(&probe), ao.radius)
+                .is_some()
+        })
+        .count();
+    blocked as f64 / ao.samples as f64
+}
+
+// Mixed into the seed for the ambient occlusion probes' RNG
+const AO_SEED: u64 = 0x5eed_a0a0_5eed_a0a0;
+
+/// Each pixel draws from its own RNG seeded from `seed` and its position, so
+/// the output doesn't depend on how rows or tiles are spread over threads.
+fn pixel_rng(seed: u64, i: usize, j: usize) -> StdRng {
+    let index = (j as u64) << 32 | i as u64;
+    StdRng::seed_from_u64(seed ^ index.wrapping_mul(0x9e37_79b9_7f4a_7c15))
+}
+
+/// How sample positions are chosen within a pixel.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SamplingMode {
+    /// Every sample is jittered uniformly over the whole pixel.
+    Random,
+    /// The pixel is split into a `sqrt_samples` x `sqrt_samples` grid with
+    /// one jittered sample per cell, ignoring `RenderSettings::samples`.
+    /// `sqrt_samples` must be at least 1.
+    Stratified { sqrt_samples: usize },
+}
+
+impl SamplingMode {
+    /// Sub-pixel offsets in `[0, 1)^2` for one pixel.
+    fn offsets(&self, samples: i32, rng: &mut impl Rng) -> Vec<(f64, f64)> {
+        match *self {
+            SamplingMode::Random => (0..samples).map(|_| (rng.gen(), rng.gen())).collect(),
+            SamplingMode::Stratified { sqrt_samples: n } => {
+                assert!(n >= 1, "SamplingMode::Stratified needs sqrt_samples >= 1");
+                let cell = 1. / n as f64;
+                (0..n * n)
+                    .map(|k| {
+                        let (a, b) = ((k % n) as f64, (k / n) as f64);
+                        ((a + rng.gen::<f64>()) * cell, (b + rng.gen::<f64>()) * cell)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+/// An infinitely small light with hard shadows, for `LightingMode::PointLights`.
+#[derive(Clone, Copy, Debug)]
+pub struct PointLight {
+    pub position: Point,
+    pub intensity: Color,
+}
+
+/// How light reaches diffuse surfaces.
+#[derive(Clone, Debug)]
+pub enum LightingMode {
+    /// Full path tracing, with light sampling for the `lights` list.
+    PathTraced,
+    /// Diffuse hits are lit only directly by these lights and end the path;
+    /// mirrors and glass are still traced. Fast but not physically based:
+    /// there's no bounce light, and emitters only show where seen directly.
+    PointLights(Vec<PointLight>),
+}
+
+/// Ambient occlusion: `samples` cosine-weighted rays from each diffuse
+/// primary hit, counting those that hit something within `radius`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AoConfig {
+    pub samples: usize,
+    pub radius: f64,
+}
+
+/// Caps on the bounces of each kind along a path, on top of `max_depth`, so
+/// for example deep stacks of glass can be cut short without darkening
+/// diffuse interreflection. `None` leaves a kind unlimited.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DepthBudget {
+    pub diffuse: Option<i32>,
+    /// Reflections off anything that isn't diffuse, mirrors and glass alike.
+    pub specular: Option<i32>,
+    /// Rays passing through a surface, as refracted by glass.
+    pub transmission: Option<i32>,
+}
+
+impl DepthBudget {
+    fn caps(&self) -> [Option<i32>; 3] {
+        [self.diffuse, self.specular, self.transmission]
+    }
+
+    /// Index into `caps` of the budget that scattering `rec` into `scattered`
+    /// draws on.
+    fn kind(rec: &HitRecord, scattered: &Ray) -> usize {
+        if scattered.dir.dot(&rec.normal) < 0. {
+            2
+        } else if rec.material.is_diffuse() {
+            0
+        } else {
+            1
+        }
+    }
+}
+
+/// How far along a ray its hits must be, so a ray leaving a surface doesn't
+/// hit the same surface again through rounding error ("shadow acne").
+///
+/// Too small and surfaces get speckled with dark acne; too large and rays
+/// skip past nearby geometry, so light leaks through thin walls and contact
+/// shadows go missing. Rounding error grows with the size of the
+/// coordinates, so `Relative` suits scenes far from unit scale.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Epsilon {
+    /// The same minimum `t` for every ray.
+    Fixed(f64),
+    /// A minimum `t` of `k * (1 + |origin|)`, using the ray origin's largest
+    /// coordinate.
+    Relative(f64),
+}
+
+impl Default for Epsilon {
+    fn default() -> Self {
+        Epsilon::Fixed(0.001)
+    }
+}
+
+impl Epsilon {
+    fn t_min(&self, ray: &Ray) -> f64 {
+        match *self {
+            Epsilon::Fixed(t) => t,
+            Epsilon::Relative(k) => k * (1. + ray.orig.amax()),
+        }
+    }
+}
+
+/// Adaptive sampling: each pixel takes `min_samples`, then keeps sampling
+/// until it is confident of its luminance to within `tolerance`, or has taken
+/// `max_samples`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AdaptiveConfig {
+    pub min_samples: i32,
+    pub max_samples: i32,
+    /// Half-width of the 95% confidence interval on the pixel's mean
+    /// luminance at which it stops.
+    pub tolerance: f64,
+}
+
+/// Running mean and variance of sample luminance, by Welford's algorithm.
+#[derive(Default)]
+struct Welford {
+    count: i32,
+    mean: f64,
+    m2: f64,
+}
+
+impl Welford {
+    fn add(&mut self, x: f64) {
+        self.count += 1;
+        let delta = x - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (x - self.mean);
+    }
+
+    /// Half-width of the 95% confidence interval on the mean.
+    fn error(&self) -> f64 {
+        if self.count < 2 {
+            return INF;
+        }
+        let variance = self.m2 / (self.count - 1) as f64;
+        1.96 * (variance / self.count as f64).sqrt()
+    }
+}
+
+/// How much each sample counts towards its pixel, by where it landed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PixelFilter {
+    /// Samples cover just the pixel and all count the same.
+    Box,
+    /// Weight falls linearly to zero one pixel from the center.
+    Tent,
+    /// Bell curve `sigma` pixels wide, cut off at three sigma. `sigma` must
+    /// be positive.
+    Gaussian { sigma: f64 },
+}
+
+impl PixelFilter {
+    /// Spread an offset in `[0, 1)^2` over the filter's footprint, giving
+    /// the position relative to the pixel's corner and the sample's weight.
+    fn place(&self, du: f64, dv: f64) -> (f64, f64, f64) {
+        let radius = match *self {
+            PixelFilter::Box => return (du, dv, 1.),
+            PixelFilter::Tent => 1.,
+            PixelFilter::Gaussian { sigma } => {
+                assert!(
+                    sigma > 0.,
+                    "PixelFilter::Gaussian needs sigma > 0, got {}",
+                    sigma
+                );
+                3. * sigma
+            }
+        };
+        let (x, y) = ((2. * du - 1.) * radius, (2. * dv - 1.) * radius);
+        (0.5 + x, 0.5 + y, self.weight(x) * self.weight(y))
+    }
+
+    /// Weight at `x` pixels from the center along one axis.
+    fn weight(&self, x: f64) -> f64 {
+        match *self {
+            PixelFilter::Box => 1.,
+            PixelFilter::Tent => (1. - x.abs()).max(0.),
+            PixelFilter::Gaussian { sigma } => (-x * x / (2. * sigma * sigma)).exp(),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RenderSettings {
+    pub width: usize,
+    pub height: usize,
+    pub samples: i32,
+    pub max_depth: i32,
+    /// The same seed always gives the same image.
+    pub seed: u64,
+    pub sampling: SamplingMode,
+    pub filter: PixelFilter,
+    pub background: Background,
+    /// Bounces after which Russian roulette may end a path early, or `None`
+    /// to always trace up to `max_depth`.
+    pub roulette_depth: Option<i32>,
+    /// Samples brighter than this luminance are scaled down to it. Trades a
+    /// little bias (bright highlights lose energy) for far fewer fireflies.
+    pub firefly_clamp: Option<f64>,
+    /// Give each sample a random wavelength, so dispersive glass splits
+    /// colors. Adds colored noise but no bias.
+    pub spectral: bool,
+    pub depth_budget: DepthBudget,
+    /// Vary the samples per pixel by how noisy each pixel is, in place of
+    /// `samples` and `sampling`.
+    pub adaptive: Option<AdaptiveConfig>,
+    pub epsilon: Epsilon,
+    pub lighting: LightingMode,
+    /// Fill `Aovs::occlusion` in `render_with_aovs`; other renders ignore it.
+    pub ao: Option<AoConfig>,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            width: 400,
+            height: 225,
+            samples: 16,
+            max_depth: 50,
+            seed: 0,
+            sampling: SamplingMode::Random,
+            filter: PixelFilter::Box,
+            background: Background::default(),
+            roulette_depth: Some(5),
+            firefly_clamp: None,
+            spectral: false,
+            depth_budget: DepthBudget::default(),
+            adaptive: None,
+            epsilon: Epsilon::default(),
+            lighting: LightingMode::PathTraced,
+            ao: None,
+        }
+    }
+}
+
+fn clamp_firefly(sample: Color, threshold: Option<f64>) -> Color {
+    match threshold {
+        Some(max) if sample.luminance() > max => sample * (max / sample.luminance()),
+        _ => sample,
+    }
+}
+
+/// Per-pixel averages of everything `render_with_aovs` records.
+#[derive(Clone, Copy)]
+struct PixelAovs {
+    color: Color,
+    albedo: Color,
+    normal: Color,
+    depth: f64,
+    occlusion: f64,
+    object_id: u32,
+    samples: i32,
+}
+
+/// Trace pixel `(i, j)`, also probing occlusion with `ao` if given. The
+/// probes have their own RNG, so they don't change the beauty pass.
+fn render_pixel(
+    camera: &Camera,
+    world: &impl Hittable,
+    lights: &HittableList,
+    settings: &RenderSettings,
+    ao: Option<&AoConfig>,
+    i: usize,
+    j: usize,
+) -> PixelAovs {
+    let (width, height) = (settings.width, settings.height);
+    let mut rng = pixel_rng(settings.seed, i, j);
+    let mut ao_rng = pixel_rng(settings.seed ^ AO_SEED, i, j);
+    let mut color = Color::black();
+    let mut albedo = Color::black();
+    let mut normal = Color::black();
+    let mut depth = INF;
+    let mut occlusion = 0.;
+    let mut object_id = None;
+    let mut total_weight = 0.;
+
+    // Adds the sample at offset `(du, dv)` and returns its luminance
+    let mut sample = |du: f64, dv: f64, rng: &mut StdRng| -> f64 {
+        let (x, y, weight) = settings.filter.place(du, dv);
+        total_weight += weight;
+        let u = (i as f64 + x) / (width - 1) as f64;
+        let v = (j as f64 + y) / (height - 1) as f64;
+        // Samples outside a fisheye's image circle stay black
+        let Some(mut ray) = camera.get_ray(u, v, rng) else {
+            return 0.;
+        };
+        let mut tint = Color(v3(1., 1., 1.));
+        if settings.spectral {
+            let nm = rng.gen_range(MIN_WAVELENGTH..MAX_WAVELENGTH);
+            ray.wavelength = Some(nm);
+            tint = Color::from_wavelength(nm);
+        }
+
+        let (radiance, hit) = trace_primary(&ray, world, lights, settings, rng);
+        // The first sample picks the ID, so edge pixels get one whole object
+        object_id.get_or_insert_with(|| match &hit {
+            Some(rec) => rec.object_id.unwrap_or(UNTAGGED_ID),
+            None => BACKGROUND_ID,
+        });
+        let radiance = clamp_firefly(radiance * tint, settings.firefly_clamp);
+        color = color + radiance * weight;
+        if let Some(rec) = hit {
+            albedo = albedo + rec.material.albedo(&rec) * weight;
+            normal = normal + Color(0.5 * (rec.normal + v3(1., 1., 1.))) * weight;
+            depth = depth.min(rec.t * ray.dir.norm());
+            if let Some(ao) = ao {
+                let time = ray.time;
+                occlusion +=
+                    ambient_occlusion(&rec, world, ao, settings.epsilon, time, &mut ao_rng)
+                        * weight;
+            }
+        }
+        radiance.luminance()
+    };
+
+    let samples = match settings.adaptive {
+        None => {
+            let offsets = settings.sampling.offsets(settings.samples, &mut rng);
+            for &(du, dv) in &offsets {
+                sample(du, dv, &mut rng);
+            }
+            offsets.len() as i32
+        }
+        Some(adaptive) => {
+            let mut luminance = Welford::default();
+            while luminance.count < adaptive.max_samples
+                && (luminance.count < adaptive.min_samples
+                    || luminance.error() > adaptive.tolerance)
+            {
+                let (du, dv) = (rng.gen(), rng.gen());
+                luminance.add(sample(du, dv, &mut rng));
+            }
+            luminance.count
+        }
+    };
+
+    // Only a tent filter with every sample on its rim adds up to nothing
+    if total_weight == 0. {
+        total_weight = 1.;
+    }
+    PixelAovs {
+        color: color / total_weight,
+        albedo: albedo / total_weight,
+        normal: normal / total_weight,
+        depth,
+        occlusion: occlusion / total_weight,
+        object_id: object_id.unwrap_or(BACKGROUND_ID),
+        samples,
+    }
+}
+
+fn render_row(
+    camera: &Camera,
+    world: &impl Hittable,
+    lights: &HittableList,
+    settings: &RenderSettings,
+    j: usize,
+) -> Vec<Color> {
+    (0..settings.width)
+        .map(|i| render_pixel(camera, world, lights, settings, None, i, j).color)
+        .collect()
+}
+
+/// Render every scanline with `render_row` over all cores, calling
+/// `on_progress(rows_done, height)` as each one finishes.
+///
+/// Once `abort` is set threads stop taking new rows, and rows finishing
+/// afterwards are dropped, leaving them empty.
+fn render_rows<T: Send>(
+    height: usize,
+    abort: Option<&AtomicBool>,
+    mut on_progress: impl FnMut(usize, usize),
+    render_row: impl Fn(usize) -> Vec<T> + Sync,
+) -> Vec<Vec<T>> {
+    let threads = thread::available_parallelism().map_or(1, |n| n.get());
+    let next_row = AtomicUsize::new(0);
+    let mut rows: Vec<Vec<T>> = (0..height).map(|_| Vec::new()).collect();
+
+    thread::scope(|s| {
+        let (tx, rx) = mpsc::channel();
+
+        // Scanlines are handed out one at a time so threads that draw cheap rows
+        // (mostly sky) keep picking up work instead of idling.
+        for _ in 0..threads {
+            let tx = tx.clone();
+            let (next_row, render_row) = (&next_row, &render_row);
+            s.spawn(move || loop {
+                let j = next_row.fetch_add(1, Ordering::Relaxed);
+                if j >= height || is_set(abort) {
+                    stats::flush();
+                    return;
+                }
+                tx.send((j, render_row(j))).unwrap();
+            });
+        }
+        drop(tx);
+
+        // Progress is reported on this thread as rows finish
+        let mut done = 0;
+        for (j, row) in rx.iter() {
+            if is_set(abort) {
+                continue;
+            }
+            rows[j] = row;
+            done += 1;
+            on_progress(done, height);
+        }
+    });
+
+    rows
+}
+
+fn is_set(abort: Option<&AtomicBool>) -> bool {
+    abort.is_some_and(|flag| flag.load(Ordering::Relaxed))
+}
+
+/// Render the scene, calling `on_progress(rows_done, height)` once for each
+/// finished scanline.
+///
+/// Setting `abort` stops the render early: the image comes back with the
+/// scanlines finished so far and the rest left black.
+pub fn render(
+    camera: &Camera,
+    world: &impl Hittable,
+    lights: &HittableList,
+    settings: &RenderSettings,
+    abort: Option<&AtomicBool>,
+    on_progress: impl FnMut(usize, usize),
+) -> Image {
+    let rows = render_rows(settings.height, abort, on_progress, |j| {
+        render_row(camera, world, lights, settings, j)
+    });
+
+    let mut image = Image::new(settings.width, settings.height);
+    for (j, row) in rows.into_iter().enumerate() {
+        for (i, color) in row.into_iter().enumerate() {
+            image.img[(i, j)] = color;
+        }
+    }
+    image
+}
+
+/// Progress of `render_progressive` after one batch of samples.
+#[derive(Clone, Copy, Debug)]
+pub struct Checkpoint {
+    /// Samples per pixel so far.
+    pub samples: i32,
+    /// Mean relative change in pixel luminance since the previous
+    /// checkpoint; infinite at the first one.
+    pub change: f64,
+}
+
+/// Render up to `settings.samples` per pixel in batches of `every`, adding
+/// each batch to a running average and reporting how much it moved. With a
+/// `target_noise`, stops as soon as the change drops below it.
+pub fn render_progressive(
+    camera: &Camera,
+    world: &impl Hittable,
+    lights: &HittableList,
+    settings: &RenderSettings,
+    every: i32,
+    target_noise: Option<f64>,
+    mut on_checkpoint: impl FnMut(Checkpoint),
+) -> Image {
+    let mut acc = Accumulator::new(settings.width, settings.height);
+    let mut average = acc.current();
+    let mut done = 0;
+
+    for batch in 0.. {
+        if done >= settings.samples {
+            break;
+        }
+        let samples = every.max(1).min(settings.samples - done);
+        let batch_settings = RenderSettings {
+            samples,
+            seed: settings.seed.wrapping_add(batch),
+            ..settings.clone()
+        };
+        acc.add_samples(
+            &render(camera, world, lights, &batch_settings, None, |_, _| ()),
+            samples as u32,
+        );
+        done += samples;
+
+        let next = acc.current();
+        let change = if batch == 0 {
+            INF
+        } else {
+            relative_change(&average, &next)
+        };
+        average = next;
+
+        on_checkpoint(Checkpoint {
+            samples: done,
+            change,
+        });
+        if target_noise.is_some_and(|target| change < target) {
+            break;
+        }
+    }
+    average
+}
+
+fn relative_change(before: &Image, after: &Image) -> f64 {
+    // The floor keeps near-black pixels from dominating
+    let changes = ndarray::Zip::from(&before.img)
+        .and(&after.img)
+        .map_collect(|a, b| (b.luminance() - a.luminance()).abs() / a.luminance().max(1e-3));
+    changes.sum() / changes.len().max(1) as f64
+}
+
+/// Render only the pixels `x0..x1` by `y0..y1` (in `Image` coordinates, so
+/// `y` counts up from the bottom), leaving the rest of the full-size image
+/// black. The rendered pixels match a full `render` exactly.
+pub fn render_region(
+    camera: &Camera,
+    world: &impl Hittable,
+    lights: &HittableList,
+    settings: &RenderSettings,
+    (x0, y0): (usize, usize),
+    (x1, y1): (usize, usize),
+) -> Image {
+    let (x1, y1) = (x1.min(settings.width), y1.min(settings.height));
+    let rows = render_rows(
+        y1.saturating_sub(y0),
+        None,
+        |_, _| (),
+        |j| {
+            (x0..x1)
+                .map(|i| render_pixel(camera, world, lights, settings, None, i, y0 + j).color)
+                .collect()
+        },
+    );
+
+    let mut image = Image::new(settings.width, settings.height);
+    for (j, row) in rows.into_iter().enumerate() {
+        for (i, color) in row.into_iter().enumerate() {
+            image.img[(x0 + i, y0 + j)] = color;
+        }
+    }
+    image
+}
+
+/// Render with `settings.samples` per pixel, except that pixels on edges,
+/// found by a Sobel filter over a one-sample preview, get `edge_samples`.
+/// Samples are always placed at random; `settings.sampling` and
+/// `settings.adaptive` are ignored.
+///
+/// Cheaper than `AdaptiveConfig`'s variance tracking, and effective where
+/// silhouettes rather than lighting make the noise.
+pub fn render_edge_adaptive(
+    camera: &Camera,
+    world: &impl Hittable,
+    lights: &HittableList,
+    settings: &RenderSettings,
+    threshold: f64,
+    edge_samples: i32,
+) -> Image {
+    let preview_settings = RenderSettings {
+        samples: 1,
+        sampling: SamplingMode::Random,
+        adaptive: None,
+        ..settings.clone()
+    };
+    let preview = render(camera, world, lights, &preview_settings, None, |_, _| ());
+    let edges = preview.sobel_magnitude();
+
+    let rows = edge_adaptive_pixels(
+        camera,
+        world,
+        lights,
+        settings,
+        &edges,
+        threshold,
+        edge_samples,
+    );
+    let mut image = Image::new(settings.width, settings.height);
+    for (j, row) in rows.into_iter().enumerate() {
+        for (i, pixel) in row.into_iter().enumerate() {
+            image.img[(i, j)] = pixel.color;
+        }
+    }
+    image
+}
+
+/// The pixels of `render_edge_adaptive`, given the preview's `edges`.
+fn edge_adaptive_pixels(
+    camera: &Camera,
+    world: &impl Hittable,
+    lights: &HittableList,
+    settings: &RenderSettings,
+    edges: &Array2<f64>,
+    threshold: f64,
+    edge_samples: i32,
+) -> Vec<Vec<PixelAovs>> {
+    // Both kinds of pixel differ only in their sample count
+    let flat_settings = RenderSettings {
+        sampling: SamplingMode::Random,
+        adaptive: None,
+        ..settings.clone()
+    };
+    let edge_settings = RenderSettings {
+        samples: edge_samples,
+        ..flat_settings.clone()
+    };
+    render_rows(
+        settings.height,
+        None,
+        |_, _| (),
+        |j| {
+            (0..settings.width)
+                .map(|i| {
+                    let pixel_settings = if edges[(i, j)] > threshold {
+                        &edge_settings
+                    } else {
+                        &flat_settings
+                    };
+                    render_pixel(camera, world, lights, pixel_settings, None, i, j)
+                })
+                .collect()
+        },
+    )
+}
+
+/// The beauty pass together with auxiliary buffers taken from each pixel's
+/// primary hits, as input for denoisers.
+pub struct Aovs {
+    pub beauty: Image,
+    /// Base color of the first surface hit, unaffected by lighting.
+    pub albedo: Image,
+    /// Surface normal of the first hit encoded as `0.5 * (n + 1)`, or black
+    /// where nothing was hit.
+    pub normal: Image,
+    /// Distance to the nearest primary hit, infinite where nothing was hit.
+    /// `Image::from_depth` turns it into something viewable.
+    pub depth: Array2<f64>,
+    /// Ambient occlusion of the primary hits, from 0 in the open to 1 fully
+    /// enclosed, or all 0 unless `RenderSettings::ao` is set. Multiplying the
+    /// beauty pass by `1 - occlusion` gives stylized contact shadows.
+    pub occlusion: Array2<f64>,
+    /// `ObjectId` of each pixel's first primary hit, `UNTAGGED_ID` for
+    /// objects without one and `BACKGROUND_ID` where nothing was hit.
+    /// `Image::from_ids` colors it.
+    pub object_id: Array2<u32>,
+    /// Samples taken in each pixel, which only varies with adaptive sampling.
+    pub samples: Array2<i32>,
+}
+
+pub fn render_with_aovs(
+    camera: &Camera,
+    world: &impl Hittable,
+    lights: &HittableList,
+    settings: &RenderSettings,
+    on_progress: impl FnMut(usize, usize),
+) -> Aovs {
+    let rows = render_rows(settings.height, None, on_progress, |j| {
+        (0..settings.width)
+            .map(|i| render_pixel(camera, world, lights, settings, settings.ao.as_ref(), i, j))
+            .collect()
+    });
+
+    let (width, height) = (settings.width, settings.height);
+    let mut aovs = Aovs {
+        beauty: Image::new(width, height),
+        albedo: Image::new(width, height),
+        normal: Image::new(width, height),
+        depth: Array2::from_elem((width, height), INF),
+        occlusion: Array2::zeros((width, height)),
+        object_id: Array2::from_elem((width, height), BACKGROUND_ID),
+        samples: Array2::zeros((width, height)),
+    };
+    for (j, row) in rows.into_iter().enumerate() {
+        for (i, pixel) in row.into_iter().enumerate() {
+            aovs.beauty.img[(i, j)] = pixel.color;
+            aovs.albedo.img[(i, j)] = pixel.albedo;
+            aovs.normal.img[(i, j)] = pixel.normal;
+            aovs.depth[(i, j)] = pixel.depth;
+            aovs.occlusion[(i, j)] = pixel.occlusion;
+            aovs.object_id[(i, j)] = pixel.object_id;
+            aovs.samples[(i, j)] = pixel.samples;
+        }
+    }
+    aovs
+}
+
+/// Render the scene in `tile_size` squares pulled from a shared queue by
+/// `threads` workers. Gives the same image as `render`, and stops early on
+/// `abort` as it does, leaving unfinished tiles black. Panics if
+/// `tile_size` or `threads` is zero.
+pub fn render_tiled(
+    camera: &Camera,
+    world: &impl Hittable,
+    lights: &HittableList,
+    settings: &RenderSettings,
+    tile_size: usize,
+    threads: usize,
+    abort: Option<&AtomicBool>,
+) -> Image {
+    assert!(
+        tile_size > 0 && threads > 0,
+        "render_tiled needs a tile_size and threads above zero"
+    );
+    let (width, height) = (settings.width, settings.height);
+    let tiles: Vec<(usize, usize)> = (0..height)
+        .step_by(tile_size)
+        .flat_map(|y| (0..width).step_by(tile_size).map(move |x| (x, y)))
+        .collect();
+    let next_tile = AtomicUsize::new(0);
+    let mut image = Image::new(width, height);
+
+    thread::scope(|s| {
+        let (tx, rx) = mpsc::channel();
+
+        for _ in 0..threads {
+            let tx = tx.clone();
+            let (tiles, next_tile) = (&tiles, &next_tile);
+            s.spawn(move || loop {
+                let next = tiles.get(next_tile.fetch_add(1, Ordering::Relaxed));
+                let Some(&(x0, y0)) = next.filter(|_| !is_set(abort)) else {
+                    stats::flush();
+                    return;
+                };
+                let w = tile_size.min(width - x0);
+                let h = tile_size.min(height - y0);
+                let tile = Array2::from_shape_fn((w, h), |(i, j)| {
+                    render_pixel(camera, world, lights, settings, None, x0 + i, y0 + j).color
+                });
+                tx.send((x0, y0, tile)).unwrap();
+            });
+        }
+        drop(tx);
+
+        for (x0, y0, tile) in rx.iter() {
+            if is_set(abort) {
+                continue;
+            }
+            let (w, h) = tile.dim();
+            image
+                .img
+                .slice_mut(s![x0..x0 + w, y0..y0 + h])
+                .assign(&tile);
+        }
+    });
+
+    image
+}
+
+/// Render a side-by-side stereo pair from `stereo_pair` eyes `ipd` apart,
+/// the left eye on the left. Each eye is `settings.width` wide.
+pub fn render_stereo(
+    camera: &Camera,
+    world: &impl Hittable,
+    lights: &HittableList,
+    settings: &RenderSettings,
+    ipd: f64,
+) -> Image {
+    let (left, right) = camera.stereo_pair(ipd);
+    let width = settings.width;
+    let mut image = Image::new(2 * width, settings.height);
+    for (eye, x0) in [(left, 0), (right, width)] {
+        let view = render(&eye, world, lights, settings, None, |_, _| ());
+        image
+            .img
+            .slice_mut(s![x0..x0 + width, ..])
+            .assign(&view.img);
+    }
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bvh::Bvh;
+    use crate::color::ToneMap;
+    use crate::geometry::{Cylinder, Sphere, XzRect, V3};
+    use crate::instance::ObjectId;
+    use crate::material::{Dielectric, DiffuseLight, Lambertian, Material, Metal};
+    use crate::scene::make_world;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_bvh_matches_list() {
+        let (width, height) = (40, 22);
+        let world = make_world(&mut StdRng::seed_from_u64(1));
+        let camera = Camera::new(
+            v3(13., 2., 3.),
+            v3(0., 0., 0.),
+            v3(0., 1., 0.),
+            20.,
+            16. / 9.,
+            0.,
+            10.,
+            0.,
+            0.,
+        );
+
+        // With a pinhole camera the primary rays are deterministic
+        let primary_hits = |world: &dyn Hittable| {
+            let mut rng = StdRng::seed_from_u64(0);
+            let mut hits = Vec::with_capacity(width * height);
+            for j in 0..height {
+                for i in 0..width {
+                    let u = i as f64 / (width - 1) as f64;
+                    let v = j as f64 / (height - 1) as f64;
+                    let ray = camera.get_ray(u, v, &mut rng).unwrap();
+                    hits.push(world.hit(&ray, 0.001, INF).map(|rec| (rec.t, rec.normal)));
+                }
+            }
+            hits
+        };
+
+        let expected = primary_hits(&world);
+        let bvh = Bvh::new(world.list);
+
+        assert_eq!(primary_hits(&bvh), expected);
+    }
+
+    #[test]
+    fn test_bvh_render_matches_list() {
+        let (width, height) = (24, 14);
+        let world = make_world(&mut StdRng::seed_from_u64(2));
+        let camera = Camera::builder(v3(13., 2., 3.), v3(0., 0., 0.))
+            .vfov(20.)
+            .aspect(width as f64 / height as f64)
+            .aperture(0.1)
+            .shutter(0., 1.)
+            .build();
+        let settings = RenderSettings {
+            width,
+            height,
+            samples: 4,
+            max_depth: 10,
+            seed: 11,
+            ..Default::default()
+        };
+
+        // Whole paths, bounces and all, see the same hits through either
+        let no_lights = HittableList::new();
+        let expected = render(&camera, &world, &no_lights, &settings, None, |_, _| ());
+        let bvh = Bvh::new(world.list);
+        let image = render(&camera, &bvh, &no_lights, &settings, None, |_, _| ());
+        assert_eq!(image.img.mapv(|c| c.0), expected.img.mapv(|c| c.0));
+    }
+
+    #[test]
+    fn test_parallel_matches_serial() {
+        let (width, height) = (32, 18);
+        let samples = 64;
+
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere {
+            center: v3(0., 0., -1.),
+            radius: 0.5,
+            material: Arc::new(Metal {
+                albedo: Color(v3(0.8, 0.6, 0.2)),
+                fuzz: 0.,
+            }),
+        }));
+        let camera = Camera::new(
+            v3(0., 0., 0.),
+            v3(0., 0., -1.),
+            v3(0., 1., 0.),
+            90.,
+            16. / 9.,
+            0.,
+            1.,
+            0.,
+            0.,
+        );
+
+        let settings = RenderSettings {
+            width,
+            height,
+            samples,
+            max_depth: 10,
+            seed: 7,
+            ..Default::default()
+        };
+
+        let mut serial = Image::new(width, height);
+        for j in 0..height {
+            let row = render_row(&camera, &world, &HittableList::new(), &settings, j);
+            for (i, color) in row.into_iter().enumerate() {
+                serial.img[(i, j)] = color;
+            }
+        }
+
+        let mut calls = Vec::new();
+        let parallel = render(
+            &camera,
+            &world,
+            &HittableList::new(),
+            &settings,
+            None,
+            |done, total| calls.push((done, total)),
+        );
+
+        // Pixels are seeded independently, so thread scheduling can't change the result
+        assert!(serial
+            .img
+            .iter()
+            .zip(parallel.img.iter())
+            .all(|(a, b)| a.0 == b.0));
+
+        // Progress is reported once per scanline, counting up to the height
+        let expected: Vec<_> = (1..=height).map(|done| (done, height)).collect();
+        assert_eq!(calls, expected);
+    }
+
+    #[test]
+    fn test_ambient_occlusion() {
+        // Two spheres almost touching along the x axis
+        let mut world = HittableList::new();
+        for &x in &[-1., 1.] {
+            world.add(Arc::new(Sphere {
+                center: v3(x, 0., 0.),
+                radius: 0.99,
+                material: Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5)))),
+            }));
+        }
+        let ao = AoConfig {
+            samples: 2000,
+            radius: 0.5,
+        };
+        let mut rng = StdRng::seed_from_u64(4);
+        // Occlusion where a ray from `orig` along `dir` hits the left sphere
+        let mut occlusion_at = |orig: Point, dir: V3| {
+            let ray = Ray {
+                orig,
+                dir,
+                time: 0.,
+                wavelength: None,
+            };
+            let rec = world.list[0].hit(&ray, 0.001, INF).unwrap();
+            ambient_occlusion(&rec, &world, &ao, Epsilon::default(), 0., &mut rng)
+        };
+
+        let open = occlusion_at(v3(-1., 5., 0.), v3(0., -1., 0.));
+        assert!(open < 0.01, "{}", open);
+        // Just above the gap, facing into it
+        let angle = 20f64.to_radians();
+        let crevice = occlusion_at(
+            v3(0., 0., 5.),
+            v3(-1. + 0.99 * angle.cos(), 0.99 * angle.sin(), -5.),
+        );
+        assert!(crevice > 0.3, "{}", crevice);
+    }
+
+    #[test]
+    fn test_ao_leaves_beauty_unchanged() {
+        let world = make_world(&mut StdRng::seed_from_u64(1));
+        let camera = Camera::builder(v3(13., 2., 3.), v3(0., 0., 0.))
+            .vfov(20.)
+            .build();
+        let settings = RenderSettings {
+            width: 16,
+            height: 9,
+            samples: 2,
+            max_depth: 5,
+            ..Default::default()
+        };
+        let with_ao = RenderSettings {
+            ao: Some(AoConfig {
+                samples: 4,
+                radius: 1.,
+            }),
+            ..settings.clone()
+        };
+
+        let lights = HittableList::new();
+        let plain = render_with_aovs(&camera, &world, &lights, &settings, |_, _| ());
+        let probed = render_with_aovs(&camera, &world, &lights, &with_ao, |_, _| ());
+        assert!(probed.occlusion.iter().any(|&o| o > 0.));
+        assert_eq!(
+            probed.beauty.img.mapv(|c| c.0),
+            plain.beauty.img.mapv(|c| c.0)
+        );
+    }
+
+    #[test]
+    fn test_point_light_shadows() {
+        let mut world = HittableList::new();
+        world.add(Arc::new(XzRect {
+            x0: -5.,
+            x1: 5.,
+            z0: -5.,
+            z1: 5.,
+            k: 0.,
+            material: Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5)))),
+        }));
+        let light = PointLight {
+            position: v3(0., 4., 0.),
+            intensity: Color(v3(16., 16., 16.)),
+        };
+        let lit = |world: &HittableList, target: Point| {
+            let settings = RenderSettings {
+                background: Background::Solid(Color::black()),
+                lighting: LightingMode::PointLights(vec![light]),
+                ..Default::default()
+            };
+            let ray = Ray {
+                orig: target + v3(1., 1., 0.),
+                dir: v3(-1., -1., 0.),
+                time: 0.,
+                wavelength: None,
+            };
+            let rng = &mut StdRng::seed_from_u64(0);
+            ray_color(&ray, world, &HittableList::new(), &settings, rng).0
+        };
+
+        // Straight below: albedo * intensity * cos / distance^2
+        assert!((lit(&world, v3(0., 0., 0.)) - v3(0.5, 0.5, 0.5)).norm() < 1e-9);
+
+        world.add(Arc::new(Sphere {
+            center: v3(0., 2., 0.),
+            radius: 0.5,
+            material: Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5)))),
+        }));
+        assert_eq!(lit(&world, v3(0., 0., 0.)), v3(0., 0., 0.));
+        // Off to the side the light gets past the sphere
+        assert!(lit(&world, v3(2., 0., 0.)).x > 0.1);
+    }
+
+    #[test]
+    fn test_ray_color_diffuse_light() {
+        let emit = Color(v3(4., 2., 1.));
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere {
+            center: v3(0., 0., -2.),
+            radius: 1.,
+            material: Arc::new(DiffuseLight::solid(emit)),
+        }));
+
+        let ray = Ray {
+            orig: v3(0., 0., 0.),
+            dir: v3(0., 0., -1.),
+            time: 0.,
+            wavelength: None,
+        };
+
+        // A light absorbs whatever hits it, so only its emission comes back
+        let settings = RenderSettings {
+            max_depth: 10,
+            background: Background::Solid(Color::black()),
+            ..Default::default()
+        };
+        let col = ray_color(
+            &ray,
+            &world,
+            &HittableList::new(),
+            &settings,
+            &mut StdRng::seed_from_u64(0),
+        );
+        assert_eq!(col.0, emit.0);
+    }
+
+    #[test]
+    fn test_render_same_seed_is_identical() {
+        let (width, height) = (24, 16);
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere {
+            center: v3(0., 0., -1.),
+            radius: 0.5,
+            material: Arc::new(Lambertian::solid(Color(v3(0.7, 0.3, 0.3)))),
+        }));
+        world.add(Arc::new(Sphere {
+            center: v3(0., -100.5, -1.),
+            radius: 100.,
+            material: Arc::new(Dielectric::new(1.5)),
+        }));
+        let camera = Camera::new(
+            v3(0., 0., 0.),
+            v3(0., 0., -1.),
+            v3(0., 1., 0.),
+            90.,
+            1.5,
+            0.1,
+            1.,
+            0.,
+            1.,
+        );
+
+        let bytes = |seed| {
+            let settings = RenderSettings {
+                width,
+                height,
+                samples: 4,
+                max_depth: 10,
+                seed,
+                ..Default::default()
+            };
+            let image = render(
+                &camera,
+                &world,
+                &HittableList::new(),
+                &settings,
+                None,
+                |_, _| (),
+            );
+            let mut buf = Vec::new();
+            image
+                .write_ppm_binary(&mut buf, ToneMap::None, 2.0)
+                .unwrap();
+            buf
+        };
+
+        assert_eq!(bytes(3), bytes(3));
+        assert_ne!(bytes(3), bytes(4));
+    }
+
+    #[test]
+    fn test_stratified_covers_cells() {
+        let mut rng = StdRng::seed_from_u64(0);
+        let mode = SamplingMode::Stratified { sqrt_samples: 2 };
+
+        for _ in 0..100 {
+            let offsets = mode.offsets(16, &mut rng);
+            assert_eq!(offsets.len(), 4);
+
+            let mut cells: Vec<_> = offsets
+                .iter()
+                .map(|&(u, v)| ((u * 2.) as usize, (v * 2.) as usize))
+                .collect();
+            cells.sort_unstable();
+            assert_eq!(cells, vec![(0, 0), (0, 1), (1, 0), (1, 1)]);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "sqrt_samples >= 1")]
+    fn test_stratified_rejects_zero() {
+        let mode = SamplingMode::Stratified { sqrt_samples: 0 };
+        mode.offsets(1, &mut StdRng::seed_from_u64(0));
+    }
+
+    #[test]
+    fn test_solid_background_on_miss() {
+        let world = HittableList::new();
+        let ray = Ray {
+            orig: v3(0., 0., 0.),
+            dir: v3(0.3, 1., -1.),
+            time: 0.,
+            wavelength: None,
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+
+        let black = RenderSettings {
+            background: Background::Solid(Color::black()),
+            ..Default::default()
+        };
+        let col = ray_color(&ray, &world, &HittableList::new(), &black, &mut rng);
+        assert_eq!(col.0, Color::black().0);
+
+        // Straight up sees the top of the default sky
+        let up = Ray {
+            dir: v3(0., 1., 0.),
+            ..ray
+        };
+        let col = ray_color(
+            &up,
+            &world,
+            &HittableList::new(),
+            &RenderSettings::default(),
+            &mut rng,
+        );
+        assert_eq!(col.0, v3(0.5, 0.7, 1.0));
+    }
+
+    #[test]
+    fn test_render_mixed_primitives() {
+        use crate::geometry::{BoxPrim, Cylinder, Disk, Triangle, XzRect};
+
+        // One material and one sphere shared by the list and the BVH
+        let red: Arc<dyn Material> = Arc::new(Lambertian::solid(Color(v3(0.8, 0.1, 0.1))));
+        let sphere: Arc<dyn Hittable> = Arc::new(Sphere {
+            center: v3(0., 0., -2.),
+            radius: 0.5,
+            material: red.clone(),
+        });
+
+        let mut world = HittableList::new();
+        world.add(sphere.clone());
+        world.add(Arc::new(XzRect {
+            x0: -5.,
+            x1: 5.,
+            z0: -5.,
+            z1: 5.,
+            k: -0.5,
+            material: Arc::new(Metal {
+                albedo: Color(v3(0.5, 0.5, 0.5)),
+                fuzz: 0.2,
+            }),
+        }));
+        world.add(Arc::new(Triangle {
+            v0: v3(-2., -0.5, -3.),
+            v1: v3(-1., -0.5, -3.),
+            v2: v3(-1.5, 0.5, -3.),
+            material: red.clone(),
+        }));
+        world.add(Arc::new(BoxPrim::new(
+            v3(1., -0.5, -3.),
+            v3(1.6, 0.1, -2.4),
+            red.clone(),
+        )));
+        world.add(Arc::new(Cylinder {
+            base: v3(0., -0.5, -4.),
+            axis: v3(0., 1., 0.),
+            radius: 0.3,
+            height: 1.,
+            capped: true,
+            material: Arc::new(Dielectric::new(1.5)),
+        }));
+        world.add(Arc::new(Disk {
+            center: v3(0., 2., -2.),
+            normal: v3(0., -1., 0.),
+            radius: 1.,
+            material: Arc::new(DiffuseLight::solid(Color(v3(4., 4., 4.)))),
+        }));
+
+        let camera = Camera::new(
+            v3(0., 0., 0.),
+            v3(0., 0., -1.),
+            v3(0., 1., 0.),
+            90.,
+            1.5,
+            0.,
+            1.,
+            0.,
+            1.,
+        );
+        let settings = RenderSettings {
+            width: 24,
+            height: 16,
+            samples: 2,
+            max_depth: 5,
+            ..Default::default()
+        };
+        let image = render(
+            &camera,
+            &world,
+            &HittableList::new(),
+            &settings,
+            None,
+            |_, _| (),
+        );
+        assert!(image.img.iter().all(|c| c.0.iter().all(|x| x.is_finite())));
+
+        // The centre pixel looks straight at the shared red sphere
+        let bvh = Bvh::new(vec![sphere]);
+        let center = image.img[(12, 8)].0;
+        assert!(center.x > center.z);
+        assert!(bvh
+            .hit(
+                &camera
+                    .get_ray(0.5, 0.5, &mut StdRng::seed_from_u64(0))
+                    .unwrap(),
+                0.001,
+                INF
+            )
+            .is_some());
+    }
+
+    #[test]
+    fn test_tiled_matches_serial() {
+        let (width, height) = (37, 21);
+        let world = make_world(&mut StdRng::seed_from_u64(1));
+        let camera = Camera::new(
+            v3(13., 2., 3.),
+            v3(0., 0., 0.),
+            v3(0., 1., 0.),
+            20.,
+            width as f64 / height as f64,
+            0.1,
+            10.,
+            0.,
+            1.,
+        );
+        let settings = RenderSettings {
+            width,
+            height,
+            samples: 2,
+            max_depth: 10,
+            seed: 5,
+            ..Default::default()
+        };
+
+        // Tiles that don't divide the image exercise the ragged edges
+        let tiled = render_tiled(&camera, &world, &HittableList::new(), &settings, 8, 3, None);
+        for j in 0..height {
+            let row = render_row(&camera, &world, &HittableList::new(), &settings, j);
+            for (i, color) in row.iter().enumerate() {
+                assert_eq!(tiled.img[(i, j)].0, color.0);
+            }
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "tile_size and threads above zero")]
+    fn test_tiled_rejects_zero_tile_size() {
+        let camera = Camera::builder(v3(0., 0., 0.), v3(0., 0., -1.)).build();
+        let world = HittableList::new();
+        render_tiled(
+            &camera,
+            &world,
+            &world,
+            &RenderSettings::default(),
+            0,
+            1,
+            None,
+        );
+    }
+
+    #[test]
+    fn test_edge_adaptive_sample_counts() {
+        let camera = Camera::builder(v3(0., 0., 0.), v3(0., 0., -1.)).build();
+        let world = HittableList::new();
+        let settings = RenderSettings {
+            width: 8,
+            height: 4,
+            samples: 2,
+            sampling: SamplingMode::Stratified { sqrt_samples: 3 },
+            ..Default::default()
+        };
+        // The left half counts as edges
+        let edges = Array2::from_shape_fn((8, 4), |(i, _)| if i < 4 { 1. } else { 0. });
+
+        let rows = edge_adaptive_pixels(&camera, &world, &world, &settings, &edges, 0.5, 8);
+        for row in &rows {
+            for (i, pixel) in row.iter().enumerate() {
+                assert_eq!(pixel.samples, if i < 4 { 8 } else { 2 });
+            }
+        }
+    }
+
+    #[test]
+    fn test_roulette_is_unbiased() {
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere {
+            center: v3(0., 0., -1.),
+            radius: 0.5,
+            material: Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5)))),
+        }));
+        world.add(Arc::new(Sphere {
+            center: v3(0., -100.5, -1.),
+            radius: 100.,
+            material: Arc::new(Lambertian::solid(Color(v3(0.8, 0.8, 0.8)))),
+        }));
+        let ray = Ray {
+            orig: v3(0., 0., 0.),
+            dir: v3(0., -0.2, -1.),
+            time: 0.,
+            wavelength: None,
+        };
+
+        let mean = |roulette_depth| {
+            let settings = RenderSettings {
+                max_depth: 30,
+                roulette_depth,
+                ..Default::default()
+            };
+            let mut rng = StdRng::seed_from_u64(11);
+            let n = 20_000;
+            let total: Color = (0..n)
+                .map(|_| ray_color(&ray, &world, &HittableList::new(), &settings, &mut rng))
+                .sum();
+            total.0.sum() / (3 * n) as f64
+        };
+
+        let full = mean(None);
+        let roulette = mean(Some(1));
+        assert!(
+            (full - roulette).abs() / full < 0.03,
+            "{} vs {}",
+            full,
+            roulette
+        );
+    }
+
+    #[test]
+    fn test_firefly_clamp() {
+        let firefly = Color(v3(500., 400., 300.));
+        let clamped = clamp_firefly(firefly, Some(10.));
+        assert!((clamped.luminance() - 10.).abs() < 1e-9);
+        // Hue is kept, only brightness drops
+        assert!((clamped.0.normalize() - firefly.0.normalize()).norm() < 1e-12);
+
+        let normal = Color(v3(0.5, 0.4, 0.3));
+        assert_eq!(clamp_firefly(normal, Some(10.)).0, normal.0);
+        assert_eq!(clamp_firefly(firefly, None).0, firefly.0);
+    }
+
+    #[test]
+    fn test_albedo_aov() {
+        let red = Color(v3(0.9, 0.1, 0.1));
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere {
+            center: v3(0., 0., -1.),
+            radius: 0.5,
+            material: Arc::new(Lambertian::solid(red)),
+        }));
+        let camera = Camera::new(
+            v3(0., 0., 0.),
+            v3(0., 0., -1.),
+            v3(0., 1., 0.),
+            90.,
+            1.,
+            0.,
+            1.,
+            0.,
+            1.,
+        );
+
+        // Lit by a bright sky or none at all, the albedo stays the same
+        for background in [Background::default(), Background::Solid(Color::black())] {
+            let settings = RenderSettings {
+                width: 9,
+                height: 9,
+                samples: 4,
+                background,
+                ..Default::default()
+            };
+            let aovs =
+                render_with_aovs(&camera, &world, &HittableList::new(), &settings, |_, _| ());
+
+            assert!((aovs.albedo.img[(4, 4)].0 - red.0).norm() < 1e-12);
+            assert_eq!(aovs.albedo.img[(0, 0)].0, Color::black().0);
+            assert_eq!(
+                aovs.beauty.img[(4, 4)].0,
+                render(
+                    &camera,
+                    &world,
+                    &HittableList::new(),
+                    &settings,
+                    None,
+                    |_, _| ()
+                )
+                .img[(4, 4)]
+                    .0
+            );
+        }
+    }
+
+    #[test]
+    fn test_normal_aov() {
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere {
+            center: v3(0., 0., -2.),
+            radius: 1.5,
+            material: Arc::new(Metal::new(Color(v3(0.8, 0.8, 0.8)), 0.)),
+        }));
+        let camera = Camera::new(
+            v3(0., 0., 0.),
+            v3(0., 0., -1.),
+            v3(0., 1., 0.),
+            90.,
+            1.,
+            0.,
+            1.,
+            0.,
+            1.,
+        );
+        let settings = RenderSettings {
+            width: 65,
+            height: 65,
+            samples: 4,
+            ..Default::default()
+        };
+        let aovs = render_with_aovs(&camera, &world, &HittableList::new(), &settings, |_, _| ());
+
+        // The middle of the sphere faces straight back at the camera
+        let center = aovs.normal.img[(32, 32)].0;
+        assert!((center - v3(0.5, 0.5, 1.)).norm() < 0.05);
+        assert_eq!(aovs.normal.img[(0, 0)].0, Color::black().0);
+    }
+
+    #[test]
+    fn test_adaptive_sampling() {
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere {
+            center: v3(0., 0., -1.),
+            radius: 0.5,
+            material: Arc::new(DiffuseLight::solid(Color(v3(4., 4., 4.)))),
+        }));
+        let camera = Camera::new(
+            v3(0., 0., 0.),
+            v3(0., 0., -1.),
+            v3(0., 1., 0.),
+            90.,
+            1.,
+            0.,
+            1.,
+            0.,
+            1.,
+        );
+        let adaptive = AdaptiveConfig {
+            min_samples: 8,
+            max_samples: 256,
+            tolerance: 0.05,
+        };
+        let settings = RenderSettings {
+            width: 21,
+            height: 21,
+            background: Background::Solid(Color::black()),
+            adaptive: Some(adaptive),
+            ..Default::default()
+        };
+        let pixel =
+            |i, j| render_pixel(&camera, &world, &HittableList::new(), &settings, None, i, j);
+
+        // Empty black sky has no variance at all
+        assert_eq!(pixel(0, 0).samples, adaptive.min_samples);
+        // Pixel 15 on the middle row straddles the sphere's silhouette
+        let edge = pixel(15, 10);
+        assert!(edge.samples > 4 * adaptive.min_samples, "{}", edge.samples);
+        assert!(edge.samples <= adaptive.max_samples);
+        assert!(edge.color.0.x > 0. && edge.color.0.x < 4.);
+    }
+
+    #[test]
+    fn test_epsilon_removes_acne() {
+        // A ground sphere ten million units from the origin
+        let offset = v3(1e7, 0., 0.);
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere {
+            center: offset + v3(0., -1000., 0.),
+            radius: 1000.,
+            material: Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5)))),
+        }));
+        let camera = Camera::new(
+            offset + v3(0., 1., 0.),
+            offset + v3(10., 0., 0.),
+            v3(0., 1., 0.),
+            60.,
+            2.,
+            0.,
+            1.,
+            0.,
+            1.,
+        );
+        let roughness = |epsilon: Epsilon| {
+            let settings = RenderSettings {
+                width: 32,
+                height: 16,
+                samples: 8,
+                max_depth: 10,
+                epsilon,
+                ..Default::default()
+            };
+            let image = render(
+                &camera,
+                &world,
+                &HittableList::new(),
+                &settings,
+                None,
+                |_, _| (),
+            );
+            // Mean difference between neighbouring pixels on the ground
+            let mut total = 0.;
+            for j in 0..6 {
+                for i in 1..32 {
+                    total +=
+                        (image.img[(i, j)].luminance() - image.img[(i - 1, j)].luminance()).abs();
+                }
+            }
+            total / (6. * 31.)
+        };
+
+        // Rounding error out here is around 1e-9, so rays leaving the ground
+        // often hit it again and speckle it dark
+        let acne = roughness(Epsilon::Fixed(1e-12));
+        let clean = roughness(Epsilon::Relative(1e-9));
+        assert!(acne > 4. * clean, "{} vs {}", acne, clean);
+    }
+
+    #[test]
+    fn test_object_id_aov() {
+        let mut world = HittableList::new();
+        for (id, x) in [(7, -1.), (9, 1.)] {
+            world.add(Arc::new(ObjectId {
+                object: Box::new(Sphere {
+                    center: v3(x, 0., -2.),
+                    radius: 0.5,
+                    material: Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5)))),
+                }),
+                id,
+            }));
+        }
+        let camera = Camera::new(
+            v3(0., 0., 0.),
+            v3(0., 0., -1.),
+            v3(0., 1., 0.),
+            90.,
+            2.,
+            0.,
+            1.,
+            0.,
+            1.,
+        );
+        let settings = RenderSettings {
+            width: 64,
+            height: 32,
+            samples: 1,
+            ..Default::default()
+        };
+        let aovs = render_with_aovs(&camera, &world, &HittableList::new(), &settings, |_, _| ());
+
+        // Pixel columns through the sphere centres
+        assert_eq!(aovs.object_id[(24, 16)], 7);
+        assert_eq!(aovs.object_id[(39, 16)], 9);
+        assert_eq!(aovs.object_id[(32, 31)], BACKGROUND_ID);
+
+        let colors = Image::from_ids(&aovs.object_id);
+        assert_ne!(colors.img[(24, 16)].0, colors.img[(39, 16)].0);
+        assert_eq!(colors.img[(32, 31)].0, v3(0., 0., 0.));
+    }
+
+    #[test]
+    fn test_depth_aov() {
+        let mut world = HittableList::new();
+        for &(x, z) in &[(-1., -2.), (1., -5.)] {
+            world.add(Arc::new(Sphere {
+                center: v3(x, 0., z),
+                radius: 0.5,
+                material: Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5)))),
+            }));
+        }
+        let camera = Camera::new(
+            v3(0., 0., 0.),
+            v3(0., 0., -1.),
+            v3(0., 1., 0.),
+            90.,
+            2.,
+            0.,
+            1.,
+            0.,
+            1.,
+        );
+        let settings = RenderSettings {
+            width: 64,
+            height: 32,
+            samples: 1,
+            ..Default::default()
+        };
+        let aovs = render_with_aovs(&camera, &world, &HittableList::new(), &settings, |_, _| ());
+
+        // Pixel columns through each sphere's centre, at mid height
+        let column = |x: f64, z: f64| ((0.5 + x / (-z * 4.)) * 63.).round() as usize;
+        let near = aovs.depth[(column(-1., -2.), 16)];
+        let far = aovs.depth[(column(1., -5.), 16)];
+        // The front of the near sphere is 1.5 away, and a bit more off-centre
+        assert!((1.5..2.).contains(&near), "{}", near);
+        assert!(near < far && far.is_finite());
+        assert_eq!(aovs.depth[(32, 31)], INF);
+
+        let gray = Image::from_depth(&aovs.depth);
+        assert!(gray.img[(column(-1., -2.), 16)].0.x > gray.img[(column(1., -5.), 16)].0.x);
+    }
+
+    #[test]
+    fn test_abort_keeps_finished_rows() {
+        let camera = Camera::new(
+            v3(0., 0., 0.),
+            v3(0., 0., -1.),
+            v3(0., 1., 0.),
+            90.,
+            2.,
+            0.,
+            1.,
+            0.,
+            1.,
+        );
+        let settings = RenderSettings {
+            width: 16,
+            height: 8,
+            samples: 2,
+            ..Default::default()
+        };
+        // The sky alone is never black
+        let world = HittableList::new();
+        let abort = AtomicBool::new(false);
+
+        let image = render(&camera, &world, &world, &settings, Some(&abort), |_, _| {
+            abort.store(true, Ordering::Relaxed)
+        });
+        let finished: Vec<usize> = (0..settings.height)
+            .filter(|&j| (0..settings.width).all(|i| image.img[(i, j)].luminance() > 0.))
+            .collect();
+        assert_eq!(finished.len(), 1);
+        let blank = (0..settings.width)
+            .flat_map(|i| (0..settings.height).map(move |j| (i, j)))
+            .filter(|&(_, j)| j != finished[0]);
+        for p in blank {
+            assert_eq!(image.img[p].0, v3(0., 0., 0.));
+        }
+    }
+
+    #[test]
+    fn test_render_region() {
+        let world = make_world(&mut StdRng::seed_from_u64(2));
+        let camera = Camera::new(
+            v3(13., 2., 3.),
+            v3(0., 0., 0.),
+            v3(0., 1., 0.),
+            20.,
+            1.5,
+            0.1,
+            10.,
+            0.,
+            1.,
+        );
+        let settings = RenderSettings {
+            width: 24,
+            height: 16,
+            samples: 2,
+            max_depth: 5,
+            ..Default::default()
+        };
+
+        let full = render(
+            &camera,
+            &world,
+            &HittableList::new(),
+            &settings,
+            None,
+            |_, _| (),
+        );
+        let region = render_region(
+            &camera,
+            &world,
+            &HittableList::new(),
+            &settings,
+            (5, 3),
+            (17, 11),
+        );
+        for ((i, j), color) in region.img.indexed_iter() {
+            if (5..17).contains(&i) && (3..11).contains(&j) {
+                assert_eq!(color.0, full.img[(i, j)].0);
+            } else {
+                assert_eq!(color.0, Color::black().0);
+            }
+        }
+    }
+
+    #[test]
+    fn test_progressive_convergence() {
+        let camera = Camera::new(
+            v3(0., 0., 0.),
+            v3(0., 0., -1.),
+            v3(0., 1., 0.),
+            90.,
+            1.,
+            0.,
+            1.,
+            0.,
+            1.,
+        );
+        let settings = RenderSettings {
+            width: 8,
+            height: 8,
+            samples: 64,
+            background: Background::Solid(Color(v3(0.3, 0.6, 0.9))),
+            ..Default::default()
+        };
+
+        // Nothing to hit and a flat sky: the first comparison already agrees
+        let mut checkpoints = Vec::new();
+        let image = render_progressive(
+            &camera,
+            &HittableList::new(),
+            &HittableList::new(),
+            &settings,
+            4,
+            Some(1e-6),
+            |c| checkpoints.push(c),
+        );
+        assert_eq!(checkpoints.len(), 2);
+        assert_eq!(checkpoints[0].change, INF);
+        assert_eq!((checkpoints[1].samples, checkpoints[1].change), (8, 0.));
+        assert!((image.img[(3, 3)].0 - v3(0.3, 0.6, 0.9)).norm() < 1e-12);
+
+        // Without a target every sample is taken
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere {
+            center: v3(0., 0., -1.),
+            radius: 0.5,
+            material: Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5)))),
+        }));
+        let mut last = None;
+        render_progressive(
+            &camera,
+            &world,
+            &HittableList::new(),
+            &settings,
+            16,
+            None,
+            |c| last = Some(c),
+        );
+        let last = last.unwrap();
+        assert_eq!(last.samples, 64);
+        assert!(last.change > 0. && last.change < 1.);
+    }
+
+    #[test]
+    fn test_pixel_filters() {
+        let world = make_world(&mut StdRng::seed_from_u64(4));
+        let camera = Camera::new(
+            v3(13., 2., 3.),
+            v3(0., 0., 0.),
+            v3(0., 1., 0.),
+            20.,
+            1.5,
+            0.1,
+            10.,
+            0.,
+            1.,
+        );
+        let settings = RenderSettings {
+            width: 12,
+            height: 8,
+            samples: 6,
+            max_depth: 5,
+            ..Default::default()
+        };
+
+        // The box filter is a plain average of jittered samples
+        for (i, j) in [(0, 0), (5, 3), (11, 7)] {
+            let mut rng = pixel_rng(settings.seed, i, j);
+            let offsets = settings.sampling.offsets(settings.samples, &mut rng);
+            let mut sum = Color::black();
+            for &(du, dv) in &offsets {
+                let u = (i as f64 + du) / 11.;
+                let v = (j as f64 + dv) / 7.;
+                let ray = camera.get_ray(u, v, &mut rng).unwrap();
+                sum = sum + ray_color(&ray, &world, &HittableList::new(), &settings, &mut rng);
+            }
+            let pixel = render_pixel(&camera, &world, &HittableList::new(), &settings, None, i, j);
+            assert_eq!(pixel.color.0, (sum / offsets.len() as f64).0);
+        }
+
+        // Weights are normalized, so a flat sky stays flat under any filter
+        let sky = Color(v3(0.2, 0.4, 0.8));
+        for filter in [PixelFilter::Tent, PixelFilter::Gaussian { sigma: 0.5 }] {
+            let settings = RenderSettings {
+                filter,
+                background: Background::Solid(sky),
+                ..settings.clone()
+            };
+            let pixel = render_pixel(
+                &camera,
+                &HittableList::new(),
+                &HittableList::new(),
+                &settings,
+                None,
+                5,
+                3,
+            );
+            assert!((pixel.color.0 - sky.0).norm() < 1e-12);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "sigma > 0, got 0")]
+    fn test_gaussian_filter_rejects_zero_sigma() {
+        PixelFilter::Gaussian { sigma: 0. }.place(0.5, 0.5);
+    }
+
+    #[test]
+    fn test_accumulated_passes_match_single_render() {
+        let world = Bvh::new(make_world(&mut StdRng::seed_from_u64(6)).list);
+        let camera = Camera::new(
+            v3(13., 2., 3.),
+            v3(0., 0., 0.),
+            v3(0., 1., 0.),
+            20.,
+            1.5,
+            0.1,
+            10.,
+            0.,
+            1.,
+        );
+        let settings = RenderSettings {
+            width: 24,
+            height: 16,
+            samples: 4,
+            max_depth: 4,
+            ..Default::default()
+        };
+
+        let mut acc = Accumulator::new(24, 16);
+        for pass in 0..4 {
+            let pass_settings = RenderSettings {
+                samples: 1,
+                seed: 100 + pass,
+                ..settings.clone()
+            };
+            acc.add_sample(&render(
+                &camera,
+                &world,
+                &HittableList::new(),
+                &pass_settings,
+                None,
+                |_, _| (),
+            ));
+        }
+        assert_eq!(acc.samples(), 4);
+
+        let single = render(
+            &camera,
+            &world,
+            &HittableList::new(),
+            &settings,
+            None,
+            |_, _| (),
+        );
+        let (mse, _) = acc.current().diff(&single).unwrap();
+        let mean = |image: &Image| image.img.iter().map(|c| c.luminance()).sum::<f64>() / 384.;
+        assert!(mse < 0.05, "{}", mse);
+        assert!((mean(&acc.current()) - mean(&single)).abs() < 0.02);
+    }
+
+    #[test]
+    fn test_ray_color_sky() {
+        let settings = RenderSettings::default();
+        let mut rng = StdRng::seed_from_u64(0);
+        let ray = |dir| Ray {
+            orig: v3(0., 0., 0.),
+            dir,
+            time: 0.,
+            wavelength: None,
+        };
+
+        // Straight up is the top of the gradient, level is halfway
+        let up = ray_color(
+            &ray(v3(0., 2., 0.)),
+            &HittableList::new(),
+            &HittableList::new(),
+            &settings,
+            &mut rng,
+        );
+        assert!((up.0 - v3(0.5, 0.7, 1.0)).norm() < 1e-12);
+        let level = ray_color(
+            &ray(v3(1., 0., 0.)),
+            &HittableList::new(),
+            &HittableList::new(),
+            &settings,
+            &mut rng,
+        );
+        assert!((level.0 - v3(0.75, 0.85, 1.0)).norm() < 1e-12);
+    }
+
+    #[test]
+    fn test_ray_color_black_sphere() {
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere {
+            center: v3(0., 0., -2.),
+            radius: 1.,
+            material: Arc::new(Lambertian::solid(Color::black())),
+        }));
+        let ray = Ray {
+            orig: v3(0., 0., 0.),
+            dir: v3(0., 0., -1.),
+            time: 0.,
+            wavelength: None,
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+
+        for max_depth in [0, 1, 5] {
+            let settings = RenderSettings {
+                max_depth,
+                ..Default::default()
+            };
+            let color = ray_color(&ray, &world, &HittableList::new(), &settings, &mut rng);
+            assert_eq!(color.0, Color::black().0);
+        }
+    }
+
+    #[test]
+    fn test_ray_color_mirror_reflects_sky() {
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere {
+            center: v3(0., 0., -2.),
+            radius: 1.,
+            material: Arc::new(Metal::new(Color(v3(0.8, 0.8, 0.8)), 0.)),
+        }));
+        let settings = RenderSettings::default();
+        let mut rng = StdRng::seed_from_u64(0);
+
+        // Hitting the mirror head on sends the ray straight back, level
+        let ray = Ray {
+            orig: v3(0., 0., 0.),
+            dir: v3(0., 0., -1.),
+            time: 0.,
+            wavelength: None,
+        };
+        let color = ray_color(&ray, &world, &HittableList::new(), &settings, &mut rng);
+        assert!((color.0 - 0.8 * v3(0.75, 0.85, 1.0)).norm() < 1e-12);
+    }
+
+    #[test]
+    fn test_light_sampling_reduces_variance() {
+        let light: Arc<dyn Hittable> = Arc::new(XzRect {
+            x0: -0.25,
+            x1: 0.25,
+            z0: -0.25,
+            z1: 0.25,
+            k: 2.,
+            material: Arc::new(DiffuseLight::solid(Color(v3(20., 20., 20.)))),
+        });
+        let mut world = HittableList::new();
+        world.add(Arc::new(XzRect {
+            x0: -10.,
+            x1: 10.,
+            z0: -10.,
+            z1: 10.,
+            k: 0.,
+            material: Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5)))),
+        }));
+        world.add(light.clone());
+        let ray = Ray {
+            orig: v3(0., 1., 0.),
+            dir: v3(0.1, -1., 0.),
+            time: 0.,
+            wavelength: None,
+        };
+
+        // Mean and variance of the red channel over many samples
+        let stats = |lights: HittableList| {
+            let settings = RenderSettings {
+                max_depth: 2,
+                background: Background::Solid(Color::black()),
+                roulette_depth: None,
+                ..Default::default()
+            };
+            let mut rng = StdRng::seed_from_u64(5);
+            let n = 20_000;
+            let samples: Vec<f64> = (0..n)
+                .map(|_| ray_color(&ray, &world, &lights, &settings, &mut rng).0.x)
+                .collect();
+            let mean = samples.iter().sum::<f64>() / n as f64;
+            let var = samples.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n as f64;
+            (mean, var)
+        };
+
+        let (plain_mean, plain_var) = stats(HittableList::new());
+        let mut lights = HittableList::new();
+        lights.add(light);
+        let (nee_mean, nee_var) = stats(lights);
+
+        assert!(
+            (plain_mean - nee_mean).abs() / nee_mean < 0.15,
+            "{} vs {}",
+            plain_mean,
+            nee_mean
+        );
+        assert!(nee_var * 10. < plain_var, "{} vs {}", nee_var, plain_var);
+    }
+
+    #[test]
+    fn test_white_furnace() {
+        // A convex object in a uniform white environment: every bounce off a
+        // diffuse sphere escapes, and clear glass loses nothing, so each
+        // sample is exactly the albedo or 1
+        let white = Background::Solid(Color(v3(1., 1., 1.)));
+        let samples = |material: Arc<dyn Material>, center: V3, settings: &RenderSettings| {
+            let mut world = HittableList::new();
+            world.add(Arc::new(Sphere {
+                center,
+                radius: 1.,
+                material,
+            }));
+            let mut rng = StdRng::seed_from_u64(9);
+            (0..200)
+                .map(|k| {
+                    let ray = Ray {
+                        orig: center + v3(0., 0., 5.),
+                        dir: v3(
+                            (k % 10) as f64 * 0.02 - 0.1,
+                            (k / 20) as f64 * 0.02 - 0.1,
+                            -1.,
+                        ),
+                        time: 0.,
+                        wavelength: None,
+                    };
+                    ray_color(&ray, &world, &HittableList::new(), settings, &mut rng)
+                        .0
+                        .x
+                })
+                .collect::<Vec<_>>()
+        };
+        let diffuse =
+            || -> Arc<dyn Material> { Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5)))) };
+        let glass = || -> Arc<dyn Material> { Arc::new(Dielectric::new(1.5)) };
+        let origin = v3(0., 0., 0.);
+        let far = v3(1000., -500., 2000.);
+
+        let settings = RenderSettings {
+            background: white,
+            ..Default::default()
+        };
+        let relative = RenderSettings {
+            epsilon: Epsilon::Relative(1e-6),
+            ..settings.clone()
+        };
+        for (center, settings) in [(origin, &settings), (far, &relative)] {
+            assert!(samples(diffuse(), center, settings)
+                .iter()
+                .all(|&x| (x - 0.5).abs() < 1e-12));
+            assert!(samples(glass(), center, settings)
+                .iter()
+                .all(|&x| (x - 1.).abs() < 1e-12));
+        }
+
+        // No diffuse bounces allowed: the sphere goes black
+        let no_diffuse = RenderSettings {
+            depth_budget: DepthBudget {
+                diffuse: Some(0),
+                ..Default::default()
+            },
+            ..settings.clone()
+        };
+        assert!(samples(diffuse(), origin, &no_diffuse)
+            .iter()
+            .all(|&x| x == 0.));
+        // Nor through glass: only the outer reflections are left
+        let no_transmission = RenderSettings {
+            depth_budget: DepthBudget {
+                transmission: Some(0),
+                ..Default::default()
+            },
+            ..settings.clone()
+        };
+        let reflected = samples(glass(), origin, &no_transmission);
+        assert!(reflected.iter().all(|&x| x == 0. || (x - 1.).abs() < 1e-12));
+        let mean = reflected.iter().sum::<f64>() / reflected.len() as f64;
+        assert!(mean < 0.2, "{}", mean);
+    }
+
+    #[test]
+    fn test_unsampleable_light_still_emits() {
+        // A floor inside a closed glowing cylinder: every bounce off the
+        // floor reaches the cylinder, which `direct_light` can't sample
+        let light = Arc::new(Cylinder {
+            base: v3(0., -50., 0.),
+            axis: v3(0., 1., 0.),
+            radius: 100.,
+            height: 100.,
+            capped: true,
+            material: Arc::new(DiffuseLight::solid(Color(v3(1., 1., 1.)))),
+        });
+        let mut world = HittableList::new();
+        world.add(Arc::new(XzRect {
+            x0: -10.,
+            x1: 10.,
+            z0: -10.,
+            z1: 10.,
+            k: 0.,
+            material: Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5)))),
+        }));
+        world.add(light.clone());
+        let mut lights = HittableList::new();
+        lights.add(light);
+
+        let settings = RenderSettings {
+            max_depth: 2,
+            ..Default::default()
+        };
+        let ray = Ray {
+            orig: v3(0., 1., 0.),
+            dir: v3(0., -1., 0.),
+            time: 0.,
+            wavelength: None,
+        };
+        let mut rng = StdRng::seed_from_u64(0);
+        let color = ray_color(&ray, &world, &lights, &settings, &mut rng);
+        assert!((color.0 - v3(0.5, 0.5, 0.5)).norm() < 1e-9, "{:?}", color.0);
+    }
+
+    #[test]
+    fn test_no_transmission_budget_blocks_glass() {
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere {
+            center: v3(0., 0., 0.),
+            radius: 1.,
+            material: Arc::new(Dielectric::new(1.5)),
+        }));
+        let ray = Ray {
+            orig: v3(0., 0., 5.),
+            dir: v3(0., 0., -1.),
+            time: 0.,
+            wavelength: None,
+        };
+        // Mean brightness seen straight through the middle of the ball
+        let mean = |depth_budget| {
+            let settings = RenderSettings {
+                background: Background::Solid(Color(v3(1., 1., 1.))),
+                depth_budget,
+                ..Default::default()
+            };
+            let mut rng = StdRng::seed_from_u64(2);
+            let n = 1000;
+            let sum: Color = (0..n)
+                .map(|_| ray_color(&ray, &world, &HittableList::new(), &settings, &mut rng))
+                .sum();
+            (sum / n as f64).0.x
+        };
+
+        let clear = mean(DepthBudget::default());
+        let opaque = mean(DepthBudget {
+            transmission: Some(0),
+            ..Default::default()
+        });
+        assert!(clear > 0.95, "{}", clear);
+        // Only the few percent reflected off the front remain
+        assert!(opaque < 0.1, "{}", opaque);
+    }
+
+    #[test]
+    fn test_stereo_halves() {
+        let camera = Camera::builder(v3(0., 0., 0.), v3(0., 0., -1.))
+            .aspect(1.)
+            .build();
+        let settings = RenderSettings {
+            width: 16,
+            height: 16,
+            samples: 2,
+            ..Default::default()
+        };
+        // Largest difference between the two eyes' views
+        let eye_difference = |world: &HittableList| {
+            let image = render_stereo(&camera, world, &HittableList::new(), &settings, 0.1);
+            assert_eq!((image.width(), image.height()), (32, 16));
+            let left = image.img.slice(s![..16, ..]);
+            let right = image.img.slice(s![16.., ..]);
+            left.iter()
+                .zip(right.iter())
+                .map(|(l, r)| (l.0 - r.0).amax())
+                .fold(0., f64::max)
+        };
+
+        // The sky is infinitely far away, so both eyes see the same
+        assert!(eye_difference(&HittableList::new()) < 1e-9);
+
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere {
+            center: v3(0.3, 0.2, -1.5),
+            radius: 0.3,
+            material: Arc::new(Lambertian::solid(Color(v3(0.8, 0.1, 0.1)))),
+        }));
+        assert!(eye_difference(&world) > 0.1);
+    }
+}
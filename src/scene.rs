@@ -0,0 +1,583 @@
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+use std::path::Path;
+use std::sync::Arc;
+
+use rand::Rng;
+
+use crate::{
+    camera::Camera,
+    color::Color,
+    geometry::{rand_in, v3, Point, Sphere, V3},
+    hittable::{Hittable, HittableList},
+    json::Json,
+    material::{Dielectric, Lambertian, Material, Metal},
+    texture::CheckerTexture,
+};
+
+/// Scene description as stored in a JSON scene file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SceneDesc {
+    pub camera: CameraDesc,
+    pub objects: Vec<ObjectDesc>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct CameraDesc {
+    pub lookfrom: Point,
+    pub lookat: Point,
+    pub vup: V3,
+    pub vfov: f64,
+    pub aspect: f64,
+    pub aperture: f64,
+    /// Defaults to the distance between `lookfrom` and `lookat`.
+    pub focus_dist: Option<f64>,
+    pub time0: f64,
+    pub time1: f64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ObjectDesc {
+    Sphere {
+        center: Point,
+        radius: f64,
+        material: MaterialDesc,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum MaterialDesc {
+    Lambertian { albedo: V3 },
+    Metal { albedo: V3, fuzz: f64 },
+    Dielectric { ir: f64 },
+}
+
+/// Read a JSON scene file and build its camera and world.
+pub fn load_scene<P: AsRef<Path>>(path: P) -> Result<(Camera, HittableList)> {
+    let text = fs::read_to_string(path)?;
+    let desc = SceneDesc::from_json(&Json::parse(&text)?)?;
+    Ok(desc.build())
+}
+
+fn invalid(msg: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, format!("scene: {}", msg))
+}
+
+fn field<'a>(obj: &'a Json, key: &str) -> Result<&'a Json> {
+    obj.get(key)
+        .ok_or_else(|| invalid(&format!("missing field '{}'", key)))
+}
+
+fn number(obj: &Json, key: &str) -> Result<f64> {
+    field(obj, key)?
+        .as_f64()
+        .ok_or_else(|| invalid(&format!("'{}' must be a number", key)))
+}
+
+fn number_or(obj: &Json, key: &str, default: f64) -> Result<f64> {
+    match obj.get(key) {
+        Some(_) => number(obj, key),
+        None => Ok(default),
+    }
+}
+
+fn vector(obj: &Json, key: &str) -> Result<V3> {
+    let items = field(obj, key)?.as_array();
+    match items.map(|a| a.iter().map(Json::as_f64).collect::<Option<Vec<_>>>()) {
+        Some(Some(c)) if c.len() == 3 => Ok(v3(c[0], c[1], c[2])),
+        _ => Err(invalid(&format!(
+            "'{}' must be an array of three numbers",
+            key
+        ))),
+    }
+}
+
+/// A color as three linear components, or a `#rrggbb` string.
+fn color(obj: &Json, key: &str) -> Result<V3> {
+    match field(obj, key)?.as_str() {
+        Some(hex) => Color::from_hex(hex).map(|c| c.0).map_err(|e| invalid(&e)),
+        None => vector(obj, key),
+    }
+}
+
+fn kind(obj: &Json) -> Result<&str> {
+    field(obj, "type")?
+        .as_str()
+        .ok_or_else(|| invalid("'type' must be a string"))
+}
+
+fn vector_json(v: &V3) -> Json {
+    Json::Array(vec![
+        Json::Number(v.x),
+        Json::Number(v.y),
+        Json::Number(v.z),
+    ])
+}
+
+fn object_json(fields: Vec<(&str, Json)>) -> Json {
+    Json::Object(
+        fields
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect(),
+    )
+}
+
+impl SceneDesc {
+    pub fn from_json(json: &Json) -> Result<Self> {
+        let camera = CameraDesc::from_json(field(json, "camera")?)?;
+        let objects = field(json, "objects")?
+            .as_array()
+            .ok_or_else(|| invalid("'objects' must be an array"))?
+            .iter()
+            .map(ObjectDesc::from_json)
+            .collect::<Result<_>>()?;
+
+        Ok(SceneDesc { camera, objects })
+    }
+
+    pub fn to_json(&self) -> Json {
+        object_json(vec![
+            ("camera", self.camera.to_json()),
+            (
+                "objects",
+                Json::Array(self.objects.iter().map(ObjectDesc::to_json).collect()),
+            ),
+        ])
+    }
+
+    pub fn build(&self) -> (Camera, HittableList) {
+        let mut world = HittableList::new();
+        for object in &self.objects {
+            world.add(object.build());
+        }
+        (self.camera.build(), world)
+    }
+}
+
+impl CameraDesc {
+    fn from_json(json: &Json) -> Result<Self> {
+        let focus_dist = match json.get("focus_dist") {
+            Some(_) => Some(number(json, "focus_dist")?),
+            None => None,
+        };
+
+        Ok(CameraDesc {
+            lookfrom: vector(json, "lookfrom")?,
+            lookat: vector(json, "lookat")?,
+            vup: vector(json, "vup")?,
+            vfov: number(json, "vfov")?,
+            aspect: number(json, "aspect")?,
+            aperture: number_or(json, "aperture", 0.)?,
+            focus_dist,
+            time0: number_or(json, "time0", 0.)?,
+            time1: number_or(json, "time1", 0.)?,
+        })
+    }
+
+    fn to_json(&self) -> Json {
+        let mut fields = vec![
+            ("lookfrom", vector_json(&self.lookfrom)),
+            ("lookat", vector_json(&self.lookat)),
+            ("vup", vector_json(&self.vup)),
+            ("vfov", Json::Number(self.vfov)),
+            ("aspect", Json::Number(self.aspect)),
+            ("aperture", Json::Number(self.aperture)),
+            ("time0", Json::Number(self.time0)),
+            ("time1", Json::Number(self.time1)),
+        ];
+        if let Some(focus_dist) = self.focus_dist {
+            fields.push(("focus_dist", Json::Number(focus_dist)));
+        }
+        object_json(fields)
+    }
+
+    pub fn build(&self) -> Camera {
+        let focus_dist = self
+            .focus_dist
+            .unwrap_or_else(|| (self.lookfrom - self.lookat).norm());
+
+        Camera::new(
+            self.lookfrom,
+            self.lookat,
+            self.vup,
+            self.vfov,
+            self.aspect,
+            self.aperture,
+            focus_dist,
+            self.time0,
+            self.time1,
+        )
+    }
+}
+
+impl ObjectDesc {
+    fn from_json(json: &Json) -> Result<Self> {
+        match kind(json)? {
+            "sphere" => Ok(ObjectDesc::Sphere {
+                center: vector(json, "center")?,
+                radius: number(json, "radius")?,
+                material: MaterialDesc::from_json(field(json, "material")?)?,
+            }),
+            other => Err(invalid(&format!("unknown object type '{}'", other))),
+        }
+    }
+
+    fn to_json(&self) -> Json {
+        match self {
+            ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+            } => object_json(vec![
+                ("type", Json::String("sphere".to_string())),
+                ("center", vector_json(center)),
+                ("radius", Json::Number(*radius)),
+                ("material", material.to_json()),
+            ]),
+        }
+    }
+
+    fn build(&self) -> Arc<Sphere> {
+        match self {
+            ObjectDesc::Sphere {
+                center,
+                radius,
+                material,
+            } => Arc::new(Sphere {
+                center: *center,
+                radius: *radius,
+                material: material.build(),
+            }),
+        }
+    }
+}
+
+impl MaterialDesc {
+    fn from_json(json: &Json) -> Result<Self> {
+        match kind(json)? {
+            "lambertian" => Ok(MaterialDesc::Lambertian {
+                albedo: color(json, "albedo")?,
+            }),
+            "metal" => Ok(MaterialDesc::Metal {
+                albedo: color(json, "albedo")?,
+                fuzz: number_or(json, "fuzz", 0.)?,
+            }),
+            "dielectric" => Ok(MaterialDesc::Dielectric {
+                ir: number(json, "ir")?,
+            }),
+            other => Err(invalid(&format!("unknown material type '{}'", other))),
+        }
+    }
+
+    fn to_json(&self) -> Json {
+        match self {
+            MaterialDesc::Lambertian { albedo } => object_json(vec![
+                ("type", Json::String("lambertian".to_string())),
+                ("albedo", vector_json(albedo)),
+            ]),
+            MaterialDesc::Metal { albedo, fuzz } => object_json(vec![
+                ("type", Json::String("metal".to_string())),
+                ("albedo", vector_json(albedo)),
+                ("fuzz", Json::Number(*fuzz)),
+            ]),
+            MaterialDesc::Dielectric { ir } => object_json(vec![
+                ("type", Json::String("dielectric".to_string())),
+                ("ir", Json::Number(*ir)),
+            ]),
+        }
+    }
+
+    fn build(&self) -> Arc<dyn Material> {
+        match self {
+            MaterialDesc::Lambertian { albedo } => Arc::new(Lambertian::solid(Color(*albedo))),
+            MaterialDesc::Metal { albedo, fuzz } => Arc::new(Metal::new(Color(*albedo), *fuzz)),
+            MaterialDesc::Dielectric { ir } => Arc::new(Dielectric::new(*ir)),
+        }
+    }
+}
+
+/// Collects objects into a `HittableList`, doing the `Arc` wrapping for the
+/// caller.
+#[derive(Default)]
+pub struct SceneBuilder {
+    world: HittableList,
+}
+
+impl SceneBuilder {
+    pub fn new() -> Self {
+        SceneBuilder::default()
+    }
+
+    pub fn sphere<M: Material + 'static>(self, center: Point, radius: f64, material: M) -> Self {
+        self.add(Sphere {
+            center,
+            radius,
+            material: Arc::new(material),
+        })
+    }
+
+    #[allow(clippy::should_implement_trait)]
+    pub fn add<H: Hittable + 'static>(mut self, object: H) -> Self {
+        self.world.add(Arc::new(object));
+        self
+    }
+
+    pub fn build(self) -> HittableList {
+        self.world
+    }
+}
+
+/// The random field of small spheres around three big ones from the cover of
+/// the first book.
+pub fn make_world(rng: &mut impl Rng) -> HittableList {
+    let ground = Lambertian {
+        albedo: Box::new(CheckerTexture {
+            odd: Color(v3(0.2, 0.3, 0.1)),
+            even: Color(v3(0.9, 0.9, 0.9)),
+            scale: 10.,
+        }),
+    };
+    let mut scene = SceneBuilder::new().sphere(v3(0., -1000., 0.), 1000., ground);
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat: f64 = rng.gen();
+            let cent = v3(
+                a as f64 + 0.9 * rng.gen::<f64>(),
+                0.2,
+                b as f64 + 0.9 * rng.gen::<f64>(),
+            );
+
+            if (cent - v3(4., 0.2, 0.)).norm() > 0.9 {
+                scene = if choose_mat < 0.8 {
+                    let albedo = Color::random(rng) * Color::random(rng);
+                    scene.sphere(cent, 0.2, Lambertian::solid(albedo))
+                } else if choose_mat < 0.95 {
+                    let albedo = Color::random_in(rng, 0.5, 1.);
+                    scene.sphere(cent, 0.2, Metal::new(albedo, rand_in(rng, 0., 0.3)))
+                } else {
+                    scene.sphere(cent, 0.2, Dielectric::new(1.5))
+                };
+            }
+        }
+    }
+
+    scene
+        .sphere(v3(0., 1., 0.), 1., Dielectric::new(1.5))
+        .sphere(
+            v3(-4., 1., 0.),
+            1.,
+            Lambertian::solid(Color(v3(0.4, 0.2, 0.1))),
+        )
+        .sphere(
+            v3(4., 1., 0.),
+            1.,
+            Metal::new(Color(v3(0.7, 0.6, 0.5)), 0.0),
+        )
+        .build()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geometry::Ray;
+    use crate::hittable::Hittable;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    fn small_scene() -> SceneDesc {
+        SceneDesc {
+            camera: CameraDesc {
+                lookfrom: v3(0., 1., 5.),
+                lookat: v3(0., 0., 0.),
+                vup: v3(0., 1., 0.),
+                vfov: 40.,
+                aspect: 1.5,
+                aperture: 0.,
+                focus_dist: None,
+                time0: 0.,
+                time1: 0.,
+            },
+            objects: vec![
+                ObjectDesc::Sphere {
+                    center: v3(0., -100.5, 0.),
+                    radius: 100.,
+                    material: MaterialDesc::Lambertian {
+                        albedo: v3(0.5, 0.5, 0.5),
+                    },
+                },
+                ObjectDesc::Sphere {
+                    center: v3(-1., 0., 0.),
+                    radius: 0.5,
+                    material: MaterialDesc::Metal {
+                        albedo: v3(0.8, 0.6, 0.2),
+                        fuzz: 0.1,
+                    },
+                },
+                ObjectDesc::Sphere {
+                    center: v3(1., 0., 0.),
+                    radius: 0.5,
+                    material: MaterialDesc::Dielectric { ir: 1.5 },
+                },
+            ],
+        }
+    }
+
+    fn primary_hits(camera: &Camera, world: &dyn Hittable) -> Vec<Option<(f64, V3)>> {
+        let (width, height) = (24, 16);
+        let mut rng = StdRng::seed_from_u64(0);
+        let mut hits = Vec::new();
+        for j in 0..height {
+            for i in 0..width {
+                let ray: Ray = camera
+                    .get_ray(
+                        i as f64 / (width - 1) as f64,
+                        j as f64 / (height - 1) as f64,
+                        &mut rng,
+                    )
+                    .unwrap();
+                hits.push(
+                    world
+                        .hit(&ray, 0.001, f64::INFINITY)
+                        .map(|r| (r.t, r.normal)),
+                );
+            }
+        }
+        hits
+    }
+
+    #[test]
+    fn test_scene_json_round_trip() {
+        let desc = small_scene();
+        let text = desc.to_json().to_string();
+        let parsed = SceneDesc::from_json(&Json::parse(&text).unwrap()).unwrap();
+        assert_eq!(parsed, desc);
+
+        let (camera, world) = parsed.build();
+
+        let mut expected = HittableList::new();
+        expected.add(Arc::new(Sphere {
+            center: v3(0., -100.5, 0.),
+            radius: 100.,
+            material: Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5)))),
+        }));
+        expected.add(Arc::new(Sphere {
+            center: v3(-1., 0., 0.),
+            radius: 0.5,
+            material: Arc::new(Metal {
+                albedo: Color(v3(0.8, 0.6, 0.2)),
+                fuzz: 0.1,
+            }),
+        }));
+        expected.add(Arc::new(Sphere {
+            center: v3(1., 0., 0.),
+            radius: 0.5,
+            material: Arc::new(Dielectric::new(1.5)),
+        }));
+        let expected_camera = Camera::new(
+            v3(0., 1., 5.),
+            v3(0., 0., 0.),
+            v3(0., 1., 0.),
+            40.,
+            1.5,
+            0.,
+            v3(0., 1., 5.).norm(),
+            0.,
+            0.,
+        );
+
+        assert_eq!(
+            primary_hits(&camera, &world),
+            primary_hits(&expected_camera, &expected)
+        );
+    }
+
+    #[test]
+    fn test_scene_unknown_material() {
+        let text = r#"{"camera": {"lookfrom": [0, 0, 1], "lookat": [0, 0, 0], "vup": [0, 1, 0],
+            "vfov": 90, "aspect": 1}, "objects": [{"type": "sphere", "center": [0, 0, 0],
+            "radius": 1, "material": {"type": "plastic"}}]}"#;
+        let err = SceneDesc::from_json(&Json::parse(text).unwrap()).unwrap_err();
+        assert!(err.to_string().contains("plastic"));
+    }
+
+    #[test]
+    fn test_scene_hex_colors() {
+        let text = r##"{"type": "lambertian", "albedo": "#ffffff"}"##;
+        match MaterialDesc::from_json(&Json::parse(text).unwrap()).unwrap() {
+            MaterialDesc::Lambertian { albedo } => assert_eq!(albedo, v3(1., 1., 1.)),
+            _ => panic!("Expected a lambertian"),
+        }
+
+        let text = r##"{"type": "metal", "albedo": "#fffz", "fuzz": 0}"##;
+        assert!(MaterialDesc::from_json(&Json::parse(text).unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_make_world_big_spheres() {
+        let world = make_world(&mut StdRng::seed_from_u64(8));
+
+        // Looking straight down on each big sphere finds its top, in its own
+        // material
+        let expected = [
+            (-4., v3(0.4, 0.2, 0.1)),
+            (0., v3(1., 1., 1.)),
+            (4., v3(0.7, 0.6, 0.5)),
+        ];
+        for &(x, albedo) in &expected {
+            let ray = Ray {
+                orig: v3(x, 10., 0.),
+                dir: v3(0., -1., 0.),
+                time: 0.,
+                wavelength: None,
+            };
+            let rec = world.hit(&ray, 0.001, f64::INFINITY).unwrap();
+            assert!((rec.point - v3(x, 2., 0.)).norm() < 1e-9);
+            assert_eq!(rec.material.albedo(&rec).0, albedo);
+        }
+    }
+
+    #[test]
+    fn test_scene_builder_matches_manual() {
+        let built = SceneBuilder::new()
+            .sphere(
+                v3(0., 0., -1.),
+                0.5,
+                Lambertian::solid(Color(v3(0.1, 0.2, 0.5))),
+            )
+            .add(Sphere {
+                center: v3(0., -100.5, -1.),
+                radius: 100.,
+                material: Arc::new(Metal::new(Color(v3(0.8, 0.8, 0.8)), 0.)),
+            })
+            .build();
+
+        let mut manual = HittableList::new();
+        manual.add(Arc::new(Sphere {
+            center: v3(0., 0., -1.),
+            radius: 0.5,
+            material: Arc::new(Lambertian::solid(Color(v3(0.1, 0.2, 0.5)))),
+        }));
+        manual.add(Arc::new(Sphere {
+            center: v3(0., -100.5, -1.),
+            radius: 100.,
+            material: Arc::new(Metal::new(Color(v3(0.8, 0.8, 0.8)), 0.)),
+        }));
+
+        for k in 0..50 {
+            let ray = Ray {
+                orig: v3(0., 0., 1.),
+                dir: v3(-0.5 + 0.02 * k as f64, -0.3 + 0.01 * k as f64, -1.),
+                time: 0.,
+                wavelength: None,
+            };
+            let a = built.hit(&ray, 0.001, f64::INFINITY);
+            let b = manual.hit(&ray, 0.001, f64::INFINITY);
+            assert_eq!(a.is_some(), b.is_some());
+            if let (Some(a), Some(b)) = (a, b) {
+                assert_eq!((a.t, a.normal), (b.t, b.normal));
+                assert_eq!(a.material.albedo(&a).0, b.material.albedo(&b).0);
+            }
+        }
+    }
+}
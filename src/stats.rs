@@ -0,0 +1,98 @@
+//! Counters for judging how much work an acceleration structure saves.
+//!
+//! Counting is only compiled in with the `stats` cargo feature (and for this
+//! crate's own tests); otherwise recording is an empty inline function and
+//! every count reads as zero.
+//!
+//! Each thread counts into its own slot so the hot path never contends on a
+//! shared cache line. Render workers move their counts into a process-wide
+//! atomic total as they finish.
+
+#[cfg(any(test, feature = "stats"))]
+use std::{cell::Cell, sync::atomic::Ordering::Relaxed};
+
+#[cfg(any(test, feature = "stats"))]
+mod counters {
+    use std::cell::Cell;
+    use std::sync::atomic::AtomicU64;
+
+    thread_local! {
+        pub static LOCAL: Cell<u64> = const { Cell::new(0) };
+    }
+    pub static TOTAL: AtomicU64 = AtomicU64::new(0);
+}
+
+/// Whether counts are being recorded in this build.
+pub const ENABLED: bool = cfg!(any(test, feature = "stats"));
+
+/// Note one ray-primitive intersection test. Called by the primitives'
+/// `Hittable::hit`, not by lists, instances or accelerators.
+#[inline]
+pub fn record_primitive_test() {
+    #[cfg(any(test, feature = "stats"))]
+    counters::LOCAL.with(|n| n.set(n.get() + 1));
+}
+
+/// Tests recorded on the current thread and not yet flushed.
+pub fn thread_primitive_tests() -> u64 {
+    #[cfg(any(test, feature = "stats"))]
+    return counters::LOCAL.with(Cell::get);
+    #[cfg(not(any(test, feature = "stats")))]
+    0
+}
+
+/// Move the current thread's count into the process-wide total.
+pub fn flush() {
+    #[cfg(any(test, feature = "stats"))]
+    counters::TOTAL.fetch_add(counters::LOCAL.with(|n| n.replace(0)), Relaxed);
+}
+
+/// Tests recorded by every flushed thread plus the current one.
+pub fn primitive_tests() -> u64 {
+    #[cfg(any(test, feature = "stats"))]
+    return counters::TOTAL.load(Relaxed) + thread_primitive_tests();
+    #[cfg(not(any(test, feature = "stats")))]
+    0
+}
+
+/// Zero the process-wide total and the current thread's count, e.g. between
+/// frames.
+pub fn reset() {
+    #[cfg(any(test, feature = "stats"))]
+    {
+        counters::TOTAL.store(0, Relaxed);
+        counters::LOCAL.with(|n| n.set(0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::color::Color;
+    use crate::geometry::{v3, Ray, Sphere};
+    use crate::hittable::{Hittable, HittableList};
+    use crate::material::Lambertian;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_one_ray_one_test() {
+        let mut world = HittableList::new();
+        world.add(Arc::new(Sphere {
+            center: v3(0., 0., -1.),
+            radius: 0.5,
+            material: Arc::new(Lambertian::solid(Color(v3(0.5, 0.5, 0.5)))),
+        }));
+        let ray = Ray {
+            orig: v3(0., 0., 0.),
+            dir: v3(0., 0., -1.),
+            time: 0.,
+            wavelength: None,
+        };
+
+        // Other tests run on their own threads, so only this thread's count
+        // is checked
+        let before = thread_primitive_tests();
+        assert!(world.hit(&ray, 0.001, f64::INFINITY).is_some());
+        assert_eq!(thread_primitive_tests() - before, 1);
+    }
+}
@@ -0,0 +1,304 @@
+use std::fs::File;
+use std::io::{BufReader, Result};
+use std::path::Path;
+
+use crate::color::Color;
+use crate::geometry::{v3, Point};
+use crate::perlin::Perlin;
+use crate::png;
+
+const DEFAULT_NOISE_SEED: u64 = 0;
+const TURB_DEPTH: usize = 7;
+
+pub trait Texture: Send + Sync {
+    fn value(&self, u: f64, v: f64, p: &Point) -> Color;
+}
+
+pub struct SolidColor {
+    pub color: Color,
+}
+
+pub struct CheckerTexture {
+    pub odd: Color,
+    pub even: Color,
+    pub scale: f64,
+}
+
+/// Checkers laid out in surface UV, so they stretch and scale with the
+/// object: `tiles_u` by `tiles_v` squares over the whole UV range.
+pub struct CheckerUv {
+    pub odd: Color,
+    pub even: Color,
+    pub tiles_u: f64,
+    pub tiles_v: f64,
+}
+
+/// Blend from `start` to `end` across the surface, along u for `axis` 0 and
+/// v for `axis` 1. Coordinates outside `[0, 1]` take the nearer end color.
+pub struct GradientTexture {
+    start: Color,
+    end: Color,
+    axis: usize,
+}
+
+pub enum NoisePattern {
+    Smooth,
+    Marble,
+}
+
+pub struct NoiseTexture {
+    pub noise: Perlin,
+    pub scale: f64,
+    pub pattern: NoisePattern,
+}
+
+/// Texture sampled from an image file by surface UV.
+pub struct ImageTexture {
+    width: usize,
+    height: usize,
+    // Rows stored top-to-bottom, as they appear in the file
+    pixels: Vec<Color>,
+}
+
+impl Texture for SolidColor {
+    fn value(&self, _u: f64, _v: f64, _p: &Point) -> Color {
+        self.color
+    }
+}
+
+impl Texture for CheckerTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point) -> Color {
+        let sines = (self.scale * p.x).sin() * (self.scale * p.y).sin() * (self.scale * p.z).sin();
+        if sines < 0. {
+            self.odd
+        } else {
+            self.even
+        }
+    }
+}
+
+impl Texture for CheckerUv {
+    fn value(&self, u: f64, v: f64, _p: &Point) -> Color {
+        let cell = (u * self.tiles_u).floor() + (v * self.tiles_v).floor();
+        if cell.rem_euclid(2.) == 0. {
+            self.even
+        } else {
+            self.odd
+        }
+    }
+}
+
+impl GradientTexture {
+    /// Panics unless `axis` is 0 (u) or 1 (v).
+    pub fn new(start: Color, end: Color, axis: usize) -> Self {
+        assert!(
+            axis < 2,
+            "GradientTexture axis must be 0 (u) or 1 (v), got {}",
+            axis
+        );
+        GradientTexture { start, end, axis }
+    }
+}
+
+impl Texture for GradientTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point) -> Color {
+        let t = [u, v][self.axis].clamp(0., 1.);
+        Color::lerp(self.start, self.end, t)
+    }
+}
+
+impl NoiseTexture {
+    pub fn new(scale: f64, seed: u64) -> Self {
+        NoiseTexture {
+            noise: Perlin::new(seed),
+            scale,
+            pattern: NoisePattern::Smooth,
+        }
+    }
+
+    /// Veined marble: a sine along z whose phase is disturbed by turbulence.
+    pub fn marble(scale: f64) -> Self {
+        NoiseTexture {
+            noise: Perlin::new(DEFAULT_NOISE_SEED),
+            scale,
+            pattern: NoisePattern::Marble,
+        }
+    }
+}
+
+impl Texture for NoiseTexture {
+    fn value(&self, _u: f64, _v: f64, p: &Point) -> Color {
+        let intensity = match self.pattern {
+            NoisePattern::Smooth => 0.5 * (1. + self.noise.noise(&(self.scale * p))),
+            NoisePattern::Marble => {
+                0.5 * (1. + (self.scale * p.z + 10. * self.noise.turb(p, TURB_DEPTH)).sin())
+            }
+        };
+        Color(intensity * v3(1., 1., 1.))
+    }
+}
+
+impl ImageTexture {
+    /// Load a PNG image from disk.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let (width, height, rgb) = png::read_rgb8(&mut reader)?;
+        Ok(Self::from_rgb8(width, height, &rgb))
+    }
+
+    /// Build a texture from 8-bit RGB rows given top-to-bottom.
+    pub fn from_rgb8(width: usize, height: usize, rgb: &[u8]) -> Self {
+        // Squaring undoes the gamma 2 encoding applied when writing images
+        let linear = |c: u8| (c as f64 / 255.).powi(2);
+        let pixels = rgb
+            .chunks(3)
+            .map(|px| Color(v3(linear(px[0]), linear(px[1]), linear(px[2]))))
+            .collect();
+
+        ImageTexture {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+impl Texture for ImageTexture {
+    fn value(&self, u: f64, v: f64, _p: &Point) -> Color {
+        if self.pixels.is_empty() {
+            return Color(v3(0., 1., 1.));
+        }
+
+        let u = u.rem_euclid(1.);
+        // Image rows run top-down while v runs bottom-up
+        let v = 1. - v.clamp(0., 1.);
+
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+
+        self.pixels[j * self.width + i]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f64::consts::PI;
+
+    #[test]
+    fn test_checker_alternates() {
+        let checker = CheckerTexture {
+            odd: Color(v3(0., 0., 0.)),
+            even: Color(v3(1., 1., 1.)),
+            scale: PI,
+        };
+
+        // Stepping one cell along x flips the sign of the product
+        let a = checker.value(0., 0., &v3(0.5, 0.5, 0.5));
+        let b = checker.value(0., 0., &v3(1.5, 0.5, 0.5));
+        let c = checker.value(0., 0., &v3(2.5, 0.5, 0.5));
+
+        assert_eq!(a.0, checker.even.0);
+        assert_eq!(b.0, checker.odd.0);
+        assert_eq!(c.0, checker.even.0);
+    }
+
+    #[test]
+    fn test_checker_uv() {
+        let mut checker = CheckerUv {
+            odd: Color(v3(0., 0., 0.)),
+            even: Color(v3(1., 1., 1.)),
+            tiles_u: 4.,
+            tiles_v: 2.,
+        };
+        let p = v3(0., 0., 0.);
+        let color_at = |checker: &CheckerUv, u: f64, v: f64| checker.value(u, v, &p).0;
+
+        // Neighbours along either direction differ, diagonals match
+        assert_eq!(color_at(&checker, 0.1, 0.1), checker.even.0);
+        assert_eq!(color_at(&checker, 0.35, 0.1), checker.odd.0);
+        assert_eq!(color_at(&checker, 0.1, 0.6), checker.odd.0);
+        assert_eq!(color_at(&checker, 0.35, 0.6), checker.even.0);
+
+        // Count color changes across u at fixed v
+        let changes = |checker: &CheckerUv| {
+            (1..1000)
+                .filter(|&k| {
+                    let (a, b) = ((k - 1) as f64 / 1000., k as f64 / 1000.);
+                    color_at(checker, a, 0.1) != color_at(checker, b, 0.1)
+                })
+                .count()
+        };
+        assert_eq!(changes(&checker), 3);
+        checker.tiles_u = 8.;
+        assert_eq!(changes(&checker), 7);
+    }
+
+    #[test]
+    fn test_gradient() {
+        let gradient = GradientTexture::new(Color(v3(1., 0., 0.)), Color(v3(0., 0.5, 1.)), 1);
+        let p = v3(0., 0., 0.);
+
+        let midpoint = gradient.value(0.9, 0.5, &p);
+        assert!((midpoint.0 - v3(0.5, 0.25, 0.5)).norm() < 1e-12);
+        assert_eq!(gradient.value(0., -2., &p).0, gradient.start.0);
+        assert_eq!(gradient.value(0., 3., &p).0, gradient.end.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "got 2")]
+    fn test_gradient_rejects_bad_axis() {
+        GradientTexture::new(Color::black(), Color::black(), 2);
+    }
+
+    #[test]
+    fn test_noise_deterministic_and_bounded() {
+        let a = NoiseTexture::new(4., 42);
+        let b = NoiseTexture::new(4., 42);
+        let marble = NoiseTexture::marble(4.);
+
+        for i in 0..1000 {
+            let t = i as f64 * 0.037;
+            let p = v3(t, 1.3 * t - 5., 7. - 0.6 * t);
+
+            let va = a.value(0., 0., &p);
+            assert_eq!(va.0, b.value(0., 0., &p).0);
+            assert!(va.0.x >= 0. && va.0.x <= 1.);
+
+            let vm = marble.value(0., 0., &p);
+            assert!(vm.0.x >= 0. && vm.0.x <= 1.);
+        }
+    }
+
+    #[test]
+    fn test_image_texture_corners() {
+        // 2x2 checkerboard: white top-left and bottom-right, black elsewhere
+        let bytes: [u8; 73] = [
+            0x89, 0x50, 0x4e, 0x47, 0x0d, 0x0a, 0x1a, 0x0a, 0x00, 0x00, 0x00, 0x0d, 0x49, 0x48,
+            0x44, 0x52, 0x00, 0x00, 0x00, 0x02, 0x00, 0x00, 0x00, 0x02, 0x08, 0x02, 0x00, 0x00,
+            0x00, 0xfd, 0xd4, 0x9a, 0x73, 0x00, 0x00, 0x00, 0x10, 0x49, 0x44, 0x41, 0x54, 0x78,
+            0xda, 0x63, 0xf8, 0xff, 0xff, 0x3f, 0x03, 0x04, 0x00, 0x59, 0x00, 0x29, 0xe4, 0x05,
+            0xfb, 0xb6, 0x21, 0x58, 0xc4, 0x00, 0x00, 0x00, 0x00, 0x49, 0x45, 0x4e, 0x44, 0xae,
+            0x42, 0x60, 0x82,
+        ];
+        // The process id keeps concurrent test runs from sharing the file
+        let file = format!("tracer_test_checker_{}.png", std::process::id());
+        let path = std::env::temp_dir().join(file);
+        std::fs::write(&path, bytes).unwrap();
+        let texture = ImageTexture::load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let white = v3(1., 1., 1.);
+        let black = v3(0., 0., 0.);
+        let p = v3(0., 0., 0.);
+
+        assert_eq!(texture.value(0.1, 0.9, &p).0, white);
+        assert_eq!(texture.value(0.9, 0.9, &p).0, black);
+        assert_eq!(texture.value(0.1, 0.1, &p).0, black);
+        assert_eq!(texture.value(0.9, 0.1, &p).0, white);
+
+        // u wraps around while v clamps at the edges
+        assert_eq!(texture.value(1.1, 0.9, &p).0, white);
+        assert_eq!(texture.value(0.9, -3., &p).0, white);
+    }
+}
@@ -0,0 +1,92 @@
+use std::sync::Arc;
+
+use tracer::background::Background;
+use tracer::camera::Camera;
+use tracer::color::Color;
+use tracer::geometry::{v3, Ray, Sphere};
+use tracer::hittable::HittableList;
+use tracer::material::Lambertian;
+use tracer::{ray_color, render, RenderSettings};
+
+use rand::{rngs::StdRng, SeedableRng};
+
+fn red_ball() -> HittableList {
+    let mut world = HittableList::new();
+    world.add(Arc::new(Sphere {
+        center: v3(0., 0., -3.),
+        radius: 1.,
+        material: Arc::new(Lambertian::solid(Color(v3(0.8, 0.1, 0.1)))),
+    }));
+    world
+}
+
+fn camera() -> Camera {
+    Camera::new(
+        v3(0., 0., 0.),
+        v3(0., 0., -1.),
+        v3(0., 1., 0.),
+        60.,
+        1.,
+        0.,
+        1.,
+        0.,
+        1.,
+    )
+}
+
+#[test]
+fn renders_a_tiny_scene() {
+    let sky = Color(v3(0.4, 0.6, 0.9));
+    let settings = RenderSettings {
+        width: 9,
+        height: 9,
+        samples: 8,
+        max_depth: 4,
+        background: Background::Solid(sky),
+        ..Default::default()
+    };
+
+    let image = render(
+        &camera(),
+        &red_ball(),
+        &HittableList::new(),
+        &settings,
+        None,
+        |_, _| (),
+    );
+    assert_eq!((image.width(), image.height()), (9, 9));
+
+    // Sky in the corners, and the ball in the middle reddened by its albedo
+    assert!((image.img[(0, 0)].0 - sky.0).norm() < 1e-12);
+    let center = image.img[(4, 4)].0;
+    assert!(center.x > center.y && center.x > center.z);
+    assert!(center.x < sky.0.x);
+}
+
+#[test]
+fn ray_color_is_deterministic() {
+    let settings = RenderSettings::default();
+    let ray = Ray {
+        orig: v3(0., 0., 0.),
+        dir: v3(0.1, 0.1, -1.),
+        time: 0.,
+        wavelength: None,
+    };
+
+    let world = red_ball();
+    let a = ray_color(
+        &ray,
+        &world,
+        &HittableList::new(),
+        &settings,
+        &mut StdRng::seed_from_u64(3),
+    );
+    let b = ray_color(
+        &ray,
+        &world,
+        &HittableList::new(),
+        &settings,
+        &mut StdRng::seed_from_u64(3),
+    );
+    assert_eq!(a.0, b.0);
+}